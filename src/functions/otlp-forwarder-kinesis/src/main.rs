@@ -12,14 +12,22 @@ use reqwest_middleware::ClientBuilder;
 use reqwest_tracing::TracingMiddleware;
 use serde::{Deserialize, Serialize};
 use serverless_otlp_forwarder_core::{
-    InstrumentedHttpClient, processor::process_event_batch, span_compactor::SpanCompactionConfig,
+    InstrumentedHttpClient, http_sender::HttpOtlpForwarderClient, processor::process_event_batch,
+    span_compactor::SpanCompactionConfig,
 };
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 // The specific parser for this Lambda
 mod parser;
-use parser::KinesisOtlpStdoutParser;
+mod signals;
+use parser::{KinesisOtlpStdoutParser, ParsedTraces};
+use signals::{Signal, SignalBatches};
+
+// Log and metric records dropped because their endpoint rejected them, over this
+// environment's lifetime
+static DROPPED_SIGNAL_RECORDS: AtomicU64 = AtomicU64::new(0);
 
 // Wrapper for KinesisEvent to implement SpanAttributesExtractor
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -88,26 +96,51 @@ async fn function_handler(
         .map_or_else(|| "kinesis_stream_unknown".to_string(), |arn| arn.clone());
 
     let parser = KinesisOtlpStdoutParser;
+    let batches = parser.parse_by_signal(event.payload.0);
+    forward_batches(batches, &source_identifier, http_client.as_ref()).await
+}
+
+/// Sends the records of each signal to its OTLP endpoint.
+async fn forward_batches(
+    batches: SignalBatches,
+    source_identifier: &str,
+    http_client: &impl HttpOtlpForwarderClient,
+) -> Result<(), LambdaError> {
     let compaction_config = SpanCompactionConfig::default();
 
-    match process_event_batch(
-        event.payload.0,
-        &parser,
-        &source_identifier,
-        http_client.as_ref(), // Pass &InstrumentedOtlpClient
+    // Spans go through the core processor, which compacts them into a single request.
+    // Only their failure fails the batch, before anything else was sent, so Kinesis retries it.
+    if let Err(e) = process_event_batch(
+        batches.traces,
+        &ParsedTraces,
+        source_identifier,
+        http_client,
         &compaction_config,
     )
     .await
     {
-        Ok(_) => {
-            tracing::info!("otlp-stdout-kinesis-processor: Batch processed successfully.");
-            Ok(())
-        }
-        Err(e) => {
-            tracing::error!(error = %e, "otlp-stdout-kinesis-processor: Error processing event batch.");
-            Err(LambdaError::from(e.to_string()))
+        tracing::error!(error = %e, "otlp-stdout-kinesis-processor: Error processing event batch.");
+        return Err(LambdaError::from(e.to_string()));
+    }
+
+    // Logs and metrics are sent to their own OTLP endpoints. A failure only drops those
+    // records: failing the batch would have Kinesis redeliver, and duplicate, its traces.
+    for (signal, batch) in [
+        (Signal::Logs, batches.logs),
+        (Signal::Metrics, batches.metrics),
+    ] {
+        let record_count = batch.len() as u64;
+        if let Err(e) =
+            signals::send_signal_batch(http_client, signal, batch, &compaction_config).await
+        {
+            let total =
+                DROPPED_SIGNAL_RECORDS.fetch_add(record_count, Ordering::Relaxed) + record_count;
+            tracing::error!(error = %e, ?signal, dropped = record_count, total_dropped = total, "otlp-stdout-kinesis-processor: Error sending batch, dropping its records.");
         }
     }
+
+    tracing::info!("otlp-stdout-kinesis-processor: Batch processed successfully.");
+    Ok(())
 }
 
 #[tokio::main]
@@ -155,6 +188,68 @@ async fn main() -> Result<(), LambdaError> {
 
 #[cfg(test)]
 mod tests {
-    // Kinesis processor specific tests (if any) would go here.
-    // For now, main logic is tested in core and parser.rs has its own tests.
+    use super::*;
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use reqwest::header::HeaderMap;
+    use serverless_otlp_forwarder_core::TelemetryData;
+    use std::time::Duration;
+    use url::Url;
+
+    // An OTLP endpoint that is down
+    struct FailingClient;
+
+    #[async_trait]
+    impl HttpOtlpForwarderClient for FailingClient {
+        async fn post_telemetry(
+            &self,
+            _target_url: Url,
+            _headers: HeaderMap,
+            _payload: Bytes,
+            _timeout: Duration,
+        ) -> anyhow::Result<reqwest::Response> {
+            anyhow::bail!("endpoint down")
+        }
+    }
+
+    fn telemetry(path: &str) -> TelemetryData {
+        TelemetryData {
+            source: "test".to_string(),
+            endpoint: format!("http://localhost:4318{path}"),
+            payload: Vec::new(),
+            content_type: "application/x-protobuf".to_string(),
+            content_encoding: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_logs_and_metrics_dont_fail_the_batch() {
+        let dropped_before = DROPPED_SIGNAL_RECORDS.load(Ordering::Relaxed);
+        let mut batches = SignalBatches::default();
+        batches.push(Signal::Logs, telemetry("/v1/logs"));
+        batches.push(Signal::Logs, telemetry("/v1/logs"));
+        batches.push(Signal::Metrics, telemetry("/v1/metrics"));
+
+        // Kinesis must not redeliver the batch, and with it traces already exported
+        assert!(
+            forward_batches(batches, "test", &FailingClient)
+                .await
+                .is_ok()
+        );
+        assert_eq!(
+            DROPPED_SIGNAL_RECORDS.load(Ordering::Relaxed) - dropped_before,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_traces_fail_the_batch() {
+        let mut batches = SignalBatches::default();
+        batches.push(Signal::Traces, telemetry("/v1/traces"));
+        assert!(
+            forward_batches(batches, "test", &FailingClient)
+                .await
+                .is_err()
+        );
+    }
 }
@@ -1,21 +1,27 @@
+use crate::signals::{self, Signal, SignalBatches};
 use anyhow::Result;
 use aws_lambda_events::event::kinesis::KinesisEvent;
 use otlp_stdout_span_exporter::ExporterOutput;
+use serde::Deserialize;
 use serverless_otlp_forwarder_core::core_parser::EventParser;
 use serverless_otlp_forwarder_core::telemetry::TelemetryData; // For parsing the JSON string within Kinesis data
 
-pub struct KinesisOtlpStdoutParser;
+// An otlp-stdout line, with the signal tag the extension adds to log and metric lines
+#[derive(Deserialize)]
+struct TaggedExporterOutput {
+    #[serde(flatten)]
+    output: ExporterOutput,
+    #[serde(default)]
+    signal: Option<String>,
+}
 
-impl EventParser for KinesisOtlpStdoutParser {
-    type EventInput = KinesisEvent;
+pub struct KinesisOtlpStdoutParser;
 
-    fn parse(
-        &self,
-        event_payload: Self::EventInput,
-        _stream_name: &str,
-    ) -> Result<Vec<TelemetryData>> {
+impl KinesisOtlpStdoutParser {
+    /// Parses every otlp-stdout line of the batch, grouped by OTLP signal.
+    pub fn parse_by_signal(&self, event_payload: KinesisEvent) -> SignalBatches {
         let records = event_payload.records;
-        let mut telemetry_items = Vec::with_capacity(records.len());
+        let mut batches = SignalBatches::default();
 
         for kinesis_event_record in records {
             let data_bytes = &kinesis_event_record.kinesis.data.0; // .0 accesses the Vec<u8> from Base64Data
@@ -33,34 +39,72 @@ impl EventParser for KinesisOtlpStdoutParser {
 
             tracing::debug!("Received Kinesis record (JSON string): {}", json_string);
 
-            let exporter_output_record: ExporterOutput = match serde_json::from_str(&json_string) {
-                Ok(output) => output,
-                Err(err) => {
-                    tracing::warn!(
-                        "Failed to parse Kinesis record JSON string as ExporterOutput: {}. Error details: {}. Skipping record.",
-                        json_string,
-                        err
-                    );
-                    continue;
-                }
-            };
+            // Aggregated records carry several newline-delimited otlp-stdout JSON lines.
+            // A non-aggregated record is simply the single-line case.
+            for line in json_string.lines().filter(|line| !line.trim().is_empty()) {
+                let tagged: TaggedExporterOutput = match serde_json::from_str(line) {
+                    Ok(output) => output,
+                    Err(err) => {
+                        tracing::warn!(
+                            "Failed to parse Kinesis record JSON string as ExporterOutput: {}. Error details: {}. Skipping record.",
+                            line,
+                            err
+                        );
+                        continue;
+                    }
+                };
+                let exporter_output_record = tagged.output;
+                let signal = Signal::from_envelope(
+                    tagged.signal.as_deref(),
+                    &exporter_output_record.endpoint,
+                );
 
-            tracing::debug!(
-                "Successfully parsed Kinesis record as ExporterOutput with version: {}",
-                exporter_output_record.version
-            );
+                tracing::debug!(
+                    "Successfully parsed Kinesis record as ExporterOutput with version: {}",
+                    exporter_output_record.version
+                );
 
-            match TelemetryData::from_log_record(exporter_output_record) {
-                Ok(telemetry_data) => telemetry_items.push(telemetry_data),
-                Err(e) => {
-                    tracing::warn!(
-                        "Failed to convert ExporterOutput to TelemetryData: {}. Skipping record.",
-                        e
-                    );
+                match signals::telemetry_from_output(signal, exporter_output_record) {
+                    Ok(telemetry_data) => batches.push(signal, telemetry_data),
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to convert ExporterOutput to TelemetryData: {}. Skipping record.",
+                            e
+                        );
+                    }
                 }
             }
         }
-        Ok(telemetry_items)
+        batches
+    }
+}
+
+impl EventParser for KinesisOtlpStdoutParser {
+    type EventInput = KinesisEvent;
+
+    /// Parses the trace records of the batch. Log and metric records are left out,
+    /// since the core processor only knows how to compact and send spans.
+    fn parse(
+        &self,
+        event_payload: Self::EventInput,
+        _stream_name: &str,
+    ) -> Result<Vec<TelemetryData>> {
+        Ok(self.parse_by_signal(event_payload).traces)
+    }
+}
+
+/// Hands over trace records that were already parsed by `parse_by_signal`.
+pub struct ParsedTraces;
+
+impl EventParser for ParsedTraces {
+    type EventInput = Vec<TelemetryData>;
+
+    fn parse(
+        &self,
+        event_payload: Self::EventInput,
+        _source_identifier: &str,
+    ) -> Result<Vec<TelemetryData>> {
+        Ok(event_payload)
     }
 }
 
@@ -196,6 +240,52 @@ mod tests {
         assert_eq!(result[0].source, "service-fine");
     }
 
+    #[test]
+    fn test_kinesis_otlp_stdout_parser_aggregated_record() {
+        let parser = KinesisOtlpStdoutParser;
+        let aggregated = [
+            create_test_exporter_output_json_string("service-a"),
+            create_test_exporter_output_json_string("service-b"),
+            create_test_exporter_output_json_string("service-c"),
+        ]
+        .join("\n");
+
+        let event = KinesisEvent {
+            records: vec![
+                create_kinesis_event_record(aggregated),
+                create_kinesis_event_record(create_test_exporter_output_json_string("service-d")),
+            ],
+        };
+
+        let result = parser.parse(event, "test-stream").unwrap();
+        let sources: Vec<&str> = result.iter().map(|t| t.source.as_str()).collect();
+        assert_eq!(
+            sources,
+            vec!["service-a", "service-b", "service-c", "service-d"]
+        );
+    }
+
+    #[test]
+    fn test_kinesis_otlp_stdout_parser_aggregated_record_with_malformed_line() {
+        let parser = KinesisOtlpStdoutParser;
+        let aggregated = [
+            create_test_exporter_output_json_string("service-a"),
+            "{\"invalid_json".to_string(),
+            create_test_exporter_output_json_string("service-b"),
+            String::new(),
+        ]
+        .join("\n");
+
+        let event = KinesisEvent {
+            records: vec![create_kinesis_event_record(aggregated)],
+        };
+
+        let result = parser.parse(event, "test-stream").unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].source, "service-a");
+        assert_eq!(result[1].source, "service-b");
+    }
+
     #[test]
     fn test_kinesis_otlp_stdout_parser_empty_records() {
         let parser = KinesisOtlpStdoutParser;
@@ -203,4 +293,112 @@ mod tests {
         let result = parser.parse(event, "test-stream").unwrap();
         assert!(result.is_empty());
     }
+
+    fn create_signal_json_string(source: &str, endpoint: &str, signal: Option<&str>) -> String {
+        let mut output: serde_json::Value =
+            serde_json::from_str(&create_test_exporter_output_json_string(source)).unwrap();
+        output["endpoint"] = json!(endpoint);
+        if let Some(signal) = signal {
+            output["signal"] = json!(signal);
+        }
+        serde_json::to_string(&output).unwrap()
+    }
+
+    #[test]
+    fn test_kinesis_otlp_stdout_parser_groups_by_signal() {
+        let parser = KinesisOtlpStdoutParser;
+        let aggregated = [
+            create_test_exporter_output_json_string("service-traces"),
+            create_signal_json_string("service-logs", "http://collector/v1/logs", Some("logs")),
+            create_signal_json_string("service-metrics", "http://collector/v1/metrics", None),
+            create_signal_json_string("service-tagged", "http://collector", Some("logs")),
+        ]
+        .join("\n");
+
+        let event = KinesisEvent {
+            records: vec![create_kinesis_event_record(aggregated)],
+        };
+
+        let batches = parser.parse_by_signal(event.clone());
+        let sources =
+            |items: &[TelemetryData]| items.iter().map(|t| t.source.clone()).collect::<Vec<_>>();
+        assert_eq!(sources(&batches.traces), vec!["service-traces"]);
+        assert_eq!(
+            sources(&batches.logs),
+            vec!["service-logs", "service-tagged"]
+        );
+        assert_eq!(sources(&batches.metrics), vec!["service-metrics"]);
+
+        // The core processor only gets the traces
+        let traces = parser.parse(event, "test-stream").unwrap();
+        assert_eq!(sources(&traces), vec!["service-traces"]);
+    }
+
+    #[test]
+    fn test_kinesis_otlp_stdout_parser_json_logs_and_metrics() {
+        use base64::{Engine, engine::general_purpose};
+        use flate2::{Compression, write::GzEncoder};
+        use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+        use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+        use prost::Message;
+        use std::io::Write;
+
+        let logs = json!({"resourceLogs": [{"scopeLogs": [{"logRecords": [{
+            "timeUnixNano": "1704067200000000000",
+            "severityText": "INFO",
+            "body": {"stringValue": "hello"}
+        }]}]}]});
+        let logs_line = json!({
+            "__otel_otlp_stdout": "otlp-stdout-span-exporter@0.16.0",
+            "source": "service-logs",
+            "endpoint": "http://collector/v1/logs",
+            "method": "POST",
+            "content-type": "application/json",
+            "content-encoding": "",
+            "payload": logs.to_string(),
+            "base64": false,
+            "signal": "logs"
+        });
+
+        let metrics = json!({"resourceMetrics": [{"scopeMetrics": [{"metrics": [{
+            "name": "faas.invocations",
+            "gauge": {"dataPoints": [{"timeUnixNano": "1704067200000000000", "asDouble": 3.0}]}
+        }]}]}]});
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(metrics.to_string().as_bytes()).unwrap();
+        let metrics_line = json!({
+            "__otel_otlp_stdout": "otlp-stdout-span-exporter@0.16.0",
+            "source": "service-metrics",
+            "endpoint": "http://collector/v1/metrics",
+            "method": "POST",
+            "content-type": "application/json",
+            "content-encoding": "gzip",
+            "payload": general_purpose::STANDARD.encode(encoder.finish().unwrap()),
+            "base64": true,
+            "signal": "metrics"
+        });
+
+        let event = KinesisEvent {
+            records: vec![create_kinesis_event_record(
+                [logs_line.to_string(), metrics_line.to_string()].join("\n"),
+            )],
+        };
+        let batches = KinesisOtlpStdoutParser.parse_by_signal(event);
+
+        assert_eq!(batches.logs.len(), 1);
+        assert_eq!(batches.logs[0].content_type, "application/x-protobuf");
+        let logs = ExportLogsServiceRequest::decode(batches.logs[0].payload.as_slice()).unwrap();
+        let record = &logs.resource_logs[0].scope_logs[0].log_records[0];
+        assert_eq!(record.severity_text, "INFO");
+        assert_eq!(record.time_unix_nano, 1_704_067_200_000_000_000);
+
+        assert_eq!(batches.metrics.len(), 1);
+        let metrics =
+            ExportMetricsServiceRequest::decode(batches.metrics[0].payload.as_slice()).unwrap();
+        assert_eq!(
+            metrics.resource_metrics[0].scope_metrics[0].metrics[0].name,
+            "faas.invocations"
+        );
+        assert!(batches.traces.is_empty());
+    }
 }
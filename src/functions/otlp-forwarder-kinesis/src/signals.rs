@@ -0,0 +1,308 @@
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose};
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use otlp_stdout_span_exporter::ExporterOutput;
+use prost::Message;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use serverless_otlp_forwarder_core::TelemetryData;
+use serverless_otlp_forwarder_core::http_sender::HttpOtlpForwarderClient;
+use serverless_otlp_forwarder_core::span_compactor::{CompressionPreference, SpanCompactionConfig};
+use std::env;
+use std::io::Read;
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_OTLP_BASE_ENDPOINT: &str = "http://localhost:4318";
+const DEFAULT_OTLP_EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The OTLP signal of a record, as tagged by the extension in the envelope's `signal` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Traces,
+    Logs,
+    Metrics,
+}
+
+impl Signal {
+    /// Works out the signal from the envelope tag, falling back to the path of the
+    /// original endpoint. Untagged records from older extensions are traces.
+    pub fn from_envelope(tag: Option<&str>, endpoint: &str) -> Self {
+        match tag.map(str::to_lowercase).as_deref() {
+            Some("logs") => return Self::Logs,
+            Some("metrics") => return Self::Metrics,
+            Some("traces") => return Self::Traces,
+            _ => {}
+        }
+        let path = endpoint.trim_end_matches('/');
+        if path.ends_with(Self::Logs.path()) {
+            Self::Logs
+        } else if path.ends_with(Self::Metrics.path()) {
+            Self::Metrics
+        } else {
+            Self::Traces
+        }
+    }
+
+    pub fn path(&self) -> &'static str {
+        match self {
+            Self::Traces => "/v1/traces",
+            Self::Logs => "/v1/logs",
+            Self::Metrics => "/v1/metrics",
+        }
+    }
+
+    // Suffix of the signal-specific OTEL_EXPORTER_OTLP_* environment variables
+    fn env_name(&self) -> &'static str {
+        match self {
+            Self::Traces => "TRACES",
+            Self::Logs => "LOGS",
+            Self::Metrics => "METRICS",
+        }
+    }
+
+    fn env_var(&self, name: &str) -> Option<String> {
+        env::var(format!("OTEL_EXPORTER_OTLP_{}_{name}", self.env_name()))
+            .or_else(|_| env::var(format!("OTEL_EXPORTER_OTLP_{name}")))
+            .ok()
+            .filter(|value| !value.is_empty())
+    }
+}
+
+/// Records of each signal found in a batch.
+#[derive(Debug, Default)]
+pub struct SignalBatches {
+    pub traces: Vec<TelemetryData>,
+    pub logs: Vec<TelemetryData>,
+    pub metrics: Vec<TelemetryData>,
+}
+
+impl SignalBatches {
+    pub fn push(&mut self, signal: Signal, telemetry: TelemetryData) {
+        match signal {
+            Signal::Traces => self.traces.push(telemetry),
+            Signal::Logs => self.logs.push(telemetry),
+            Signal::Metrics => self.metrics.push(telemetry),
+        }
+    }
+}
+
+/// Converts an otlp-stdout line into an uncompressed protobuf record of its signal.
+///
+/// The core's `TelemetryData::from_log_record` reads every OTLP/JSON payload as a trace
+/// export request, so JSON log and metric payloads are decoded here with their own
+/// request type and re-encoded to protobuf.
+pub fn telemetry_from_output(signal: Signal, output: ExporterOutput) -> Result<TelemetryData> {
+    if signal == Signal::Traces || output.content_type != "application/json" {
+        return TelemetryData::from_log_record(output);
+    }
+
+    let mut bytes = if output.base64 {
+        general_purpose::STANDARD
+            .decode(&output.payload)
+            .context("Failed to decode base64 payload")?
+    } else {
+        output.payload.into_bytes()
+    };
+    if output.content_encoding == "gzip" {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut decompressed)
+            .context("Failed to decompress payload")?;
+        bytes = decompressed;
+    }
+    let payload = match signal {
+        Signal::Logs => serde_json::from_slice::<ExportLogsServiceRequest>(&bytes)
+            .context("Failed to parse JSON as ExportLogsServiceRequest")?
+            .encode_to_vec(),
+        Signal::Metrics => serde_json::from_slice::<ExportMetricsServiceRequest>(&bytes)
+            .context("Failed to parse JSON as ExportMetricsServiceRequest")?
+            .encode_to_vec(),
+        Signal::Traces => unreachable!("trace records are converted by the core"),
+    };
+
+    Ok(TelemetryData {
+        source: output.source,
+        endpoint: output.endpoint,
+        payload,
+        content_type: "application/x-protobuf".to_string(),
+        content_encoding: None,
+    })
+}
+
+/// Resolves the OTLP endpoint of a signal, following the OpenTelemetry conventions:
+/// `OTEL_EXPORTER_OTLP_<SIGNAL>_ENDPOINT` as is, then `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// with the signal path appended, then the local collector.
+pub fn resolve_endpoint(signal: Signal) -> Result<Url> {
+    let specific = format!("OTEL_EXPORTER_OTLP_{}_ENDPOINT", signal.env_name());
+    if let Some(endpoint) = env::var(&specific).ok().filter(|value| !value.is_empty()) {
+        return Url::parse(&endpoint)
+            .with_context(|| format!("Invalid URL from {specific}: {endpoint}"));
+    }
+    let base = env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| DEFAULT_OTLP_BASE_ENDPOINT.to_string());
+    let url = format!("{}{}", base.trim_end_matches('/'), signal.path());
+    Url::parse(&url).with_context(|| format!("Invalid OTLP endpoint: {url}"))
+}
+
+/// Parses `key1=value1,key2=value2` headers, skipping malformed entries.
+fn parse_headers(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for pair in value.split(',') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        match (
+            HeaderName::from_bytes(key.trim().as_bytes()),
+            HeaderValue::from_str(value.trim()),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.append(name, value);
+            }
+            _ => tracing::warn!("Skipping invalid OTLP header: '{}'", key.trim()),
+        }
+    }
+    headers
+}
+
+/// Merges the records of a log or metric batch into a single export request.
+///
+/// The payloads are uncompressed protobuf export requests made only of repeated fields,
+/// so concatenating them yields one request holding all of their resources.
+pub fn merge_payloads(
+    batch: Vec<TelemetryData>,
+    config: &SpanCompactionConfig,
+) -> Result<Option<TelemetryData>> {
+    let mut items = batch.into_iter();
+    let Some(mut merged) = items.next() else {
+        return Ok(None);
+    };
+    for item in items {
+        merged.payload.extend(item.payload);
+    }
+    if let CompressionPreference::Gzip = config.compression {
+        merged.compress(config.gzip_compression_level)?;
+    }
+    Ok(Some(merged))
+}
+
+/// Sends a batch of log or metric records to the signal's OTLP endpoint.
+pub async fn send_signal_batch(
+    client: &impl HttpOtlpForwarderClient,
+    signal: Signal,
+    batch: Vec<TelemetryData>,
+    config: &SpanCompactionConfig,
+) -> Result<()> {
+    let record_count = batch.len();
+    let Some(telemetry) = merge_payloads(batch, config)? else {
+        return Ok(());
+    };
+
+    let url = resolve_endpoint(signal)?;
+    let mut headers = signal
+        .env_var("HEADERS")
+        .map(|value| parse_headers(&value))
+        .unwrap_or_default();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&telemetry.content_type).context("Invalid content type")?,
+    );
+    if let Some(encoding) = &telemetry.content_encoding {
+        headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_str(encoding).context("Invalid content encoding")?,
+        );
+    }
+    let timeout = signal
+        .env_var("TIMEOUT")
+        .and_then(|value| value.parse().ok())
+        .map_or(DEFAULT_OTLP_EXPORT_TIMEOUT, Duration::from_millis);
+
+    tracing::debug!(
+        "Sending {} {:?} records ({} bytes) to {}",
+        record_count,
+        signal,
+        telemetry.payload.len(),
+        url
+    );
+    let response = client
+        .post_telemetry(
+            url.clone(),
+            headers,
+            Bytes::from(telemetry.payload),
+            timeout,
+        )
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!(
+            "OTLP export to {} failed. Status: {}. Body: {}",
+            url,
+            status,
+            body
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn telemetry(payload: &[u8]) -> TelemetryData {
+        TelemetryData {
+            source: "test".to_string(),
+            endpoint: "http://localhost:4318/v1/logs".to_string(),
+            payload: payload.to_vec(),
+            content_type: "application/x-protobuf".to_string(),
+            content_encoding: None,
+        }
+    }
+
+    #[test]
+    fn test_signal_from_envelope() {
+        assert_eq!(
+            Signal::from_envelope(Some("logs"), "http://localhost:4318/v1/traces"),
+            Signal::Logs
+        );
+        assert_eq!(
+            Signal::from_envelope(None, "http://localhost:4318/v1/metrics"),
+            Signal::Metrics
+        );
+        assert_eq!(
+            Signal::from_envelope(None, "http://localhost:4318/v1/traces"),
+            Signal::Traces
+        );
+        assert_eq!(Signal::from_envelope(Some("bogus"), ""), Signal::Traces);
+    }
+
+    #[test]
+    fn test_merge_payloads_concatenates_requests() {
+        let config = SpanCompactionConfig {
+            compression: CompressionPreference::None,
+            gzip_compression_level: 6,
+        };
+        let merged = merge_payloads(
+            vec![telemetry(b"\x0a\x00"), telemetry(b"\x0a\x02\x0a\x00")],
+            &config,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(merged.payload, b"\x0a\x00\x0a\x02\x0a\x00");
+        assert_eq!(merged.content_encoding, None);
+
+        assert!(merge_payloads(Vec::new(), &config).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = parse_headers("x-api-key=secret, x-team = obs,malformed");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["x-team"], "obs");
+    }
+}
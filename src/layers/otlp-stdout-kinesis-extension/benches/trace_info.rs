@@ -0,0 +1,137 @@
+//! Compares the trace info fast path with a full decode of the payload.
+//!
+//! Run with `cargo bench -p otlp-stdout-kinesis-extension-layer --bench trace_info`.
+
+// The extension is a binary, so the parsing modules are compiled in here directly
+#![allow(dead_code)]
+
+#[path = "../src/otlp_parsing.rs"]
+mod otlp_parsing;
+#[path = "../src/proto_scan.rs"]
+mod proto_scan;
+
+use base64::{Engine, engine::general_purpose};
+use divan::Bencher;
+use flate2::{Compression, write::GzEncoder};
+use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue, any_value};
+use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span, span::Event};
+use prost::Message;
+use proto_scan::SpanIds;
+use std::io::Write;
+
+// Span flags of a span with a remote parent
+const REMOTE_PARENT_FLAGS: u32 = 0x100 | 0x200;
+
+fn main() {
+    divan::main();
+}
+
+/// The full-decode implementation the scanner replaced.
+fn extract_trace_info_full_decode(line: &str) -> anyhow::Result<Option<(TraceId, SpanId)>> {
+    let Some(payload) = otlp_parsing::decode_otlp_payload(line)? else {
+        return Ok(None);
+    };
+    let Some(request) = payload.into_trace_request()? else {
+        return Ok(None);
+    };
+    Ok(request
+        .resource_spans
+        .iter()
+        .flat_map(|rs| &rs.scope_spans)
+        .flat_map(|ss| &ss.spans)
+        .find_map(|span| {
+            otlp_parsing::entry_span_ids(&SpanIds {
+                trace_id: &span.trace_id,
+                span_id: &span.span_id,
+                parent_span_id: &span.parent_span_id,
+                flags: span.flags,
+            })
+        }))
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// A realistic payload: `span_count` child spans with attributes and events, followed
+/// by the entry span, so the whole payload has to be scanned.
+fn large_trace_line(span_count: usize) -> String {
+    let trace_id = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10")
+        .unwrap()
+        .to_bytes()
+        .to_vec();
+    let entry_id = SpanId::from_hex("1112131415161718").unwrap().to_bytes();
+    let mut spans: Vec<Span> = (0..span_count)
+        .map(|i| Span {
+            trace_id: trace_id.clone(),
+            span_id: (i as u64 + 1).to_be_bytes().to_vec(),
+            parent_span_id: entry_id.to_vec(),
+            name: "child".to_string(),
+            flags: 0x100,
+            attributes: (0..10)
+                .map(|a| string_attribute(&format!("attribute.{a}"), &"v".repeat(40)))
+                .collect(),
+            events: vec![Event {
+                name: "event".to_string(),
+                attributes: vec![string_attribute("exception.message", "boom")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .collect();
+    spans.push(Span {
+        trace_id,
+        span_id: entry_id.to_vec(),
+        parent_span_id: SpanId::from_hex("2122232425262728")
+            .unwrap()
+            .to_bytes()
+            .to_vec(),
+        name: "entry".to_string(),
+        flags: REMOTE_PARENT_FLAGS,
+        ..Default::default()
+    });
+    let request = ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans {
+            scope_spans: vec![ScopeSpans {
+                spans,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    };
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&request.encode_to_vec()).unwrap();
+    serde_json::json!({
+        "__otel_otlp_stdout": "otlp-stdout-span-exporter@0.16.0",
+        "source": "bench-service",
+        "endpoint": "http://localhost:4318/v1/traces",
+        "method": "POST",
+        "content-type": "application/x-protobuf",
+        "content-encoding": "gzip",
+        "payload": general_purpose::STANDARD.encode(encoder.finish().unwrap()),
+        "base64": true
+    })
+    .to_string()
+}
+
+#[divan::bench(args = [100, 1_000, 5_000])]
+fn full_decode(bencher: Bencher, span_count: usize) {
+    let line = large_trace_line(span_count);
+    bencher.bench(|| extract_trace_info_full_decode(divan::black_box(&line)).unwrap());
+}
+
+#[divan::bench(args = [100, 1_000, 5_000])]
+fn fast_path(bencher: Bencher, span_count: usize) {
+    let line = large_trace_line(span_count);
+    bencher.bench(|| {
+        otlp_parsing::extract_trace_info_from_json_line(divan::black_box(&line)).unwrap()
+    });
+}
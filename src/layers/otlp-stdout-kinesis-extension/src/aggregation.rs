@@ -1,17 +1,23 @@
 use crate::events::{ParsedPlatformEvent, PlatformEventData, TelemetrySpan};
+use crate::init_phase::{self, InitPhaseInfo, InitSpanMode, StartupKind};
+use crate::trace_context::{ParentContext, TraceContextPrecedence, TraceContextSource};
 use chrono::{DateTime, Utc};
 use lambda_extension::Status as LambdaStatus;
 use opentelemetry::{
-    InstrumentationScope, KeyValue,
-    trace::{SpanContext, SpanId, SpanKind, Status as OtelStatus, TraceFlags, TraceId, TraceState},
+    InstrumentationScope, KeyValue, Value as OtelValue,
+    trace::{
+        Link, SpanContext, SpanId, SpanKind, Status as OtelStatus, TraceFlags, TraceId, TraceState,
+    },
 };
 use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_semantic_conventions::attribute as semconv;
 use rand::Rng;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
 use std::time::{Duration as StdDuration, SystemTime};
 
 // Define constants for synthesized span names
-const INIT_PHASE_NAME: &str = "Lambda/Init";
 const LAMBDA_INVOKE_NAME: &str = "Lambda/Invoke";
 // Define constants for platform span names we want to map
 const RESPONSE_LATENCY_NAME: &str = "Response/Latency";
@@ -19,6 +25,84 @@ const RESPONSE_DURATION_NAME: &str = "Response/Duration";
 const EXTENSION_OVERHEAD_NAME: &str = "Overhead/Extension";
 const RUNTIME_OVERHEAD_NAME: &str = "Overhead/Runtime";
 
+// Report metric holding the invocation duration, used to recover a missing start time
+const REPORT_DURATION_METRIC: &str = "report.durationMs";
+
+// Report metrics with a semantic convention attribute, or telling of a cold start
+const REPORT_MEMORY_SIZE_METRIC: &str = "report.memorySizeMB";
+const REPORT_INIT_DURATION_METRIC: &str = "report.initDurationMs";
+const REPORT_RESTORE_DURATION_METRIC: &str = "report.restoreDurationMs";
+
+// Number of emitted request IDs remembered to recognize late events
+pub const TOMBSTONE_CAPACITY: usize = 4096;
+
+/// Attribute names of the `Lambda/Invoke`, `Lambda/Init` and `Lambda/Restore` spans and of
+/// function log records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttributeNaming {
+    /// OpenTelemetry FaaS and AWS Lambda semantic conventions (`faas.invocation_id`,
+    /// `faas.version`, `faas.coldstart`, `faas.max_memory`, `aws.lambda.invoked_arn`,
+    /// `error.type`), with the other platform metrics as `lambda.runtime.*` and
+    /// `lambda.report.*` and snake_case init keys such as `lambda.init.runtime_version`.
+    #[default]
+    SemConv,
+    /// The names of earlier versions (`faas.execution`, `faas.instance` for the function
+    /// version, `lambda.runtime.runtime.*`, `lambda.report.report.*`, camelCase init keys
+    /// such as `lambda.init.errorType`), for existing dashboards.
+    Legacy,
+}
+
+impl AttributeNaming {
+    /// Key of a platform metric such as `report.durationMs`, reported by the event `event`.
+    fn metric_key(&self, event: &str, metric: &str) -> String {
+        match self {
+            Self::SemConv => format!("lambda.{metric}"),
+            Self::Legacy => format!("lambda.{event}.{metric}"),
+        }
+    }
+}
+
+impl FromStr for AttributeNaming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "semconv" => Ok(Self::SemConv),
+            "legacy" => Ok(Self::Legacy),
+            _ => Err(format!("Invalid attribute naming: {s}")),
+        }
+    }
+}
+
+/// Settings deciding how the spans of an invocation are assembled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AggregationOptions {
+    pub init_span_mode: InitSpanMode,
+    pub trace_context_precedence: TraceContextPrecedence,
+    /// Whether invocations without any trace context get a trace of their own, rooted at
+    /// their `Lambda/Invoke` span.
+    pub generate_traces: bool,
+    pub attribute_naming: AttributeNaming,
+}
+
+/// Lifecycle of an invocation's aggregation. Phases only move forward, whatever order
+/// the platform events arrive in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AggregationPhase {
+    /// Waiting for the invocation to finish (`platform.start` may or may not have arrived).
+    Started,
+    /// `platform.runtimeDone` arrived; the runtime finished handling the invocation.
+    RuntimeDone,
+    /// `platform.report` arrived. The aggregation is complete once `platform.runtimeDone`
+    /// arrived too; a report that overtook it is held until the next drain.
+    Reported,
+    /// The spans were taken for export; any further event for the request is late.
+    Emitted,
+}
+
+/// The trace ID, span ID and trace flags an invocation's log records are stamped with.
+pub type LogContext = (TraceId, SpanId, TraceFlags);
+
 #[derive(Debug)]
 pub struct SpanAggregator {
     pub request_id: String,
@@ -27,6 +111,9 @@ pub struct SpanAggregator {
     pub span_id: Option<SpanId>,
     pub function_root_span_id: Option<SpanId>,
     pub trace_flags: TraceFlags,
+    pub trace_source: Option<TraceContextSource>,
+    pub invoked_arn: Option<String>,
+    pub attribute_naming: AttributeNaming,
 
     pub start_time: Option<SystemTime>,
     pub end_time: Option<SystemTime>,
@@ -36,7 +123,12 @@ pub struct SpanAggregator {
     pub kind: SpanKind,
     pub attributes: Vec<KeyValue>,
     pub child_spans_data: Vec<SpanData>,
+    pub init_phases: Vec<InitPhaseInfo>,
+    pub linked_init_phases: Vec<InitPhaseInfo>,
 
+    pub phase: AggregationPhase,
+    // Set by the first drain that finds the report without platform.runtimeDone
+    pub held: bool,
     pub received_event_types: Vec<String>,
     pub first_seen_timestamp: DateTime<Utc>,
     pub last_updated_timestamp: DateTime<Utc>,
@@ -50,6 +142,9 @@ impl SpanAggregator {
             span_id: None,
             function_root_span_id: None,
             trace_flags: TraceFlags::NOT_SAMPLED,
+            trace_source: None,
+            invoked_arn: None,
+            attribute_naming: AttributeNaming::default(),
             start_time: None,
             end_time: None,
             status: OtelStatus::Unset,
@@ -57,48 +152,112 @@ impl SpanAggregator {
             kind: SpanKind::Server,
             attributes: Vec::new(),
             child_spans_data: Vec::new(),
+            init_phases: Vec::new(),
+            linked_init_phases: Vec::new(),
+            phase: AggregationPhase::Started,
+            held: false,
             received_event_types: Vec::new(),
             first_seen_timestamp: timestamp,
             last_updated_timestamp: timestamp,
         }
     }
 
-    /// Sets the trace context information for this aggregator from the execution_trace_map.
-    /// This method will only set the fields if they have not been set yet (are None).
-    pub fn set_trace_context(&mut self, trace_id: TraceId, root_span_id: SpanId) {
-        // Only set trace_id if it's not already set
-        if self.trace_id.is_none() {
-            tracing::debug!(%trace_id, %root_span_id, "Setting trace context for request_id: {}", self.request_id);
-
-            self.trace_id = Some(trace_id);
-            self.function_root_span_id = Some(root_span_id);
-
-            // Generate and store the span_id for *this* aggregator's span ("Lambda Invoke")
-            if self.span_id.is_none() {
-                let mut rng = rand::rng();
-                self.span_id = Some(SpanId::from_bytes(rng.random::<[u8; 8]>()));
-                tracing::debug!(generated_span_id = ?self.span_id, "Generated span_id for Lambda Invoke span");
+    /// Sets the context the `Lambda/Invoke` span is parented to. A context already set is
+    /// only replaced by one from the source `precedence` prefers, or when it was generated,
+    /// in which case the child spans built so far move to the new trace.
+    pub fn set_parent_context(
+        &mut self,
+        parent: ParentContext,
+        precedence: TraceContextPrecedence,
+    ) {
+        if self.trace_id.is_some() {
+            let replaces = self.trace_source.is_some_and(|current| {
+                current == TraceContextSource::Generated
+                    || (current != parent.source && precedence.preferred() == parent.source)
+            });
+            if !replaces {
+                return;
             }
+        }
+        tracing::debug!(trace_id = %parent.trace_id, root_span_id = %parent.span_id, source = ?parent.source, "Setting trace context for request_id: {}", self.request_id);
+
+        self.trace_id = Some(parent.trace_id);
+        self.function_root_span_id = Some(parent.span_id);
+        self.trace_flags = parent.trace_flags;
+        self.trace_source = Some(parent.source);
+
+        // Generate and store the span_id for *this* aggregator's span ("Lambda Invoke")
+        if self.span_id.is_none() {
+            let mut rng = rand::rng();
+            self.span_id = Some(SpanId::from_bytes(rng.random::<[u8; 8]>()));
+            tracing::debug!(generated_span_id = ?self.span_id, "Generated span_id for Lambda Invoke span");
+        }
 
-            // Mark as sampled since we have trace information
-            self.trace_flags = TraceFlags::SAMPLED;
+        for child in &mut self.child_spans_data {
+            child.span_context = SpanContext::new(
+                parent.trace_id,
+                child.span_context.span_id(),
+                parent.trace_flags,
+                false,
+                TraceState::default(),
+            );
         }
     }
 
+    /// The trace and span the invocation's log records are stamped with, once its context is
+    /// resolved: the function's entry span when it came from the pipe, else the
+    /// `Lambda/Invoke` span, so the logs land in the trace that span is exported to. The
+    /// trace flags are those of the resolved parent.
+    pub fn log_context(&self) -> Option<LogContext> {
+        let trace_id = self.trace_id?;
+        let span_id = match self.trace_source? {
+            TraceContextSource::Pipe => self.function_root_span_id?,
+            TraceContextSource::XRay | TraceContextSource::Generated => self.span_id?,
+        };
+        Some((trace_id, span_id, self.trace_flags))
+    }
+
     /// Updates the aggregator state based on a received platform event.
+    ///
+    /// Events are accepted in any order: `platform.start` only fills in the start time,
+    /// and a report without one recovers it from the reported duration. Events arriving
+    /// after the spans were emitted, and repeated events, are ignored.
     pub fn update_from_event(&mut self, event: &ParsedPlatformEvent) {
-        self.last_updated_timestamp = event.timestamp;
         let event_type_str = match &event.data {
             PlatformEventData::InitStart { .. } => "platform.initStart",
+            PlatformEventData::InitRuntimeDone { .. } => "platform.initRuntimeDone",
+            PlatformEventData::InitReport { .. } => "platform.initReport",
+            PlatformEventData::RestoreStart { .. } => "platform.restoreStart",
+            PlatformEventData::RestoreRuntimeDone { .. } => "platform.restoreRuntimeDone",
+            PlatformEventData::RestoreReport { .. } => "platform.restoreReport",
             PlatformEventData::Start { .. } => "platform.start",
             PlatformEventData::RuntimeDone { .. } => "platform.runtimeDone",
             PlatformEventData::Report { .. } => "platform.report",
         };
+        if self.phase == AggregationPhase::Emitted {
+            tracing::debug!(request_id = %self.request_id, event = event_type_str, "Ignoring event for an already emitted aggregation.");
+            return;
+        }
+        if self
+            .received_event_types
+            .iter()
+            .any(|t| t == event_type_str)
+        {
+            tracing::debug!(request_id = %self.request_id, event = event_type_str, "Ignoring repeated event.");
+            return;
+        }
+        self.last_updated_timestamp = event.timestamp;
         self.received_event_types.push(event_type_str.to_string());
 
         match &event.data {
-            PlatformEventData::InitStart { .. } => {
-                tracing::trace!(request_id=%self.request_id, "Processing InitStart event in aggregator (no-op for now).");
+            PlatformEventData::InitStart { .. }
+            | PlatformEventData::InitRuntimeDone { .. }
+            | PlatformEventData::InitReport { .. }
+            | PlatformEventData::RestoreStart { .. }
+            | PlatformEventData::RestoreRuntimeDone { .. }
+            | PlatformEventData::RestoreReport { .. } => {
+                // Init and restore events are collected separately and attached with attach_init_phases
+                tracing::trace!(request_id=%self.request_id, event = event_type_str, "Ignoring init event in aggregator.");
             }
             PlatformEventData::Start {
                 version,
@@ -108,8 +267,11 @@ impl SpanAggregator {
                     self.start_time = Some(event.timestamp.into());
                 }
                 if let Some(v) = version {
-                    self.attributes
-                        .push(KeyValue::new("faas.instance", v.clone()));
+                    let key = match self.attribute_naming {
+                        AttributeNaming::SemConv => semconv::FAAS_VERSION,
+                        AttributeNaming::Legacy => "faas.instance",
+                    };
+                    self.attributes.push(KeyValue::new(key, v.clone()));
                 }
             }
             PlatformEventData::RuntimeDone {
@@ -123,10 +285,13 @@ impl SpanAggregator {
                     self.set_otel_status(status.clone(), error_type.as_deref());
                 }
                 for (k, v) in metrics {
-                    self.attributes
-                        .push(KeyValue::new(format!("lambda.runtime.{}", k), v.clone()));
+                    self.attributes.push(KeyValue::new(
+                        self.attribute_naming.metric_key("runtime", k),
+                        v.clone(),
+                    ));
                 }
                 self.add_child_spans(spans);
+                self.phase = self.phase.max(AggregationPhase::RuntimeDone);
             }
             PlatformEventData::Report {
                 status,
@@ -136,24 +301,83 @@ impl SpanAggregator {
                 ..  // Remove trace_context from pattern
             } => {
                 self.end_time = Some(event.timestamp.into());
+                if self.start_time.is_none() {
+                    // platform.start is late or was dropped: the report tells how long it ran
+                    if let Some(OtelValue::F64(duration_ms)) = metrics.get(REPORT_DURATION_METRIC) {
+                        let duration = StdDuration::from_secs_f64(duration_ms.max(0.0) / 1000.0);
+                        self.start_time = SystemTime::from(event.timestamp).checked_sub(duration);
+                    }
+                }
                 self.set_otel_status(status.clone(), error_type.as_deref());
-                for (k, v) in metrics {
-                    self.attributes
-                        .push(KeyValue::new(format!("lambda.report.{}", k), v.clone()));
+                match self.attribute_naming {
+                    AttributeNaming::SemConv => self.add_report_attributes(metrics),
+                    AttributeNaming::Legacy => {
+                        for (k, v) in metrics {
+                            self.attributes.push(KeyValue::new(
+                                self.attribute_naming.metric_key("report", k),
+                                v.clone(),
+                            ));
+                        }
+                        self.attributes
+                            .push(KeyValue::new("faas.execution", self.request_id.clone()));
+                    }
                 }
-                self.attributes
-                    .push(KeyValue::new("faas.execution", self.request_id.clone()));
                 self.add_child_spans(spans);
+                self.phase = AggregationPhase::Reported;
             }
         }
     }
 
+    fn add_report_attributes(&mut self, metrics: &HashMap<String, OtelValue>) {
+        for (k, v) in metrics {
+            match (k.as_str(), v) {
+                (REPORT_MEMORY_SIZE_METRIC, OtelValue::I64(memory_size_mb)) => {
+                    self.attributes.push(KeyValue::new(
+                        semconv::FAAS_MAX_MEMORY,
+                        memory_size_mb * 1024 * 1024,
+                    ));
+                }
+                _ => self.attributes.push(KeyValue::new(
+                    self.attribute_naming.metric_key("report", k),
+                    v.clone(),
+                )),
+            }
+        }
+        // The report of the first invocation after an init or restore tells its duration
+        let coldstart = !self.init_phases.is_empty()
+            || !self.linked_init_phases.is_empty()
+            || metrics.contains_key(REPORT_INIT_DURATION_METRIC)
+            || metrics.contains_key(REPORT_RESTORE_DURATION_METRIC);
+        self.attributes
+            .push(KeyValue::new(semconv::FAAS_COLDSTART, coldstart));
+        self.attributes.push(KeyValue::new(
+            semconv::FAAS_INVOCATION_ID,
+            self.request_id.clone(),
+        ));
+    }
+
+    /// Records the ARN the invocation was invoked with, e.g. with an alias qualifier.
+    pub fn set_invoked_arn(&mut self, invoked_arn: &str) {
+        if self.invoked_arn.is_some() {
+            return;
+        }
+        self.invoked_arn = Some(invoked_arn.to_string());
+        if self.attribute_naming == AttributeNaming::SemConv {
+            self.attributes.push(KeyValue::new(
+                semconv::AWS_LAMBDA_INVOKED_ARN,
+                invoked_arn.to_string(),
+            ));
+        }
+    }
+
+    /// Whether both the report and `platform.runtimeDone` arrived, so the spans can be
+    /// emitted. A report without runtimeDone is left to [`drain_held`].
     pub fn is_complete(&self) -> bool {
-        self.received_event_types
-            .contains(&"platform.runtimeDone".to_string())
+        self.phase == AggregationPhase::Reported
             && self
                 .received_event_types
-                .contains(&"platform.report".to_string())
+                .iter()
+                .any(|t| t == "platform.runtimeDone")
     }
 
     pub fn to_otel_span_data(&self) -> Option<SpanData> {
@@ -190,6 +414,88 @@ impl SpanAggregator {
         })
     }
 
+    /// Returns the `Lambda/Invoke` span followed by its child spans and the `Lambda/Init`
+    /// spans, ready for export. Init spans emitted as their own traces come last and are
+    /// linked from the `Lambda/Invoke` span. Only those are returned while the trace
+    /// context or start time is still unknown.
+    pub fn take_spans(&mut self) -> Vec<SpanData> {
+        self.phase = AggregationPhase::Emitted;
+        let linked_init_spans: Vec<SpanData> = std::mem::take(&mut self.linked_init_phases)
+            .iter()
+            .filter_map(|init| init.to_root_span_data(self.attribute_naming))
+            .collect();
+        let Some(mut span_data) = self.to_otel_span_data() else {
+            return linked_init_spans;
+        };
+        span_data.links.links.extend(
+            linked_init_spans
+                .iter()
+                .map(|init| Link::with_context(init.span_context.clone())),
+        );
+        let mut spans = vec![span_data];
+        spans.append(&mut self.child_spans_data);
+        spans.extend(self.take_init_spans());
+        spans.extend(linked_init_spans);
+        spans
+    }
+
+    /// Attaches init or restore attempts that finished before this invocation, so they are
+    /// emitted as its `Lambda/Init` and `Lambda/Restore` spans, placed according to `mode`.
+    /// The invocation is marked as the first of a restored environment or of a cold start
+    /// accordingly.
+    pub fn attach_init_phases(&mut self, init_phases: Vec<InitPhaseInfo>, mode: InitSpanMode) {
+        if init_phases.is_empty() {
+            return;
+        }
+        let startup = if init_phases
+            .iter()
+            .any(|init| init.kind == StartupKind::Restore)
+        {
+            StartupKind::Restore
+        } else {
+            StartupKind::Init
+        };
+        self.attributes
+            .push(KeyValue::new("lambda.startup", startup.as_str()));
+        for init in init_phases {
+            if mode.is_linked(&init) {
+                self.linked_init_phases.push(init);
+            } else {
+                self.init_phases.push(init);
+            }
+        }
+    }
+
+    /// Builds the `Lambda/Init` and `Lambda/Restore` spans as children of the function's
+    /// root span.
+    fn take_init_spans(&mut self) -> Vec<SpanData> {
+        let init_phases = std::mem::take(&mut self.init_phases);
+        if init_phases.is_empty() {
+            return Vec::new();
+        }
+        let (Some(trace_id), Some(root_span_id)) = (self.trace_id, self.function_root_span_id)
+        else {
+            tracing::warn!(request_id=%self.request_id, "Cannot add init or restore spans: function trace context is missing.");
+            return Vec::new();
+        };
+        // When the `Lambda/Invoke` span is the root of the trace, they hang off it instead
+        let parent_span_id = match self.span_id {
+            Some(span_id) if root_span_id == SpanId::INVALID => span_id,
+            _ => root_span_id,
+        };
+        init_phases
+            .iter()
+            .filter_map(|init| {
+                init.to_span_data(
+                    trace_id,
+                    parent_span_id,
+                    self.trace_flags,
+                    self.attribute_naming,
+                )
+            })
+            .collect()
+    }
+
     fn set_otel_status(&mut self, lambda_status: LambdaStatus, error_type: Option<&str>) {
         match lambda_status {
             LambdaStatus::Success => {
@@ -224,7 +530,7 @@ impl SpanAggregator {
         let parent_span_id = match self.span_id {
             Some(id) => id,
             None => {
-                // This case should be less likely as set_trace_context generates span_id
+                // This case should be less likely as set_parent_context generates span_id
                 tracing::error!(
                     request_id=%self.request_id,
                     "Cannot create platform child spans: parent span_id (Lambda Invoke ID) is missing."
@@ -267,73 +573,250 @@ impl SpanAggregator {
         }
     }
 
-    /// Adds a synthesized span representing the Lambda Init phase.
-    /// This is called when InitDataAvailable is processed, using the stored start time
-    /// and the duration from the first invoke's report.
-    pub fn add_init_phase_span(&mut self, start_time: SystemTime, duration_ms: f64) {
-        // Ensure we have the necessary context from the main function trace
-        let trace_id = if let Some(id) = self.trace_id {
-            id
-        } else {
-            tracing::warn!(request_id=%self.request_id, "Cannot add init phase span: trace_id is missing.");
-            return;
-        };
-        let parent_span_id = if let Some(id) = self.function_root_span_id {
-            id
+    /// Maps known platform span names to standardized constant names.
+    /// Returns the original name if no mapping exists.
+    fn map_platform_span_name(original_name: &str) -> Cow<'static, str> {
+        match original_name {
+            "responseLatency" => Cow::Borrowed(RESPONSE_LATENCY_NAME),
+            "responseDuration" => Cow::Borrowed(RESPONSE_DURATION_NAME),
+            "extensionOverhead" => Cow::Borrowed(EXTENSION_OVERHEAD_NAME),
+            "runtimeOverhead" => Cow::Borrowed(RUNTIME_OVERHEAD_NAME),
+            // Add other mappings here if needed
+            _ => Cow::Owned(original_name.to_string()), // Fallback to original name (owned copy)
+        }
+    }
+}
+
+/// Removes aggregations first seen more than `timeout` ago and returns their spans,
+/// together with the request IDs that were removed.
+pub fn drain_timed_out(
+    aggregations: &mut HashMap<String, SpanAggregator>,
+    now: DateTime<Utc>,
+    timeout: chrono::Duration,
+) -> (Vec<SpanData>, Vec<String>) {
+    let mut spans = Vec::new();
+    let mut request_ids = Vec::new();
+    aggregations.retain(|key, agg| {
+        if (now - agg.first_seen_timestamp) > timeout {
+            tracing::warn!(request_id = %key, ?timeout, "Aggregation timed out. Emitting.");
+            spans.append(&mut agg.take_spans());
+            request_ids.push(key.clone());
+            false
         } else {
-            // Should only happen if OTLP data wasn't parsed before report w/ initDuration
-            tracing::warn!(request_id=%self.request_id, "Cannot add init phase span: function root span_id is missing.");
+            true
+        }
+    });
+    (spans, request_ids)
+}
+
+/// Emits reported aggregations still missing `platform.runtimeDone` after being held
+/// through one drain, and returns their spans together with the request IDs removed.
+///
+/// The Telemetry API may deliver the report in an earlier batch than runtimeDone, whose
+/// metrics and `Response/*` and `Overhead/Runtime` child spans would otherwise be lost. A
+/// runtimeDone that never comes (e.g. the runtime crashed) only delays the spans one drain.
+pub fn drain_held(
+    aggregations: &mut HashMap<String, SpanAggregator>,
+) -> (Vec<SpanData>, Vec<String>) {
+    let mut spans = Vec::new();
+    let mut request_ids = Vec::new();
+    aggregations.retain(|key, agg| {
+        if agg.phase != AggregationPhase::Reported {
+            return true;
+        }
+        if !agg.held {
+            agg.held = true;
+            return true;
+        }
+        tracing::debug!(request_id = %key, "No platform.runtimeDone after the report. Emitting.");
+        spans.append(&mut agg.take_spans());
+        request_ids.push(key.clone());
+        false
+    });
+    (spans, request_ids)
+}
+
+/// Recently emitted request IDs, so that events arriving after an aggregation was emitted
+/// are dropped instead of starting an orphan aggregation. Forgets the oldest IDs first.
+#[derive(Debug)]
+pub struct Tombstones {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+    capacity: usize,
+}
+
+impl Tombstones {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn insert(&mut self, request_id: String) {
+        if self.ids.contains(&request_id) {
             return;
-        };
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(request_id.clone());
+        self.order.push_back(request_id);
+    }
 
-        tracing::debug!(request_id=%self.request_id, %trace_id, %parent_span_id, start=?start_time, duration_ms, "Adding Init Phase span.");
+    pub fn contains(&self, request_id: &str) -> bool {
+        self.ids.contains(request_id)
+    }
+}
 
-        // Calculate end time
-        let duration = StdDuration::from_secs_f64(duration_ms / 1000.0);
-        let end_time = start_time + duration;
+/// ARNs the latest invocations were invoked with, by request ID, kept until their platform
+/// events arrive. Forgets the oldest invocations first.
+#[derive(Debug)]
+pub struct InvokedArns {
+    order: VecDeque<String>,
+    arns: HashMap<String, String>,
+    capacity: usize,
+}
 
-        // Generate a unique span ID for the init phase span
-        let mut rng = rand::rng();
-        let init_span_id = SpanId::from_bytes(rng.random::<[u8; 8]>());
+impl InvokedArns {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            order: VecDeque::new(),
+            arns: HashMap::new(),
+            capacity: capacity.max(1),
+        }
+    }
 
-        let init_span_context = SpanContext::new(
-            trace_id,
-            init_span_id,
-            self.trace_flags, // Inherit flags from parent
-            false,
-            TraceState::default(),
+    pub fn insert(&mut self, request_id: String, invoked_arn: String) {
+        if self.arns.contains_key(&request_id) {
+            return;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.arns.remove(&oldest);
+            }
+        }
+        self.arns.insert(request_id.clone(), invoked_arn);
+        self.order.push_back(request_id);
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<&str> {
+        self.arns.get(request_id).map(String::as_str)
+    }
+}
+
+/// Applies a platform event to the aggregation of its request, creating it if needed, and
+/// returns the spans of the aggregation if the event completed it, together with the trace
+/// context the request's log records are stamped with (see [`SpanAggregator::log_context`]).
+///
+/// `trace_context` is the trace and root span ID of the function's own trace, if known.
+/// Finished init attempts in `init_phases` are handed to the aggregation, since the first
+/// invocation event processed after an init belongs to the invocation that followed it;
+/// `options.init_span_mode` decides whether they join its trace or are linked from it.
+///
+/// `trace_context` is the function's entry span, found on the pipe. The X-Ray context of
+/// the event is the fallback, unless `options.trace_context_precedence` prefers it; an
+/// X-Ray header with `Sampled=0` is ignored, since X-Ray chose not to record that trace.
+/// With `options.generate_traces`, an invocation with neither gets a new trace, replaced
+/// by a context found later.
+///
+/// `invoked_arn` is the ARN the invocation was invoked with, if known.
+pub fn process_event(
+    aggregations: &mut HashMap<String, SpanAggregator>,
+    tombstones: &mut Tombstones,
+    event: &ParsedPlatformEvent,
+    trace_context: Option<(TraceId, SpanId)>,
+    invoked_arn: Option<&str>,
+    init_phases: &mut Vec<InitPhaseInfo>,
+    options: AggregationOptions,
+) -> (Vec<SpanData>, Option<LogContext>) {
+    let request_id = &event.request_id;
+    if tombstones.contains(request_id) {
+        tracing::debug!(request_id = %request_id, "Ignoring late platform event for an emitted aggregation.");
+        return (Vec::new(), None);
+    }
+
+    let agg = aggregations
+        .entry(request_id.clone())
+        .or_insert_with(|| SpanAggregator::new(request_id.clone(), event.timestamp));
+    let precedence = options.trace_context_precedence;
+    if let Some((trace_id, root_span_id)) = trace_context {
+        agg.set_parent_context(
+            ParentContext::from_entry_span(trace_id, root_span_id),
+            precedence,
         );
+    }
+    if let Some(xray_context) = event
+        .data
+        .trace_context()
+        .filter(|context| context.trace_flags.is_sampled())
+    {
+        agg.set_parent_context(xray_context, precedence);
+    }
+    if options.generate_traces && agg.trace_id.is_none() {
+        agg.set_parent_context(ParentContext::generated(), precedence);
+    }
+    agg.attribute_naming = options.attribute_naming;
+    if let Some(invoked_arn) = invoked_arn {
+        agg.set_invoked_arn(invoked_arn);
+    }
+    agg.attach_init_phases(
+        init_phase::take_finished(init_phases),
+        options.init_span_mode,
+    );
+    agg.update_from_event(event);
+    let log_context = agg.log_context();
+    if !agg.is_complete() {
+        return (Vec::new(), log_context);
+    }
 
-        let init_span_data = SpanData {
-            span_context: init_span_context,
-            parent_span_id, // Parent is the actual function's root span
-            span_kind: SpanKind::Internal,
-            name: INIT_PHASE_NAME.into(),
-            start_time,
-            end_time,
-            attributes: vec![], // TODO: Could add lambda.init_type attribute later
-            events: SpanEvents::default(),
-            links: SpanLinks::default(),
-            status: OtelStatus::Ok, // Assume OK if we got this far
-            dropped_attributes_count: 0,
-            instrumentation_scope: InstrumentationScope::default(),
-        };
+    let spans = agg.take_spans();
+    aggregations.remove(request_id);
+    tombstones.insert(request_id.clone());
+    (spans, log_context)
+}
 
-        self.child_spans_data.push(init_span_data);
+/// Removes the oldest aggregations until at most `max_aggregations` are left and returns
+/// their (possibly partial) spans, together with the request IDs that were removed.
+pub fn evict_oldest(
+    aggregations: &mut HashMap<String, SpanAggregator>,
+    max_aggregations: usize,
+) -> (Vec<SpanData>, Vec<String>) {
+    let excess = aggregations.len().saturating_sub(max_aggregations);
+    if excess == 0 {
+        return (Vec::new(), Vec::new());
     }
 
-    /// Maps known platform span names to standardized constant names.
-    /// Returns the original name if no mapping exists.
-    fn map_platform_span_name(original_name: &str) -> Cow<'static, str> {
-        match original_name {
-            "responseLatency" => Cow::Borrowed(RESPONSE_LATENCY_NAME),
-            "responseDuration" => Cow::Borrowed(RESPONSE_DURATION_NAME),
-            "extensionOverhead" => Cow::Borrowed(EXTENSION_OVERHEAD_NAME),
-            "runtimeOverhead" => Cow::Borrowed(RUNTIME_OVERHEAD_NAME),
-            // Add other mappings here if needed
-            _ => Cow::Owned(original_name.to_string()), // Fallback to original name (owned copy)
+    let mut by_age: Vec<(DateTime<Utc>, String)> = aggregations
+        .iter()
+        .map(|(key, agg)| (agg.first_seen_timestamp, key.clone()))
+        .collect();
+    by_age.sort_unstable();
+
+    let mut spans = Vec::new();
+    let mut request_ids = Vec::with_capacity(excess);
+    for (_, key) in by_age.into_iter().take(excess) {
+        if let Some(mut agg) = aggregations.remove(&key) {
+            tracing::warn!(request_id = %key, max_aggregations, "Too many pending aggregations, emitting oldest early.");
+            spans.append(&mut agg.take_spans());
+            request_ids.push(key);
         }
     }
+    (spans, request_ids)
+}
+
+/// Removes every aggregation and returns their spans, e.g. on SHUTDOWN.
+pub fn drain_all(aggregations: &mut HashMap<String, SpanAggregator>) -> Vec<SpanData> {
+    aggregations
+        .drain()
+        .flat_map(|(key, mut agg)| {
+            tracing::debug!("Flushing remaining agg for request_id '{}'", key);
+            agg.take_spans()
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -364,7 +847,7 @@ mod tests {
         assert!(agg.start_time.is_none());
         assert!(agg.end_time.is_none());
         assert_eq!(agg.status, OtelStatus::Unset);
-        assert_eq!(agg.name, "Lambda Invoke");
+        assert_eq!(agg.name, LAMBDA_INVOKE_NAME);
         assert!(matches!(agg.kind, SpanKind::Server));
         assert!(agg.attributes.is_empty());
         assert!(agg.child_spans_data.is_empty());
@@ -378,12 +861,16 @@ mod tests {
         let request_id = "req-start".to_string();
         let timestamp = default_ts();
         let mut agg = SpanAggregator::new(request_id.clone(), timestamp);
+        agg.attribute_naming = AttributeNaming::Legacy;
 
         let trace_id = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10").unwrap();
         let root_span_id = SpanId::from_hex("0102030405060708").unwrap();
 
         // First, set the trace context directly
-        agg.set_trace_context(trace_id, root_span_id);
+        agg.set_parent_context(
+            ParentContext::from_entry_span(trace_id, root_span_id),
+            TraceContextPrecedence::default(),
+        );
 
         // Then create and apply the start event (without trace context)
         let start_event = ParsedPlatformEvent {
@@ -391,6 +878,7 @@ mod tests {
             request_id,
             data: PlatformEventData::Start {
                 version: Some("1.0".to_string()),
+                trace_context: None,
             },
         };
 
@@ -414,6 +902,7 @@ mod tests {
         let request_id = "req-rtd-success".to_string();
         let timestamp = default_ts();
         let mut agg = SpanAggregator::new(request_id.clone(), timestamp);
+        agg.attribute_naming = AttributeNaming::Legacy;
 
         let mut metrics = HashMap::new();
         metrics.insert("runtime.durationMs".to_string(), OtelValue::F64(123.45));
@@ -427,6 +916,7 @@ mod tests {
                 error_type: None,
                 metrics,       // Pass the created metrics map
                 spans: vec![], // No child spans for this test
+                trace_context: None,
             },
         };
 
@@ -458,6 +948,7 @@ mod tests {
                 error_type: Some("Error".to_string()),
                 metrics: HashMap::new(),
                 spans: vec![],
+                trace_context: None,
             },
         };
 
@@ -476,6 +967,7 @@ mod tests {
         let request_id = "req-report".to_string();
         let timestamp = default_ts();
         let mut agg = SpanAggregator::new(request_id.clone(), timestamp);
+        agg.attribute_naming = AttributeNaming::Legacy;
 
         // Simulate receiving RuntimeDone first (optional, but good test)
         let runtime_done_event = ParsedPlatformEvent {
@@ -486,6 +978,7 @@ mod tests {
                 error_type: None,
                 metrics: HashMap::new(),
                 spans: vec![],
+                trace_context: None,
             },
         };
         agg.update_from_event(&runtime_done_event);
@@ -505,6 +998,7 @@ mod tests {
                 error_type: Some("ReportError".to_string()),
                 metrics: report_metrics,
                 spans: vec![],
+                trace_context: None,
             },
         };
 
@@ -535,6 +1029,98 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_semconv_attribute_naming() {
+        let request_id = "req-semconv".to_string();
+        let timestamp = default_ts();
+        let mut agg = SpanAggregator::new(request_id.clone(), timestamp);
+        agg.set_invoked_arn("arn:aws:lambda:us-east-1:123456789012:function:my-function:live");
+
+        let runtime_metrics =
+            HashMap::from([("runtime.producedBytes".to_string(), OtelValue::I64(1024))]);
+        let report_metrics = HashMap::from([
+            ("report.durationMs".to_string(), OtelValue::F64(250.0)),
+            ("report.memorySizeMB".to_string(), OtelValue::I64(128)),
+            ("report.initDurationMs".to_string(), OtelValue::F64(180.0)),
+        ]);
+        for data in [
+            PlatformEventData::Start {
+                version: Some("7".to_string()),
+                trace_context: None,
+            },
+            PlatformEventData::RuntimeDone {
+                status: LambdaStatus::Success,
+                error_type: None,
+                metrics: runtime_metrics,
+                spans: vec![],
+                trace_context: None,
+            },
+            PlatformEventData::Report {
+                status: LambdaStatus::Success,
+                error_type: None,
+                metrics: report_metrics,
+                spans: vec![],
+                trace_context: None,
+            },
+        ] {
+            agg.update_from_event(&ParsedPlatformEvent {
+                timestamp,
+                request_id: request_id.clone(),
+                data,
+            });
+        }
+
+        let attribute = |key: &str| {
+            agg.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+        assert_eq!(attribute(semconv::FAAS_VERSION), Some("7".into()));
+        assert_eq!(
+            attribute(semconv::FAAS_INVOCATION_ID),
+            Some("req-semconv".into())
+        );
+        assert_eq!(
+            attribute(semconv::FAAS_MAX_MEMORY),
+            Some(OtelValue::I64(128 * 1024 * 1024))
+        );
+        assert_eq!(attribute(semconv::FAAS_COLDSTART), Some(true.into()));
+        assert_eq!(
+            attribute(semconv::AWS_LAMBDA_INVOKED_ARN),
+            Some("arn:aws:lambda:us-east-1:123456789012:function:my-function:live".into())
+        );
+        assert_eq!(
+            attribute("lambda.runtime.producedBytes"),
+            Some(OtelValue::I64(1024))
+        );
+        assert_eq!(
+            attribute("lambda.report.durationMs"),
+            Some(OtelValue::F64(250.0))
+        );
+        for legacy in [
+            "faas.instance",
+            "faas.execution",
+            "lambda.report.report.durationMs",
+            "lambda.report.memorySizeMB",
+        ] {
+            assert_eq!(attribute(legacy), None, "{legacy}");
+        }
+    }
+
+    #[test]
+    fn test_attribute_naming_from_str() {
+        assert_eq!(
+            "SemConv".parse::<AttributeNaming>(),
+            Ok(AttributeNaming::SemConv)
+        );
+        assert_eq!(
+            "legacy".parse::<AttributeNaming>(),
+            Ok(AttributeNaming::Legacy)
+        );
+        assert!("otel".parse::<AttributeNaming>().is_err());
+    }
+
     #[test]
     fn test_is_complete() {
         let request_id = "req-complete".to_string();
@@ -547,7 +1133,10 @@ mod tests {
         let start_event = ParsedPlatformEvent {
             timestamp,
             request_id: request_id.clone(),
-            data: PlatformEventData::Start { version: None },
+            data: PlatformEventData::Start {
+                version: None,
+                trace_context: None,
+            },
         };
         agg.update_from_event(&start_event);
         assert!(!agg.is_complete());
@@ -561,6 +1150,7 @@ mod tests {
                 error_type: None,
                 metrics: HashMap::new(),
                 spans: vec![],
+                trace_context: None,
             },
         };
         agg.update_from_event(&runtime_done_event);
@@ -575,6 +1165,7 @@ mod tests {
                 error_type: None,
                 metrics: HashMap::new(),
                 spans: vec![],
+                trace_context: None,
             },
         };
         agg.update_from_event(&report_event);
@@ -647,7 +1238,7 @@ mod tests {
         assert_eq!(span_data.span_context.span_id(), span_id);
         assert_eq!(span_data.span_context.trace_flags(), TraceFlags::SAMPLED);
         assert_eq!(span_data.parent_span_id, root_span_id);
-        assert_eq!(span_data.name.as_ref(), "Lambda Invoke"); // Compare Cow as &str
+        assert_eq!(span_data.name.as_ref(), LAMBDA_INVOKE_NAME); // Compare Cow as &str
         assert_eq!(span_data.start_time, start_system_time);
         assert_eq!(span_data.end_time, end_system_time);
         assert_eq!(span_data.status, OtelStatus::Ok);
@@ -721,6 +1312,7 @@ mod tests {
                 error_type: None,
                 metrics: HashMap::new(),
                 spans: telemetry_spans, // Pass the child spans
+                trace_context: None,
             },
         };
 
@@ -757,4 +1349,657 @@ mod tests {
         assert_eq!(child2.end_time, expected_end2);
         assert!(matches!(child2.span_kind, SpanKind::Internal));
     }
+
+    #[test]
+    fn test_evict_oldest() {
+        let mut aggregations = HashMap::new();
+        for (minutes, request_id) in [(2, "req-new"), (0, "req-oldest"), (1, "req-old")] {
+            let timestamp = default_ts() + chrono::Duration::minutes(minutes);
+            let mut agg = SpanAggregator::new(request_id.to_string(), timestamp);
+            agg.set_parent_context(
+                ParentContext::from_entry_span(
+                    TraceId::from_bytes([1; 16]),
+                    SpanId::from_bytes([1; 8]),
+                ),
+                TraceContextPrecedence::default(),
+            );
+            agg.update_from_event(&ParsedPlatformEvent {
+                timestamp,
+                request_id: request_id.to_string(),
+                data: PlatformEventData::Start {
+                    version: None,
+                    trace_context: None,
+                },
+            });
+            aggregations.insert(request_id.to_string(), agg);
+        }
+
+        let (spans, request_ids) = evict_oldest(&mut aggregations, 1);
+        assert_eq!(request_ids, vec!["req-oldest", "req-old"]);
+        // The evicted aggregations are emitted as partial spans rather than lost
+        assert_eq!(spans.len(), 2);
+        assert!(aggregations.contains_key("req-new"));
+
+        let (spans, request_ids) = evict_oldest(&mut aggregations, 1);
+        assert!(spans.is_empty());
+        assert!(request_ids.is_empty());
+    }
+
+    /// A platform event of an invocation that started at `default_ts()` and ran 200ms.
+    fn lifecycle_event(request_id: &str, event_type: &str) -> ParsedPlatformEvent {
+        let (offset_ms, data) = match event_type {
+            "start" => (
+                0,
+                PlatformEventData::Start {
+                    version: None,
+                    trace_context: None,
+                },
+            ),
+            "runtimeDone" => (
+                150,
+                PlatformEventData::RuntimeDone {
+                    status: LambdaStatus::Success,
+                    error_type: None,
+                    metrics: HashMap::new(),
+                    spans: vec![],
+                    trace_context: None,
+                },
+            ),
+            "report" => (
+                200,
+                PlatformEventData::Report {
+                    status: LambdaStatus::Success,
+                    error_type: None,
+                    metrics: HashMap::from([(
+                        REPORT_DURATION_METRIC.to_string(),
+                        OtelValue::F64(200.0),
+                    )]),
+                    spans: vec![],
+                    trace_context: None,
+                },
+            ),
+            other => panic!("unknown event type {other}"),
+        };
+        ParsedPlatformEvent {
+            timestamp: default_ts() + chrono::Duration::milliseconds(offset_ms),
+            request_id: request_id.to_string(),
+            data,
+        }
+    }
+
+    // The runtimeDone fixture, with the metrics and child spans it carries in practice
+    fn runtime_done_with_spans(request_id: &str) -> ParsedPlatformEvent {
+        let mut event = lifecycle_event(request_id, "runtimeDone");
+        if let PlatformEventData::RuntimeDone { metrics, spans, .. } = &mut event.data {
+            metrics.insert("runtime.durationMs".to_string(), OtelValue::F64(150.0));
+            spans.push(TelemetrySpan {
+                duration_ms: 10.0,
+                name: "responseLatency".to_string(),
+                start: default_ts() + chrono::Duration::milliseconds(140),
+            });
+        }
+        event
+    }
+
+    #[test]
+    fn test_process_event_in_every_order() {
+        let trace_context = Some((TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8])));
+        let orders = [
+            ["start", "runtimeDone", "report"],
+            ["start", "report", "runtimeDone"],
+            ["runtimeDone", "start", "report"],
+            ["runtimeDone", "report", "start"],
+            ["report", "start", "runtimeDone"],
+            ["report", "runtimeDone", "start"],
+        ];
+
+        for order in orders {
+            let mut aggregations = HashMap::new();
+            let mut tombstones = Tombstones::new(16);
+            let mut emitted = Vec::new();
+            let mut seen = Vec::new();
+            for (i, event_type) in order.iter().enumerate() {
+                let event = match *event_type {
+                    "runtimeDone" => runtime_done_with_spans("req-1"),
+                    other => lifecycle_event("req-1", other),
+                };
+                let (spans, _) = process_event(
+                    &mut aggregations,
+                    &mut tombstones,
+                    &event,
+                    trace_context,
+                    None,
+                    &mut Vec::new(),
+                    AggregationOptions::default(),
+                );
+                seen.push(*event_type);
+                // The aggregation completes once both the report and runtimeDone arrived
+                assert_eq!(
+                    !spans.is_empty(),
+                    seen.contains(&"report") && seen.contains(&"runtimeDone") && emitted.is_empty(),
+                    "{order:?} at {i}"
+                );
+                emitted.extend(spans);
+            }
+
+            assert_eq!(emitted.len(), 2, "{order:?}");
+            let span = &emitted[0];
+            assert_eq!(span.name, LAMBDA_INVOKE_NAME, "{order:?}");
+            // The start time comes from platform.start, or from the report's duration
+            assert_eq!(span.start_time, SystemTime::from(default_ts()), "{order:?}");
+            assert_eq!(
+                span.end_time,
+                SystemTime::from(default_ts() + chrono::Duration::milliseconds(200)),
+                "{order:?}"
+            );
+            // runtimeDone's attributes and child spans survive a report that arrived first
+            assert!(
+                span.attributes
+                    .iter()
+                    .any(|kv| kv.key.as_str() == "lambda.runtime.durationMs"
+                        && kv.value == OtelValue::F64(150.0)),
+                "{order:?}"
+            );
+            assert_eq!(emitted[1].name, RESPONSE_LATENCY_NAME, "{order:?}");
+            assert_eq!(
+                emitted[1].parent_span_id,
+                span.span_context.span_id(),
+                "{order:?}"
+            );
+            assert!(
+                aggregations.is_empty(),
+                "{order:?} left an orphan aggregation"
+            );
+            assert!(tombstones.contains("req-1"), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn test_report_is_held_for_runtime_done_until_next_drain() {
+        let trace_context = Some((TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8])));
+        let mut aggregations = HashMap::new();
+        let mut tombstones = Tombstones::new(16);
+        let mut process = |aggregations: &mut HashMap<String, SpanAggregator>,
+                           event: ParsedPlatformEvent| {
+            process_event(
+                aggregations,
+                &mut tombstones,
+                &event,
+                trace_context,
+                None,
+                &mut Vec::new(),
+                AggregationOptions::default(),
+            )
+            .0
+        };
+
+        // runtimeDone comes in the batch after the report: the drain in between holds it
+        assert!(process(&mut aggregations, lifecycle_event("req-1", "report")).is_empty());
+        assert!(drain_held(&mut aggregations).0.is_empty());
+        assert!(aggregations["req-1"].held);
+        let emitted = process(&mut aggregations, runtime_done_with_spans("req-1"));
+        assert_eq!(emitted.len(), 2);
+        assert_eq!(emitted[1].name, RESPONSE_LATENCY_NAME);
+        assert!(aggregations.is_empty());
+
+        // runtimeDone never comes: the second drain emits what the report told
+        assert!(process(&mut aggregations, lifecycle_event("req-2", "report")).is_empty());
+        assert!(drain_held(&mut aggregations).0.is_empty());
+        let (emitted, request_ids) = drain_held(&mut aggregations);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].name, LAMBDA_INVOKE_NAME);
+        assert_eq!(request_ids, vec!["req-2".to_string()]);
+        assert!(aggregations.is_empty());
+
+        // Aggregations still waiting for their report are left alone
+        assert!(process(&mut aggregations, lifecycle_event("req-3", "start")).is_empty());
+        drain_held(&mut aggregations);
+        assert!(drain_held(&mut aggregations).0.is_empty());
+        assert!(aggregations.contains_key("req-3"));
+    }
+
+    #[test]
+    fn test_process_event_ignores_repeated_events() {
+        let trace_context = Some((TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8])));
+        let mut aggregations = HashMap::new();
+        let mut tombstones = Tombstones::new(16);
+        for event_type in ["start", "runtimeDone", "runtimeDone"] {
+            let (spans, _) = process_event(
+                &mut aggregations,
+                &mut tombstones,
+                &lifecycle_event("req-1", event_type),
+                trace_context,
+                None,
+                &mut Vec::new(),
+                AggregationOptions::default(),
+            );
+            assert!(spans.is_empty());
+        }
+
+        let agg = &aggregations["req-1"];
+        assert_eq!(agg.phase, AggregationPhase::RuntimeDone);
+        assert_eq!(
+            agg.received_event_types,
+            vec!["platform.start", "platform.runtimeDone"]
+        );
+    }
+
+    #[test]
+    fn test_emitted_aggregation_ignores_late_events() {
+        let mut agg = SpanAggregator::new("req-1".to_string(), default_ts());
+        agg.update_from_event(&lifecycle_event("req-1", "start"));
+        agg.take_spans();
+        assert_eq!(agg.phase, AggregationPhase::Emitted);
+
+        agg.update_from_event(&lifecycle_event("req-1", "report"));
+        assert_eq!(agg.phase, AggregationPhase::Emitted);
+        assert!(agg.end_time.is_none());
+    }
+
+    #[test]
+    fn test_tombstones_forget_oldest() {
+        let mut tombstones = Tombstones::new(2);
+        tombstones.insert("req-1".to_string());
+        tombstones.insert("req-2".to_string());
+        tombstones.insert("req-2".to_string());
+        assert!(tombstones.contains("req-1"));
+
+        tombstones.insert("req-3".to_string());
+        assert!(!tombstones.contains("req-1"));
+        assert!(tombstones.contains("req-2"));
+        assert!(tombstones.contains("req-3"));
+    }
+
+    // Runs an invocation preceded by a finished init of the given type, returning its spans
+    fn invoke_after_init(
+        initialization_type: lambda_extension::InitType,
+        init_span_mode: InitSpanMode,
+    ) -> Vec<SpanData> {
+        let mut init_phases = Vec::new();
+        init_phase::record_event(
+            &mut init_phases,
+            &ParsedPlatformEvent {
+                timestamp: default_ts() - chrono::Duration::milliseconds(300),
+                request_id: String::new(),
+                data: PlatformEventData::InitReport {
+                    initialization_type,
+                    phase: lambda_extension::InitPhase::Init,
+                    duration_ms: 250.0,
+                },
+            },
+        );
+
+        let trace_context = Some((TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8])));
+        let mut aggregations = HashMap::new();
+        let mut tombstones = Tombstones::new(16);
+        let mut emitted = Vec::new();
+        for event_type in ["start", "runtimeDone", "report"] {
+            let (spans, _) = process_event(
+                &mut aggregations,
+                &mut tombstones,
+                &lifecycle_event("req-1", event_type),
+                trace_context,
+                None,
+                &mut init_phases,
+                AggregationOptions {
+                    init_span_mode,
+                    ..Default::default()
+                },
+            );
+            emitted.extend(spans);
+        }
+
+        assert!(init_phases.is_empty());
+        emitted
+    }
+
+    #[test]
+    fn test_finished_init_is_emitted_with_next_invocation() {
+        let emitted = invoke_after_init(lambda_extension::InitType::OnDemand, InitSpanMode::Auto);
+
+        let names: Vec<&str> = emitted.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, vec![LAMBDA_INVOKE_NAME, "Lambda/Init"]);
+        // The init span hangs off the function's root span, like the invocation span
+        assert_eq!(emitted[1].parent_span_id, SpanId::from_bytes([2; 8]));
+        assert!(emitted[0].links.is_empty());
+    }
+
+    #[test]
+    fn test_provisioned_concurrency_init_is_linked_trace() {
+        for mode in [InitSpanMode::Auto, InitSpanMode::Linked] {
+            let emitted =
+                invoke_after_init(lambda_extension::InitType::ProvisionedConcurrency, mode);
+
+            let names: Vec<&str> = emitted.iter().map(|span| span.name.as_ref()).collect();
+            assert_eq!(names, vec![LAMBDA_INVOKE_NAME, "Lambda/Init"]);
+            let (invoke, init) = (&emitted[0], &emitted[1]);
+            // The init span is the root of its own trace...
+            assert_eq!(init.parent_span_id, SpanId::INVALID);
+            assert_ne!(init.span_context.trace_id(), invoke.span_context.trace_id());
+            assert!(
+                init.attributes
+                    .iter()
+                    .any(|kv| kv.key.as_str() == "lambda.init.type"
+                        && kv.value.as_str() == "provisioned-concurrency")
+            );
+            // ...linked from the first invocation
+            assert_eq!(invoke.links.len(), 1);
+            assert_eq!(invoke.links[0].span_context, init.span_context);
+        }
+
+        // Child mode keeps it in the invocation's trace regardless of the init type
+        let emitted = invoke_after_init(
+            lambda_extension::InitType::ProvisionedConcurrency,
+            InitSpanMode::Child,
+        );
+        assert_eq!(emitted[1].parent_span_id, SpanId::from_bytes([2; 8]));
+        assert!(emitted[0].links.is_empty());
+    }
+
+    #[test]
+    fn test_linked_init_is_emitted_without_trace_context() {
+        let mut agg = SpanAggregator::new("req-1".to_string(), default_ts());
+        let mut init_phases = Vec::new();
+        init_phase::record_event(
+            &mut init_phases,
+            &ParsedPlatformEvent {
+                timestamp: default_ts(),
+                request_id: String::new(),
+                data: PlatformEventData::InitReport {
+                    initialization_type: lambda_extension::InitType::OnDemand,
+                    phase: lambda_extension::InitPhase::Init,
+                    duration_ms: 100.0,
+                },
+            },
+        );
+        agg.attach_init_phases(init_phases, InitSpanMode::Linked);
+        agg.update_from_event(&lifecycle_event("req-1", "report"));
+
+        // Without the function's trace context only the init trace can be emitted
+        let spans = agg.take_spans();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "Lambda/Init");
+    }
+
+    const XRAY_HEADER: &str =
+        "Root=1-6352a70e-1e2c502e358361800241fd45;Parent=35465b3a9e2f7c6a;Sampled=1";
+
+    /// A lifecycle event carrying the X-Ray tracing header, with child spans on runtimeDone.
+    fn xray_event(event_type: &str) -> ParsedPlatformEvent {
+        let mut event = lifecycle_event("req-1", event_type);
+        let xray_context = ParentContext::from_xray_header(XRAY_HEADER);
+        match &mut event.data {
+            PlatformEventData::Start { trace_context, .. }
+            | PlatformEventData::Report { trace_context, .. } => *trace_context = xray_context,
+            PlatformEventData::RuntimeDone {
+                trace_context,
+                spans,
+                ..
+            } => {
+                *trace_context = xray_context;
+                spans.push(TelemetrySpan {
+                    duration_ms: 10.0,
+                    name: "responseLatency".to_string(),
+                    start: default_ts(),
+                });
+            }
+            _ => unreachable!(),
+        }
+        event
+    }
+
+    // Runs an invocation whose events carry an X-Ray header, with the pipe trace context
+    // known from the event at `pipe_from` on
+    fn invoke_with_xray(pipe_from: usize, precedence: TraceContextPrecedence) -> Vec<SpanData> {
+        let pipe_context = (TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8]));
+        let mut aggregations = HashMap::new();
+        let mut tombstones = Tombstones::new(16);
+        let mut emitted = Vec::new();
+        for (i, event_type) in ["start", "runtimeDone", "report"].iter().enumerate() {
+            let (spans, _) = process_event(
+                &mut aggregations,
+                &mut tombstones,
+                &xray_event(event_type),
+                (i >= pipe_from).then_some(pipe_context),
+                None,
+                &mut Vec::new(),
+                AggregationOptions {
+                    trace_context_precedence: precedence,
+                    ..Default::default()
+                },
+            );
+            emitted.extend(spans);
+        }
+        emitted
+    }
+
+    #[test]
+    fn test_xray_context_is_fallback_parent() {
+        let xray_trace_id = TraceId::from_hex("6352a70e1e2c502e358361800241fd45").unwrap();
+
+        // No spans from the function: the invocation joins the X-Ray trace
+        let emitted = invoke_with_xray(usize::MAX, TraceContextPrecedence::Pipe);
+        assert_eq!(emitted.len(), 2);
+        assert!(
+            emitted
+                .iter()
+                .all(|span| span.span_context.trace_id() == xray_trace_id)
+        );
+        assert_eq!(
+            emitted[0].parent_span_id,
+            SpanId::from_hex("35465b3a9e2f7c6a").unwrap()
+        );
+        assert_eq!(emitted[1].parent_span_id, emitted[0].span_context.span_id());
+
+        // The function's trace, known late, still wins and takes the child spans along
+        let emitted = invoke_with_xray(2, TraceContextPrecedence::Pipe);
+        assert_eq!(emitted.len(), 2);
+        assert!(
+            emitted
+                .iter()
+                .all(|span| span.span_context.trace_id() == TraceId::from_bytes([1; 16]))
+        );
+        assert_eq!(emitted[0].parent_span_id, SpanId::from_bytes([2; 8]));
+    }
+
+    #[test]
+    fn test_xray_context_precedence() {
+        let emitted = invoke_with_xray(0, TraceContextPrecedence::XRay);
+        assert_eq!(
+            emitted[0].span_context.trace_id(),
+            TraceId::from_hex("6352a70e1e2c502e358361800241fd45").unwrap()
+        );
+        assert_eq!(
+            emitted[0].parent_span_id,
+            SpanId::from_hex("35465b3a9e2f7c6a").unwrap()
+        );
+
+        let emitted = invoke_with_xray(0, TraceContextPrecedence::Pipe);
+        assert_eq!(
+            emitted[0].span_context.trace_id(),
+            TraceId::from_bytes([1; 16])
+        );
+    }
+
+    // Runs an invocation without X-Ray header, with the pipe trace context known from the
+    // event at `pipe_from` on, after an on-demand init
+    fn invoke_without_context(pipe_from: usize, options: AggregationOptions) -> Vec<SpanData> {
+        let mut init_phases = Vec::new();
+        init_phase::record_event(
+            &mut init_phases,
+            &ParsedPlatformEvent {
+                timestamp: default_ts() - chrono::Duration::milliseconds(300),
+                request_id: String::new(),
+                data: PlatformEventData::InitReport {
+                    initialization_type: lambda_extension::InitType::OnDemand,
+                    phase: lambda_extension::InitPhase::Init,
+                    duration_ms: 250.0,
+                },
+            },
+        );
+
+        let pipe_context = (TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8]));
+        let mut aggregations = HashMap::new();
+        let mut tombstones = Tombstones::new(16);
+        let mut emitted = Vec::new();
+        for (i, event_type) in ["start", "runtimeDone", "report"].iter().enumerate() {
+            let mut event = lifecycle_event("req-1", event_type);
+            if let PlatformEventData::RuntimeDone { spans, .. } = &mut event.data {
+                spans.push(TelemetrySpan {
+                    duration_ms: 10.0,
+                    name: "responseLatency".to_string(),
+                    start: default_ts(),
+                });
+            }
+            let (spans, _) = process_event(
+                &mut aggregations,
+                &mut tombstones,
+                &event,
+                (i >= pipe_from).then_some(pipe_context),
+                None,
+                &mut init_phases,
+                options,
+            );
+            emitted.extend(spans);
+        }
+        emitted
+    }
+
+    #[test]
+    fn test_generated_trace_for_invocation_without_context() {
+        let options = AggregationOptions {
+            generate_traces: true,
+            ..Default::default()
+        };
+        let emitted = invoke_without_context(usize::MAX, options);
+
+        let names: Vec<&str> = emitted.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(
+            names,
+            vec![LAMBDA_INVOKE_NAME, RESPONSE_LATENCY_NAME, "Lambda/Init"]
+        );
+        let invoke = &emitted[0];
+        let trace_id = invoke.span_context.trace_id();
+        assert_ne!(trace_id, TraceId::INVALID);
+        assert!(invoke.span_context.is_sampled());
+        // Lambda/Invoke is the root, with the platform and init spans under it
+        assert_eq!(invoke.parent_span_id, SpanId::INVALID);
+        for span in &emitted[1..] {
+            assert_eq!(span.span_context.trace_id(), trace_id);
+            assert_eq!(span.parent_span_id, invoke.span_context.span_id());
+        }
+
+        // Off by default: without a trace context nothing can be emitted
+        assert!(invoke_without_context(usize::MAX, AggregationOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_generated_trace_is_replaced_by_pipe_context() {
+        let options = AggregationOptions {
+            generate_traces: true,
+            ..Default::default()
+        };
+        let emitted = invoke_without_context(2, options);
+
+        assert_eq!(emitted.len(), 3);
+        assert!(
+            emitted
+                .iter()
+                .all(|span| span.span_context.trace_id() == TraceId::from_bytes([1; 16]))
+        );
+        assert_eq!(emitted[0].parent_span_id, SpanId::from_bytes([2; 8]));
+        assert_eq!(emitted[2].parent_span_id, SpanId::from_bytes([2; 8]));
+    }
+
+    // Runs an invocation whose events carry `xray_header`, returning its spans and the log
+    // context reported for each event
+    fn invoke_for_log_context(
+        xray_header: &str,
+        pipe_context: Option<(TraceId, SpanId)>,
+        options: AggregationOptions,
+    ) -> (Vec<SpanData>, Vec<Option<LogContext>>) {
+        let mut aggregations = HashMap::new();
+        let mut tombstones = Tombstones::new(16);
+        let mut emitted = Vec::new();
+        let mut log_contexts = Vec::new();
+        for event_type in ["start", "runtimeDone", "report"] {
+            let mut event = lifecycle_event("req-1", event_type);
+            match &mut event.data {
+                PlatformEventData::Start { trace_context, .. }
+                | PlatformEventData::RuntimeDone { trace_context, .. }
+                | PlatformEventData::Report { trace_context, .. } => {
+                    *trace_context = ParentContext::from_xray_header(xray_header);
+                }
+                _ => unreachable!(),
+            }
+            let (spans, log_context) = process_event(
+                &mut aggregations,
+                &mut tombstones,
+                &event,
+                pipe_context,
+                None,
+                &mut Vec::new(),
+                options,
+            );
+            emitted.extend(spans);
+            log_contexts.push(log_context);
+        }
+        (emitted, log_contexts)
+    }
+
+    #[test]
+    fn test_log_context_follows_resolved_parent() {
+        let invoke_context = |emitted: &[SpanData]| {
+            let invoke = &emitted[0];
+            Some((
+                invoke.span_context.trace_id(),
+                invoke.span_context.span_id(),
+                invoke.span_context.trace_flags(),
+            ))
+        };
+
+        // Pipe: logs join the function's entry span, as before
+        let pipe_context = (TraceId::from_bytes([1; 16]), SpanId::from_bytes([2; 8]));
+        let (_, log_contexts) = invoke_for_log_context(
+            XRAY_HEADER,
+            Some(pipe_context),
+            AggregationOptions::default(),
+        );
+        let (trace_id, span_id) = pipe_context;
+        assert!(
+            log_contexts
+                .iter()
+                .all(|c| *c == Some((trace_id, span_id, TraceFlags::SAMPLED)))
+        );
+
+        // X-Ray fallback: logs join the exported Lambda/Invoke span from the first event on
+        let (emitted, log_contexts) =
+            invoke_for_log_context(XRAY_HEADER, None, AggregationOptions::default());
+        assert_eq!(
+            emitted[0].span_context.trace_id(),
+            TraceId::from_hex("6352a70e1e2c502e358361800241fd45").unwrap()
+        );
+        assert!(log_contexts.iter().all(|c| *c == invoke_context(&emitted)));
+
+        // Generated trace: same, in the minted trace
+        let options = AggregationOptions {
+            generate_traces: true,
+            ..Default::default()
+        };
+        let unsampled =
+            "Root=1-6352a70e-1e2c502e358361800241fd45;Parent=35465b3a9e2f7c6a;Sampled=0";
+        let (emitted, log_contexts) = invoke_for_log_context(unsampled, None, options);
+        assert_ne!(
+            emitted[0].span_context.trace_id(),
+            TraceId::from_hex("6352a70e1e2c502e358361800241fd45").unwrap()
+        );
+        assert!(emitted[0].span_context.is_sampled());
+        assert!(log_contexts.iter().all(|c| *c == invoke_context(&emitted)));
+
+        // An unsampled X-Ray header is no fallback: nothing to parent spans or logs to
+        let (emitted, log_contexts) =
+            invoke_for_log_context(unsampled, None, AggregationOptions::default());
+        assert!(emitted.is_empty());
+        assert!(log_contexts.iter().all(Option::is_none));
+    }
 }
@@ -0,0 +1,411 @@
+use lambda_extension::tracing;
+use rand::Rng;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::task::{JoinError, JoinSet};
+
+// Backoff bounds for retrying records rejected by a batch request
+const RETRY_BASE_DELAY_MS: u64 = 50;
+const RETRY_MAX_DELAY_MS: u64 = 1000;
+
+/// Controls how records rejected by a batch request are resubmitted.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub budget: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, budget_ms: u64) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            budget: Duration::from_millis(budget_ms),
+        }
+    }
+
+    /// Returns the delay before the given retry attempt (1-based), using exponential
+    /// backoff with full jitter so concurrent environments don't retry in lockstep.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = RETRY_BASE_DELAY_MS.saturating_mul(1u64 << attempt.saturating_sub(1).min(16));
+        let cap = exp.min(RETRY_MAX_DELAY_MS);
+        Duration::from_millis(rand::rng().random_range(0..=cap))
+    }
+}
+
+/// Returns the entries whose matching result reports a failure.
+/// PutRecords and PutRecordBatch both return results in the same order as the request entries.
+pub fn failed_entries<T>(sent: Vec<T>, failures: &[Option<String>]) -> Vec<T> {
+    sent.into_iter()
+        .zip(failures)
+        .filter_map(|(entry, failure)| failure.is_some().then_some(entry))
+        .collect()
+}
+
+/// Splits records into groups that each fit in a single batch request,
+/// given the request's record count and payload size limits.
+pub fn split_into_requests<T>(
+    records: Vec<T>,
+    max_records: usize,
+    max_bytes: usize,
+    size_of: impl Fn(&T) -> usize,
+) -> Vec<Vec<T>> {
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut current_size = 0;
+
+    for record in records {
+        let size = size_of(&record);
+        if !current.is_empty() && (current.len() >= max_records || current_size + size > max_bytes)
+        {
+            chunks.push(std::mem::take(&mut current));
+            current_size = 0;
+        }
+        current_size += size;
+        current.push(record);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Result of delivering one request-sized chunk.
+#[derive(Debug)]
+pub struct ChunkOutcome<T> {
+    pub delivered: usize,
+    /// Records still rejected once the retry policy ran out.
+    pub given_up: Vec<T>,
+    /// Records that were never accepted because the request itself failed.
+    pub unsent: Vec<T>,
+    pub error: Option<String>,
+}
+
+/// Sends one chunk, resubmitting only the entries rejected by each call
+/// until the retry policy's attempt count or time budget runs out.
+///
+/// `send` builds a single batch request from the pending entries and returns, for each
+/// entry in order, a description of why it was rejected or `None` if it was accepted.
+/// The records themselves stay here, so rejected ones are retained in place.
+pub async fn send_with_retry<T, F, Fut>(
+    records: Vec<T>,
+    policy: &RetryPolicy,
+    mut send: F,
+) -> ChunkOutcome<T>
+where
+    F: FnMut(&[T]) -> Fut,
+    Fut: Future<Output = Result<Vec<Option<String>>, String>>,
+{
+    let total = records.len();
+    let mut pending = records;
+    let deadline = Instant::now() + policy.budget;
+    let mut attempt: u32 = 1;
+
+    loop {
+        let failures = match send(&pending).await {
+            Ok(failures) => failures,
+            Err(error) => {
+                return ChunkOutcome {
+                    delivered: total - pending.len(),
+                    given_up: Vec::new(),
+                    unsent: pending,
+                    error: Some(error),
+                };
+            }
+        };
+
+        let failed_count = failures.iter().filter(|f| f.is_some()).count();
+        if failed_count == 0 {
+            return ChunkOutcome {
+                delivered: total,
+                given_up: Vec::new(),
+                unsent: Vec::new(),
+                error: None,
+            };
+        }
+
+        tracing::warn!(
+            "Failed to put {} of {} records (attempt {}/{})",
+            failed_count,
+            pending.len(),
+            attempt,
+            policy.max_attempts
+        );
+        for (i, failure) in failures.iter().enumerate() {
+            if let Some(failure) = failure {
+                tracing::debug!("Record {} failed with error: {}", i, failure);
+            }
+        }
+        pending = failed_entries(pending, &failures);
+
+        if attempt >= policy.max_attempts {
+            break;
+        }
+        let delay = policy.backoff(attempt);
+        if Instant::now() + delay > deadline {
+            tracing::debug!("Retry budget exhausted");
+            break;
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+
+    ChunkOutcome {
+        delivered: total - pending.len(),
+        given_up: pending,
+        unsent: Vec::new(),
+        error: None,
+    }
+}
+
+/// Totals across all chunks of a single flush.
+#[derive(Debug)]
+pub struct FlushSummary<T> {
+    pub delivered: usize,
+    pub given_up: Vec<T>,
+    pub failed_chunks: usize,
+    pub unsent: Vec<T>,
+}
+
+impl<T> Default for FlushSummary<T> {
+    fn default() -> Self {
+        Self {
+            delivered: 0,
+            given_up: Vec::new(),
+            failed_chunks: 0,
+            unsent: Vec::new(),
+        }
+    }
+}
+
+impl<T> FlushSummary<T> {
+    /// Accounts for a finished chunk task.
+    pub fn absorb(&mut self, joined: Result<(usize, ChunkOutcome<T>), JoinError>) {
+        match joined {
+            Ok((index, mut outcome)) => {
+                self.delivered += outcome.delivered;
+                self.given_up.append(&mut outcome.given_up);
+                if let Some(error) = outcome.error {
+                    tracing::error!(
+                        chunk = index,
+                        unsent = outcome.unsent.len(),
+                        "Batch request failed: {}",
+                        error
+                    );
+                    self.failed_chunks += 1;
+                    self.unsent.append(&mut outcome.unsent);
+                }
+            }
+            Err(e) => {
+                // Release builds abort on panic and tasks are never cancelled, so this is
+                // only reachable in tests; the task's records went down with it
+                tracing::error!("Batch request task failed, dropping its records: {}", e);
+                self.failed_chunks += 1;
+            }
+        }
+    }
+}
+
+/// Delivers chunks concurrently, keeping at most `max_concurrency` requests in flight.
+pub async fn deliver_chunks<T, F, Fut>(
+    chunks: Vec<Vec<T>>,
+    max_concurrency: usize,
+    mut deliver: F,
+) -> FlushSummary<T>
+where
+    T: Send + 'static,
+    F: FnMut(Vec<T>) -> Fut,
+    Fut: Future<Output = ChunkOutcome<T>> + Send + 'static,
+{
+    let mut summary = FlushSummary::default();
+    let mut tasks = JoinSet::new();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        if tasks.len() >= max_concurrency.max(1) {
+            if let Some(joined) = tasks.join_next().await {
+                summary.absorb(joined);
+            }
+        }
+        let request = deliver(chunk);
+        tasks.spawn(async move { (index, request.await) });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        summary.absorb(joined);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_entries_keeps_only_rejected_records() {
+        let sent = vec!["a", "b", "c"];
+        let failures = vec![
+            None,
+            Some("ProvisionedThroughputExceededException".to_string()),
+            None,
+        ];
+        assert_eq!(failed_entries(sent, &failures), vec!["b"]);
+    }
+
+    #[test]
+    fn test_failed_entries_all_succeeded() {
+        let sent = vec!["a", "b"];
+        assert!(failed_entries(sent, &[None, None]).is_empty());
+    }
+
+    #[test]
+    fn test_retry_backoff_is_bounded() {
+        let policy = RetryPolicy::new(10, 5000);
+        for attempt in 1..=10 {
+            let cap = (RETRY_BASE_DELAY_MS << (attempt - 1)).min(RETRY_MAX_DELAY_MS);
+            assert!(policy.backoff(attempt) <= Duration::from_millis(cap));
+        }
+        // Very large attempt numbers must not overflow
+        assert!(policy.backoff(u32::MAX) <= Duration::from_millis(RETRY_MAX_DELAY_MS));
+    }
+
+    #[test]
+    fn test_retry_policy_requires_one_attempt() {
+        assert_eq!(RetryPolicy::new(0, 100).max_attempts, 1);
+    }
+
+    #[test]
+    fn test_split_into_requests_record_count_limit() {
+        let chunks = split_into_requests(vec![1usize; 1201], 500, usize::MAX, |size| *size);
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![500, 500, 201]);
+    }
+
+    #[test]
+    fn test_split_into_requests_payload_size_limit() {
+        let chunks = split_into_requests(vec![400usize; 6], 500, 1000, |size| *size);
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 2]);
+    }
+
+    #[test]
+    fn test_split_into_requests_empty() {
+        assert!(split_into_requests(Vec::<usize>::new(), 500, 1000, |size| *size).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_resubmits_rejected_entries() {
+        let mut calls: Vec<Vec<&str>> = Vec::new();
+        let outcome = send_with_retry(vec!["a", "b", "c"], &RetryPolicy::new(3, 1000), |pending| {
+            calls.push(pending.to_vec());
+            let failures = pending
+                .iter()
+                .map(|entry| (calls.len() == 1 && *entry != "a").then(|| "Throttled".to_string()))
+                .collect();
+            async move { Ok(failures) }
+        })
+        .await;
+
+        assert_eq!(calls, vec![vec!["a", "b", "c"], vec!["b", "c"]]);
+        assert_eq!(outcome.delivered, 3);
+        assert!(outcome.given_up.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_gives_up_after_max_attempts() {
+        let mut calls = 0;
+        let outcome = send_with_retry(vec!["a", "b"], &RetryPolicy::new(2, 1000), |pending| {
+            calls += 1;
+            let failures = vec![Some("InternalFailure".to_string()); pending.len()];
+            async move { Ok(failures) }
+        })
+        .await;
+
+        assert_eq!(calls, 2);
+        assert_eq!(outcome.delivered, 0);
+        assert_eq!(outcome.given_up, vec!["a", "b"]);
+        assert!(outcome.unsent.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_keeps_records_of_failed_request() {
+        let outcome = send_with_retry(vec!["a", "b"], &RetryPolicy::new(3, 1000), |_| async {
+            Err("connection reset".to_string())
+        })
+        .await;
+
+        assert_eq!(outcome.unsent, vec!["a", "b"]);
+        assert_eq!(outcome.error.as_deref(), Some("connection reset"));
+    }
+
+    #[test]
+    fn test_flush_summary_accounts_per_chunk() {
+        let mut summary = FlushSummary::default();
+        summary.absorb(Ok((
+            0,
+            ChunkOutcome {
+                delivered: 500,
+                given_up: Vec::new(),
+                unsent: Vec::new(),
+                error: None,
+            },
+        )));
+        summary.absorb(Ok((
+            1,
+            ChunkOutcome {
+                delivered: 498,
+                given_up: vec!["v", "w"],
+                unsent: Vec::new(),
+                error: None,
+            },
+        )));
+        summary.absorb(Ok((
+            2,
+            ChunkOutcome {
+                delivered: 0,
+                given_up: Vec::new(),
+                unsent: vec!["x", "y", "z"],
+                error: Some("throttled".to_string()),
+            },
+        )));
+
+        assert_eq!(summary.delivered, 998);
+        assert_eq!(summary.given_up, vec!["v", "w"]);
+        assert_eq!(summary.failed_chunks, 1);
+        assert_eq!(summary.unsent, vec!["x", "y", "z"]);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_chunks_collects_all_outcomes() {
+        let chunks = vec![vec![1, 2], vec![3], vec![4, 5, 6]];
+        let summary = deliver_chunks(chunks, 2, |chunk| async move {
+            ChunkOutcome {
+                delivered: chunk.len(),
+                given_up: Vec::new(),
+                unsent: Vec::new(),
+                error: None,
+            }
+        })
+        .await;
+
+        assert_eq!(summary.delivered, 6);
+        assert_eq!(summary.failed_chunks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_chunks_counts_panicked_task_as_failed() {
+        let chunks = vec![vec![1, 2], vec![3], vec![4, 5, 6]];
+        let summary = deliver_chunks(chunks, 2, |chunk| async move {
+            if chunk == vec![3] {
+                panic!("request task panicked");
+            }
+            ChunkOutcome {
+                delivered: chunk.len(),
+                given_up: Vec::new(),
+                unsent: Vec::new(),
+                error: None,
+            }
+        })
+        .await;
+
+        assert_eq!(summary.delivered, 5);
+        assert_eq!(summary.failed_chunks, 1);
+        assert!(summary.unsent.is_empty());
+    }
+}
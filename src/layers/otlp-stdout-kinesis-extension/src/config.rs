@@ -1,9 +1,36 @@
+use crate::aggregation::AttributeNaming;
+use crate::init_phase::InitSpanMode;
+use crate::logs::LogSource;
+use crate::otlp_http;
+use crate::partition_key::PartitionKeyStrategy;
+use crate::sink::SinkKind;
+use crate::trace_context::TraceContextPrecedence;
 use lambda_extension::{Error, tracing};
 use std::env;
+use std::path::PathBuf;
 
 // Environment variable name for Kinesis stream
 pub const ENV_VAR_STREAM_NAME: &str = "OTEL_LITE_EXTENSION_STREAM_NAME";
 
+// Environment variable name for Firehose delivery stream
+pub const ENV_VAR_DELIVERY_STREAM_NAME: &str = "OTEL_LITE_EXTENSION_DELIVERY_STREAM_NAME";
+
+// Environment variable names for the OTLP/HTTP sink
+pub const ENV_VAR_OTLP_ENDPOINT: &str = "OTEL_LITE_EXTENSION_OTLP_ENDPOINT";
+pub const ENV_VAR_OTLP_HEADERS: &str = "OTEL_LITE_EXTENSION_OTLP_HEADERS";
+pub const ENV_VAR_OTLP_COMPRESSION: &str = "OTEL_LITE_EXTENSION_OTLP_COMPRESSION";
+pub const ENV_VAR_OTLP_TIMEOUT_MS: &str = "OTEL_LITE_EXTENSION_OTLP_TIMEOUT_MS";
+
+// Default upper bound for a single OTLP/HTTP export, further limited by the invocation deadline
+pub const DEFAULT_OTLP_TIMEOUT_MS: u64 = 5000;
+
+// Environment variable names for output sink selection
+pub const ENV_VAR_SINK: &str = "OTEL_LITE_EXTENSION_SINK";
+pub const ENV_VAR_FILE_SINK_PATH: &str = "OTEL_LITE_EXTENSION_FILE_SINK_PATH";
+
+// Default file for the file sink (only /tmp is writable in Lambda)
+pub const DEFAULT_FILE_SINK_PATH: &str = "/tmp/otlp-stdout-kinesis-extension.jsonl";
+
 // Default buffering values
 pub const DEFAULT_BUFFER_TIMEOUT_MS: u32 = 100;
 pub const DEFAULT_BUFFER_MAX_BYTES: usize = 256 * 1024; // 256KB
@@ -17,31 +44,222 @@ pub const ENV_VAR_BUFFER_MAX_ITEMS: &str = "OTEL_LITE_EXTENSION_BUFFER_MAX_ITEMS
 // Environment variable name for enabling platform telemetry
 pub const ENV_VAR_ENABLE_PLATFORM_TELEMETRY: &str = "OTEL_LITE_EXTENSION_ENABLE_PLATFORM_TELEMETRY";
 
+// Default bounds on platform telemetry state kept between invocations
+pub const DEFAULT_TELEMETRY_CHANNEL_CAPACITY: usize = 2048;
+pub const DEFAULT_MAX_AGGREGATIONS: usize = 1000;
+pub const DEFAULT_MAX_TRACE_MAP_ENTRIES: usize = 1000;
+
+// Environment variable names for platform telemetry bounds
+pub const ENV_VAR_TELEMETRY_CHANNEL_CAPACITY: &str =
+    "OTEL_LITE_EXTENSION_TELEMETRY_CHANNEL_CAPACITY";
+pub const ENV_VAR_MAX_AGGREGATIONS: &str = "OTEL_LITE_EXTENSION_MAX_AGGREGATIONS";
+pub const ENV_VAR_MAX_TRACE_MAP_ENTRIES: &str = "OTEL_LITE_EXTENSION_MAX_TRACE_MAP_ENTRIES";
+
+// Environment variable overriding the function timeout in seconds. Lambda doesn't expose the
+// timeout to extensions, so without it the timeout is taken from the first INVOKE's deadline.
+pub const ENV_VAR_FUNCTION_TIMEOUT_SECS: &str = "OTEL_LITE_EXTENSION_FUNCTION_TIMEOUT_SECS";
+
+// Defaults used when the function timeout is unknown
+pub const DEFAULT_AGGREGATION_TIMEOUT_SECS: u64 = 1800;
+pub const DEFAULT_TRACE_MAP_TTL_SECS: u64 = 300;
+
+// Slack added to the function timeout for late telemetry (buffering, report delivery)
+pub const FUNCTION_TIMEOUT_MARGIN_SECS: u64 = 60;
+
+// Environment variable names for aggregation lifetime config
+pub const ENV_VAR_AGGREGATION_TIMEOUT_SECS: &str = "OTEL_LITE_EXTENSION_AGGREGATION_TIMEOUT_SECS";
+pub const ENV_VAR_TRACE_MAP_TTL_SECS: &str = "OTEL_LITE_EXTENSION_TRACE_MAP_TTL_SECS";
+
+// Environment variable name for the Telemetry API log types to capture as OTLP log records
+// (comma-separated: function, extension). None are captured by default.
+pub const ENV_VAR_LOG_TYPES: &str = "OTEL_LITE_EXTENSION_LOG_TYPES";
+
+// Environment variable name for placing init spans in the first invocation's trace or their own
+pub const ENV_VAR_INIT_SPAN_MODE: &str = "OTEL_LITE_EXTENSION_INIT_SPAN_MODE";
+
+// Environment variable name for the trace context preferred for platform spans when both the
+// function's entry span and the X-Ray header are known (pipe or xray)
+pub const ENV_VAR_TRACE_CONTEXT_PRECEDENCE: &str = "OTEL_LITE_EXTENSION_TRACE_CONTEXT_PRECEDENCE";
+
+// Environment variable name for generating a trace rooted at Lambda/Invoke for invocations
+// with no trace context, e.g. functions that emit no spans of their own
+pub const ENV_VAR_GENERATE_TRACES: &str = "OTEL_LITE_EXTENSION_GENERATE_TRACES";
+
+// Environment variable name for the attribute names of synthesized spans and log records
+// (semconv or legacy)
+pub const ENV_VAR_ATTRIBUTE_NAMING: &str = "OTEL_LITE_EXTENSION_ATTRIBUTE_NAMING";
+
+// Default cadence of the OTLP metrics built from platform.report
+pub const DEFAULT_METRICS_INTERVAL_SECS: u64 = 60;
+
+// Environment variable names for the OTLP metrics built from platform.report
+pub const ENV_VAR_ENABLE_METRICS: &str = "OTEL_LITE_EXTENSION_ENABLE_METRICS";
+pub const ENV_VAR_METRICS_INTERVAL_SECS: &str = "OTEL_LITE_EXTENSION_METRICS_INTERVAL_SECS";
+
+// Default retry values for records rejected by PutRecords
+pub const DEFAULT_KINESIS_MAX_ATTEMPTS: u32 = 4;
+pub const DEFAULT_KINESIS_RETRY_BUDGET_MS: u64 = 1000;
+
+// Default number of PutRecords requests sent concurrently
+pub const DEFAULT_KINESIS_MAX_CONCURRENCY: usize = 4;
+
+// Environment variable names for Kinesis retry config
+pub const ENV_VAR_KINESIS_MAX_ATTEMPTS: &str = "OTEL_LITE_EXTENSION_KINESIS_MAX_ATTEMPTS";
+pub const ENV_VAR_KINESIS_RETRY_BUDGET_MS: &str = "OTEL_LITE_EXTENSION_KINESIS_RETRY_BUDGET_MS";
+pub const ENV_VAR_KINESIS_MAX_CONCURRENCY: &str = "OTEL_LITE_EXTENSION_KINESIS_MAX_CONCURRENCY";
+
+// Default retry values for records rejected by PutRecordBatch
+pub const DEFAULT_FIREHOSE_MAX_ATTEMPTS: u32 = 4;
+pub const DEFAULT_FIREHOSE_RETRY_BUDGET_MS: u64 = 1000;
+
+// Default number of PutRecordBatch requests sent concurrently
+pub const DEFAULT_FIREHOSE_MAX_CONCURRENCY: usize = 4;
+
+// Environment variable names for Firehose retry config
+pub const ENV_VAR_FIREHOSE_MAX_ATTEMPTS: &str = "OTEL_LITE_EXTENSION_FIREHOSE_MAX_ATTEMPTS";
+pub const ENV_VAR_FIREHOSE_RETRY_BUDGET_MS: &str = "OTEL_LITE_EXTENSION_FIREHOSE_RETRY_BUDGET_MS";
+pub const ENV_VAR_FIREHOSE_MAX_CONCURRENCY: &str = "OTEL_LITE_EXTENSION_FIREHOSE_MAX_CONCURRENCY";
+
+// Default target size of an aggregated Kinesis record
+pub const DEFAULT_RECORD_AGGREGATION_TARGET_BYTES: usize = 64 * 1024; // 64KB
+
+// Environment variable names for record aggregation config
+pub const ENV_VAR_ENABLE_RECORD_AGGREGATION: &str = "OTEL_LITE_EXTENSION_ENABLE_RECORD_AGGREGATION";
+pub const ENV_VAR_RECORD_AGGREGATION_TARGET_BYTES: &str =
+    "OTEL_LITE_EXTENSION_RECORD_AGGREGATION_TARGET_BYTES";
+
+// Default location and bounds of the spool for undelivered Kinesis records
+pub const DEFAULT_SPOOL_PATH: &str = "/tmp/otlp-stdout-kinesis-extension.spool";
+pub const DEFAULT_SPOOL_MAX_BYTES: u64 = 16 * 1_048_576; // 16MB
+pub const DEFAULT_SPOOL_MAX_AGE_SECS: u64 = 3600;
+
+// Environment variable names for spool config
+pub const ENV_VAR_ENABLE_SPOOL: &str = "OTEL_LITE_EXTENSION_ENABLE_SPOOL";
+pub const ENV_VAR_SPOOL_PATH: &str = "OTEL_LITE_EXTENSION_SPOOL_PATH";
+pub const ENV_VAR_SPOOL_MAX_BYTES: &str = "OTEL_LITE_EXTENSION_SPOOL_MAX_BYTES";
+pub const ENV_VAR_SPOOL_MAX_AGE_SECS: &str = "OTEL_LITE_EXTENSION_SPOOL_MAX_AGE_SECS";
+
+// Default number of partition key buckets for low-cardinality strategies
+pub const DEFAULT_PARTITION_KEY_SPREAD: usize = 4;
+
+// Environment variable names for partition key config
+pub const ENV_VAR_PARTITION_KEY: &str = "OTEL_LITE_EXTENSION_PARTITION_KEY";
+pub const ENV_VAR_PARTITION_KEY_SPREAD: &str = "OTEL_LITE_EXTENSION_PARTITION_KEY_SPREAD";
+
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub sink: SinkKind,
+    pub file_sink_path: PathBuf,
     pub kinesis_stream_name: Option<String>,
+    pub firehose_delivery_stream_name: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub otlp_headers: Vec<(String, String)>,
+    pub otlp_gzip: bool,
+    pub otlp_timeout_ms: u64,
     pub buffer_timeout_ms: u32,
     pub buffer_max_bytes: usize,
     pub buffer_max_items: usize,
     pub enable_platform_telemetry: bool,
+    pub log_types: Vec<LogSource>,
+    pub telemetry_channel_capacity: usize,
+    pub max_aggregations: usize,
+    pub max_trace_map_entries: usize,
+    pub lifetimes: Lifetimes,
+    pub init_span_mode: InitSpanMode,
+    pub trace_context_precedence: TraceContextPrecedence,
+    pub generate_traces: bool,
+    pub attribute_naming: AttributeNaming,
+    pub enable_metrics: bool,
+    pub metrics_interval_secs: u64,
+    pub kinesis_max_attempts: u32,
+    pub kinesis_retry_budget_ms: u64,
+    pub kinesis_max_concurrency: usize,
+    pub firehose_max_attempts: u32,
+    pub firehose_retry_budget_ms: u64,
+    pub firehose_max_concurrency: usize,
+    pub enable_record_aggregation: bool,
+    pub record_aggregation_target_bytes: usize,
+    pub partition_key_strategy: PartitionKeyStrategy,
+    pub partition_key_spread: usize,
+    pub enable_spool: bool,
+    pub spool_path: PathBuf,
+    pub spool_max_bytes: u64,
+    pub spool_max_age_secs: u64,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, Error> {
         let kinesis_stream_name = env::var(ENV_VAR_STREAM_NAME).ok();
+        let firehose_delivery_stream_name = env::var(ENV_VAR_DELIVERY_STREAM_NAME).ok();
+        let otlp_endpoint = env::var(ENV_VAR_OTLP_ENDPOINT).ok();
 
-        if kinesis_stream_name.is_none() {
-            tracing::info!(
-                "extension: {} not set, disabling Kinesis output. Will write records to stdout.",
-                ENV_VAR_STREAM_NAME
-            );
-        } else {
-            tracing::info!(
-                "extension: Kinesis stream name set: {}",
-                kinesis_stream_name.as_ref().unwrap()
-            );
+        // Without an explicit sink, keep the historical behaviour: Kinesis if a stream is set
+        let sink = match env::var(ENV_VAR_SINK) {
+            Ok(v) => v.parse::<SinkKind>().map_err(Error::from)?,
+            Err(_) if kinesis_stream_name.is_some() => SinkKind::Kinesis,
+            Err(_) if firehose_delivery_stream_name.is_some() => SinkKind::Firehose,
+            Err(_) => SinkKind::Stdout,
+        };
+
+        match sink {
+            SinkKind::Kinesis => {
+                let Some(stream_name) = &kinesis_stream_name else {
+                    return Err(Error::from(format!(
+                        "{} is kinesis but {} is not set",
+                        ENV_VAR_SINK, ENV_VAR_STREAM_NAME
+                    )));
+                };
+                tracing::info!("extension: Kinesis stream name set: {}", stream_name);
+            }
+            SinkKind::Firehose => {
+                let Some(delivery_stream_name) = &firehose_delivery_stream_name else {
+                    return Err(Error::from(format!(
+                        "{} is firehose but {} is not set",
+                        ENV_VAR_SINK, ENV_VAR_DELIVERY_STREAM_NAME
+                    )));
+                };
+                tracing::info!(
+                    "extension: Firehose delivery stream name set: {}",
+                    delivery_stream_name
+                );
+            }
+            SinkKind::OtlpHttp => {
+                let Some(endpoint) = &otlp_endpoint else {
+                    return Err(Error::from(format!(
+                        "{} is otlp-http but {} is not set",
+                        ENV_VAR_SINK, ENV_VAR_OTLP_ENDPOINT
+                    )));
+                };
+                tracing::info!("extension: OTLP/HTTP endpoint set: {}", endpoint);
+            }
+            SinkKind::Stdout if kinesis_stream_name.is_none() => {
+                tracing::info!(
+                    "extension: {} not set, disabling Kinesis output. Will write records to stdout.",
+                    ENV_VAR_STREAM_NAME
+                );
+            }
+            other => {
+                tracing::info!("extension: using {:?} sink", other);
+            }
         }
 
+        let file_sink_path = env::var(ENV_VAR_FILE_SINK_PATH)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_FILE_SINK_PATH));
+
+        let otlp_headers = env::var(ENV_VAR_OTLP_HEADERS)
+            .map(|v| otlp_http::parse_headers(&v))
+            .unwrap_or_default();
+
+        // Payloads are gzip-compressed unless explicitly disabled
+        let otlp_gzip = env::var(ENV_VAR_OTLP_COMPRESSION)
+            .map(|v| v.to_lowercase() != "none")
+            .unwrap_or(true);
+
+        let otlp_timeout_ms = env::var(ENV_VAR_OTLP_TIMEOUT_MS)
+            .map(|v| v.parse::<u64>().unwrap_or(DEFAULT_OTLP_TIMEOUT_MS))
+            .unwrap_or(DEFAULT_OTLP_TIMEOUT_MS);
+
         let buffer_timeout_ms = env::var(ENV_VAR_BUFFER_TIMEOUT_MS)
             .map(|v| v.parse::<u32>().unwrap_or(DEFAULT_BUFFER_TIMEOUT_MS))
             .unwrap_or(DEFAULT_BUFFER_TIMEOUT_MS);
@@ -58,20 +276,410 @@ impl Config {
             .map(|v| v.to_lowercase() == "true")
             .unwrap_or(false);
 
+        let log_types = env::var(ENV_VAR_LOG_TYPES)
+            .map(|v| parse_log_types(&v))
+            .unwrap_or_default();
+
+        // Zero would disable the bounds entirely (or panic, for the channel), so clamp to 1
+        let telemetry_channel_capacity = env::var(ENV_VAR_TELEMETRY_CHANNEL_CAPACITY)
+            .map(|v| {
+                v.parse::<usize>()
+                    .unwrap_or(DEFAULT_TELEMETRY_CHANNEL_CAPACITY)
+            })
+            .unwrap_or(DEFAULT_TELEMETRY_CHANNEL_CAPACITY)
+            .max(1);
+
+        let max_aggregations = env::var(ENV_VAR_MAX_AGGREGATIONS)
+            .map(|v| v.parse::<usize>().unwrap_or(DEFAULT_MAX_AGGREGATIONS))
+            .unwrap_or(DEFAULT_MAX_AGGREGATIONS)
+            .max(1);
+
+        let max_trace_map_entries = env::var(ENV_VAR_MAX_TRACE_MAP_ENTRIES)
+            .map(|v| v.parse::<usize>().unwrap_or(DEFAULT_MAX_TRACE_MAP_ENTRIES))
+            .unwrap_or(DEFAULT_MAX_TRACE_MAP_ENTRIES)
+            .max(1);
+
+        let lifetimes = Lifetimes {
+            function_timeout_secs: optional_secs_from_env(ENV_VAR_FUNCTION_TIMEOUT_SECS),
+            aggregation_timeout_secs: optional_secs_from_env(ENV_VAR_AGGREGATION_TIMEOUT_SECS),
+            trace_map_ttl_secs: optional_secs_from_env(ENV_VAR_TRACE_MAP_TTL_SECS),
+        };
+
+        let init_span_mode = env::var(ENV_VAR_INIT_SPAN_MODE)
+            .map(|v| {
+                v.parse::<InitSpanMode>().unwrap_or_else(|e| {
+                    tracing::warn!("{}, using auto", e);
+                    InitSpanMode::default()
+                })
+            })
+            .unwrap_or_default();
+
+        let trace_context_precedence = env::var(ENV_VAR_TRACE_CONTEXT_PRECEDENCE)
+            .map(|v| {
+                v.parse::<TraceContextPrecedence>().unwrap_or_else(|e| {
+                    tracing::warn!("{}, using pipe", e);
+                    TraceContextPrecedence::default()
+                })
+            })
+            .unwrap_or_default();
+
+        let generate_traces = env::var(ENV_VAR_GENERATE_TRACES)
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+        if generate_traces && !enable_platform_telemetry {
+            tracing::warn!(
+                "{} requires {}=true, no traces will be generated",
+                ENV_VAR_GENERATE_TRACES,
+                ENV_VAR_ENABLE_PLATFORM_TELEMETRY
+            );
+        }
+
+        let attribute_naming = env::var(ENV_VAR_ATTRIBUTE_NAMING)
+            .map(|v| {
+                v.parse::<AttributeNaming>().unwrap_or_else(|e| {
+                    tracing::warn!("{}, using semconv", e);
+                    AttributeNaming::default()
+                })
+            })
+            .unwrap_or_default();
+
+        let enable_metrics = env::var(ENV_VAR_ENABLE_METRICS)
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+        if enable_metrics && !enable_platform_telemetry {
+            tracing::warn!(
+                "{} requires {}=true, no metrics will be recorded",
+                ENV_VAR_ENABLE_METRICS,
+                ENV_VAR_ENABLE_PLATFORM_TELEMETRY
+            );
+        }
+
+        let metrics_interval_secs =
+            positive_secs_from_env(ENV_VAR_METRICS_INTERVAL_SECS, DEFAULT_METRICS_INTERVAL_SECS);
+
+        // At least one attempt is always made, otherwise nothing would ever be sent
+        let kinesis_max_attempts = env::var(ENV_VAR_KINESIS_MAX_ATTEMPTS)
+            .map(|v| v.parse::<u32>().unwrap_or(DEFAULT_KINESIS_MAX_ATTEMPTS))
+            .unwrap_or(DEFAULT_KINESIS_MAX_ATTEMPTS)
+            .max(1);
+
+        let kinesis_retry_budget_ms = env::var(ENV_VAR_KINESIS_RETRY_BUDGET_MS)
+            .map(|v| v.parse::<u64>().unwrap_or(DEFAULT_KINESIS_RETRY_BUDGET_MS))
+            .unwrap_or(DEFAULT_KINESIS_RETRY_BUDGET_MS);
+
+        let kinesis_max_concurrency = env::var(ENV_VAR_KINESIS_MAX_CONCURRENCY)
+            .map(|v| {
+                v.parse::<usize>()
+                    .unwrap_or(DEFAULT_KINESIS_MAX_CONCURRENCY)
+            })
+            .unwrap_or(DEFAULT_KINESIS_MAX_CONCURRENCY)
+            .max(1);
+
+        let firehose_max_attempts = env::var(ENV_VAR_FIREHOSE_MAX_ATTEMPTS)
+            .map(|v| v.parse::<u32>().unwrap_or(DEFAULT_FIREHOSE_MAX_ATTEMPTS))
+            .unwrap_or(DEFAULT_FIREHOSE_MAX_ATTEMPTS)
+            .max(1);
+
+        let firehose_retry_budget_ms = env::var(ENV_VAR_FIREHOSE_RETRY_BUDGET_MS)
+            .map(|v| v.parse::<u64>().unwrap_or(DEFAULT_FIREHOSE_RETRY_BUDGET_MS))
+            .unwrap_or(DEFAULT_FIREHOSE_RETRY_BUDGET_MS);
+
+        let firehose_max_concurrency = env::var(ENV_VAR_FIREHOSE_MAX_CONCURRENCY)
+            .map(|v| {
+                v.parse::<usize>()
+                    .unwrap_or(DEFAULT_FIREHOSE_MAX_CONCURRENCY)
+            })
+            .unwrap_or(DEFAULT_FIREHOSE_MAX_CONCURRENCY)
+            .max(1);
+
+        let enable_record_aggregation = env::var(ENV_VAR_ENABLE_RECORD_AGGREGATION)
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let record_aggregation_target_bytes = env::var(ENV_VAR_RECORD_AGGREGATION_TARGET_BYTES)
+            .map(|v| {
+                v.parse::<usize>()
+                    .unwrap_or(DEFAULT_RECORD_AGGREGATION_TARGET_BYTES)
+            })
+            .unwrap_or(DEFAULT_RECORD_AGGREGATION_TARGET_BYTES);
+
+        let partition_key_strategy = env::var(ENV_VAR_PARTITION_KEY)
+            .map(|v| {
+                v.parse::<PartitionKeyStrategy>().unwrap_or_else(|e| {
+                    tracing::warn!("{}, using random partition keys", e);
+                    PartitionKeyStrategy::default()
+                })
+            })
+            .unwrap_or_default();
+
+        let partition_key_spread = env::var(ENV_VAR_PARTITION_KEY_SPREAD)
+            .map(|v| v.parse::<usize>().unwrap_or(DEFAULT_PARTITION_KEY_SPREAD))
+            .unwrap_or(DEFAULT_PARTITION_KEY_SPREAD)
+            .max(1);
+
+        let enable_spool = env::var(ENV_VAR_ENABLE_SPOOL)
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let spool_path = env::var(ENV_VAR_SPOOL_PATH)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_SPOOL_PATH));
+
+        let spool_max_bytes = env::var(ENV_VAR_SPOOL_MAX_BYTES)
+            .map(|v| v.parse::<u64>().unwrap_or(DEFAULT_SPOOL_MAX_BYTES))
+            .unwrap_or(DEFAULT_SPOOL_MAX_BYTES);
+
+        let spool_max_age_secs = env::var(ENV_VAR_SPOOL_MAX_AGE_SECS)
+            .map(|v| v.parse::<u64>().unwrap_or(DEFAULT_SPOOL_MAX_AGE_SECS))
+            .unwrap_or(DEFAULT_SPOOL_MAX_AGE_SECS);
+
         tracing::debug!(
-            "Configuration: buffer_timeout_ms={}, buffer_max_bytes={}, buffer_max_items={}, enable_platform_telemetry={}",
+            "Configuration: otlp_gzip={}, otlp_timeout_ms={}, buffer_timeout_ms={}, buffer_max_bytes={}, buffer_max_items={}, enable_platform_telemetry={}, log_types={:?}, telemetry_channel_capacity={}, max_aggregations={}, max_trace_map_entries={}, lifetimes={:?}, init_span_mode={:?}, trace_context_precedence={:?}, generate_traces={}, attribute_naming={:?}, enable_metrics={}, metrics_interval_secs={}, kinesis_max_attempts={}, kinesis_retry_budget_ms={}, kinesis_max_concurrency={}, firehose_max_attempts={}, firehose_retry_budget_ms={}, firehose_max_concurrency={}, enable_record_aggregation={}, record_aggregation_target_bytes={}, partition_key_strategy={:?}, partition_key_spread={}, enable_spool={}, spool_max_bytes={}, spool_max_age_secs={}",
+            otlp_gzip,
+            otlp_timeout_ms,
             buffer_timeout_ms,
             buffer_max_bytes,
             buffer_max_items,
-            enable_platform_telemetry
+            enable_platform_telemetry,
+            log_types,
+            telemetry_channel_capacity,
+            max_aggregations,
+            max_trace_map_entries,
+            lifetimes,
+            init_span_mode,
+            trace_context_precedence,
+            generate_traces,
+            attribute_naming,
+            enable_metrics,
+            metrics_interval_secs,
+            kinesis_max_attempts,
+            kinesis_retry_budget_ms,
+            kinesis_max_concurrency,
+            firehose_max_attempts,
+            firehose_retry_budget_ms,
+            firehose_max_concurrency,
+            enable_record_aggregation,
+            record_aggregation_target_bytes,
+            partition_key_strategy,
+            partition_key_spread,
+            enable_spool,
+            spool_max_bytes,
+            spool_max_age_secs
         );
 
         Ok(Self {
+            sink,
+            file_sink_path,
             kinesis_stream_name,
+            firehose_delivery_stream_name,
+            otlp_endpoint,
+            otlp_headers,
+            otlp_gzip,
+            otlp_timeout_ms,
             buffer_timeout_ms,
             buffer_max_bytes,
             buffer_max_items,
             enable_platform_telemetry,
+            log_types,
+            telemetry_channel_capacity,
+            max_aggregations,
+            max_trace_map_entries,
+            lifetimes,
+            init_span_mode,
+            trace_context_precedence,
+            generate_traces,
+            attribute_naming,
+            enable_metrics,
+            metrics_interval_secs,
+            kinesis_max_attempts,
+            kinesis_retry_budget_ms,
+            kinesis_max_concurrency,
+            firehose_max_attempts,
+            firehose_retry_budget_ms,
+            firehose_max_concurrency,
+            enable_record_aggregation,
+            record_aggregation_target_bytes,
+            partition_key_strategy,
+            partition_key_spread,
+            enable_spool,
+            spool_path,
+            spool_max_bytes,
+            spool_max_age_secs,
         })
     }
 }
+
+/// Lifetime overrides for aggregations and trace map entries, in seconds.
+///
+/// Each is `None` unless set through its environment variable. Unset lifetimes follow the
+/// function timeout, which is the configured override or the one observed on the first INVOKE.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lifetimes {
+    pub function_timeout_secs: Option<u64>,
+    pub aggregation_timeout_secs: Option<u64>,
+    pub trace_map_ttl_secs: Option<u64>,
+}
+
+impl Lifetimes {
+    /// Aggregation timeout and trace map TTL, in seconds, given the function timeout observed
+    /// on the first INVOKE (if one was seen yet).
+    pub fn resolve(&self, observed_function_timeout_secs: Option<u64>) -> (u64, u64) {
+        let (aggregation_timeout_secs, trace_map_ttl_secs) = derived_lifetimes_secs(
+            self.function_timeout_secs
+                .or(observed_function_timeout_secs),
+        );
+        (
+            self.aggregation_timeout_secs
+                .unwrap_or(aggregation_timeout_secs),
+            self.trace_map_ttl_secs.unwrap_or(trace_map_ttl_secs),
+        )
+    }
+}
+
+/// The function timeout in seconds, derived from the deadline of the first INVOKE.
+///
+/// The first INVOKE is received as the invocation starts, so the time left until its
+/// deadline is the full timeout minus a few milliseconds; rounding up absorbs them.
+pub fn function_timeout_from_deadline(deadline_ms: u64, now_ms: u64) -> Option<u64> {
+    deadline_ms
+        .checked_sub(now_ms)
+        .filter(|remaining_ms| *remaining_ms > 0)
+        .map(|remaining_ms| remaining_ms.div_ceil(1000))
+}
+
+/// Default aggregation timeout and trace map TTL, in seconds.
+///
+/// Both only need to outlive an invocation plus the delivery of its platform events, so
+/// with a known function timeout they follow it instead of the fixed defaults.
+fn derived_lifetimes_secs(function_timeout_secs: Option<u64>) -> (u64, u64) {
+    match function_timeout_secs {
+        Some(timeout) => {
+            let lifetime = timeout.saturating_add(FUNCTION_TIMEOUT_MARGIN_SECS);
+            (lifetime, lifetime)
+        }
+        None => (DEFAULT_AGGREGATION_TIMEOUT_SECS, DEFAULT_TRACE_MAP_TTL_SECS),
+    }
+}
+
+/// Parses a comma-separated list of log types, skipping unknown ones and duplicates.
+fn parse_log_types(value: &str) -> Vec<LogSource> {
+    let mut log_types = Vec::new();
+    for name in value.split(',').filter(|name| !name.trim().is_empty()) {
+        match name.parse::<LogSource>() {
+            Ok(log_type) if !log_types.contains(&log_type) => log_types.push(log_type),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("{}, ignoring it", e),
+        }
+    }
+    log_types
+}
+
+/// Reads an optional duration in seconds; invalid or zero values are ignored with a warning.
+fn optional_secs_from_env(name: &str) -> Option<u64> {
+    let value = env::var(name).ok()?;
+    match value.parse::<u64>() {
+        Ok(secs) if secs > 0 => Some(secs),
+        _ => {
+            tracing::warn!(
+                "Invalid {} '{}', expected a positive number of seconds. Ignoring it",
+                name,
+                value
+            );
+            None
+        }
+    }
+}
+
+/// Reads a duration in seconds, falling back to `default` when unset, invalid or zero.
+fn positive_secs_from_env(name: &str, default: u64) -> u64 {
+    let Ok(value) = env::var(name) else {
+        return default;
+    };
+    match value.parse::<u64>() {
+        Ok(secs) if secs > 0 => secs,
+        _ => {
+            tracing::warn!(
+                "Invalid {} '{}', expected a positive number of seconds. Using {}",
+                name,
+                value,
+                default
+            );
+            default
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_lifetimes_follow_function_timeout() {
+        assert_eq!(
+            derived_lifetimes_secs(None),
+            (DEFAULT_AGGREGATION_TIMEOUT_SECS, DEFAULT_TRACE_MAP_TTL_SECS)
+        );
+        // A 15 minute function keeps its trace context past the 5 minute default
+        assert_eq!(derived_lifetimes_secs(Some(900)), (960, 960));
+        // A short API doesn't keep stale aggregations around for half an hour
+        assert_eq!(derived_lifetimes_secs(Some(3)), (63, 63));
+    }
+
+    #[test]
+    fn test_function_timeout_from_deadline() {
+        let now_ms = 1_700_000_000_000;
+        // The first INVOKE arrives a few milliseconds into a 15 minute invocation
+        assert_eq!(
+            function_timeout_from_deadline(now_ms + 899_995, now_ms),
+            Some(900)
+        );
+        assert_eq!(
+            function_timeout_from_deadline(now_ms + 3_000, now_ms),
+            Some(3)
+        );
+        // A deadline that already passed says nothing about the timeout
+        assert_eq!(function_timeout_from_deadline(now_ms, now_ms), None);
+        assert_eq!(function_timeout_from_deadline(now_ms - 1, now_ms), None);
+    }
+
+    #[test]
+    fn test_lifetimes_resolve_from_observed_timeout_unless_overridden() {
+        let now_ms = 1_700_000_000_000;
+        let observed = function_timeout_from_deadline(now_ms + 899_995, now_ms);
+
+        let derived = Lifetimes::default();
+        assert_eq!(
+            derived.resolve(None),
+            (DEFAULT_AGGREGATION_TIMEOUT_SECS, DEFAULT_TRACE_MAP_TTL_SECS)
+        );
+        assert_eq!(derived.resolve(observed), (960, 960));
+
+        // A configured function timeout wins over the observed one
+        let configured = Lifetimes {
+            function_timeout_secs: Some(3),
+            ..Lifetimes::default()
+        };
+        assert_eq!(configured.resolve(observed), (63, 63));
+
+        // Explicit lifetimes win over both
+        let explicit = Lifetimes {
+            function_timeout_secs: Some(3),
+            aggregation_timeout_secs: Some(120),
+            trace_map_ttl_secs: Some(30),
+        };
+        assert_eq!(explicit.resolve(observed), (120, 30));
+    }
+
+    #[test]
+    fn test_parse_log_types() {
+        assert_eq!(
+            parse_log_types("function, Extension,function"),
+            vec![LogSource::Function, LogSource::Extension]
+        );
+        assert_eq!(
+            parse_log_types("platform,function"),
+            vec![LogSource::Function]
+        );
+        assert!(parse_log_types("").is_empty());
+    }
+}
@@ -1,5 +1,6 @@
+use crate::trace_context::ParentContext;
 use chrono::{DateTime, Utc};
-use lambda_extension::{Span as LambdaSpan, Status as LambdaStatus};
+use lambda_extension::{InitPhase, InitType, Span as LambdaSpan, Status as LambdaStatus};
 use opentelemetry::Value as OtelValue;
 use std::collections::HashMap;
 
@@ -7,25 +8,86 @@ use std::collections::HashMap;
 /// We use an enum to clearly distinguish event types and their specific data.
 #[derive(Debug, Clone)]
 pub enum PlatformEventData {
-    InitStart {},
+    // --- Init Phase ---
+    InitStart {
+        initialization_type: InitType,
+        phase: InitPhase,
+        runtime_version: Option<String>,
+        runtime_version_arn: Option<String>,
+    },
+    InitRuntimeDone {
+        initialization_type: InitType,
+        phase: Option<InitPhase>,
+        status: LambdaStatus,
+        error_type: Option<String>,
+    },
+    InitReport {
+        initialization_type: InitType,
+        phase: InitPhase,
+        duration_ms: f64,
+    },
+    // --- Restore Phase (SnapStart) ---
+    RestoreStart {
+        runtime_version: Option<String>,
+        runtime_version_arn: Option<String>,
+    },
+    RestoreRuntimeDone {
+        status: LambdaStatus,
+        error_type: Option<String>,
+    },
+    RestoreReport {
+        status: LambdaStatus,
+        error_type: Option<String>,
+        duration_ms: Option<f64>,
+    },
     // --- Invoke Phase ---
     Start {
         version: Option<String>,
+        // The invocation's X-Ray tracing header, when Lambda reports one
+        trace_context: Option<ParentContext>,
     },
     RuntimeDone {
         status: LambdaStatus,
         error_type: Option<String>,
         metrics: HashMap<String, OtelValue>,
         spans: Vec<TelemetrySpan>,
+        trace_context: Option<ParentContext>,
     },
     Report {
         status: LambdaStatus,
         error_type: Option<String>,
         metrics: HashMap<String, OtelValue>,
         spans: Vec<TelemetrySpan>,
+        trace_context: Option<ParentContext>,
     },
 }
 
+impl PlatformEventData {
+    /// Whether the event belongs to the initialization or restore of the execution
+    /// environment rather than to an invocation.
+    pub fn is_startup_event(&self) -> bool {
+        matches!(
+            self,
+            Self::InitStart { .. }
+                | Self::InitRuntimeDone { .. }
+                | Self::InitReport { .. }
+                | Self::RestoreStart { .. }
+                | Self::RestoreRuntimeDone { .. }
+                | Self::RestoreReport { .. }
+        )
+    }
+
+    /// The X-Ray trace context of the invocation the event belongs to, if reported.
+    pub fn trace_context(&self) -> Option<ParentContext> {
+        match self {
+            Self::Start { trace_context, .. }
+            | Self::RuntimeDone { trace_context, .. }
+            | Self::Report { trace_context, .. } => *trace_context,
+            _ => None,
+        }
+    }
+}
+
 /// Structure to hold parsed platform event data passed through the channel.
 #[derive(Debug, Clone)]
 pub struct ParsedPlatformEvent {
@@ -0,0 +1,285 @@
+use crate::logs;
+use crate::metrics;
+use crate::sink::Sink;
+use lambda_extension::tracing;
+use opentelemetry_proto::tonic::logs::v1::LogRecord;
+use opentelemetry_proto::tonic::metrics::v1::Metric;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+use otlp_stdout_span_exporter::{BufferOutput, OtlpStdoutSpanExporter};
+
+/// Exports synthesized spans with the internal exporter and hands the resulting
+/// otlp-stdout lines to the sink.
+///
+/// The exporter only writes into `buffer`, so every caller must go through here for the
+/// spans to actually leave the extension. `reason` is used in logs to tell the paths apart.
+/// Returns the number of lines written to the sink.
+pub async fn export_to_sink(
+    exporter: &OtlpStdoutSpanExporter,
+    buffer: &BufferOutput,
+    sink: &dyn Sink,
+    spans: Vec<SpanData>,
+    reason: &str,
+) -> usize {
+    if spans.is_empty() {
+        return 0;
+    }
+
+    let span_count = spans.len();
+    tracing::debug!(count = span_count, reason, "Exporting synthesized spans");
+    if let Err(e) = exporter.export(spans).await {
+        tracing::error!(count = span_count, reason, error = ?e, "Failed to export synthesized spans");
+    }
+
+    // Drain the buffer even after an error, so nothing written so far is left behind
+    let lines = match buffer.take_lines() {
+        Ok(lines) => lines,
+        Err(e) => {
+            tracing::error!(
+                "Failed to take lines from internal exporter buffer: {:?}",
+                e
+            );
+            return 0;
+        }
+    };
+
+    let mut written = 0;
+    for line in lines {
+        match sink.write_line(line).await {
+            Ok(()) => written += 1,
+            Err(e) => {
+                tracing::error!(error = %e, sink = sink.name(), reason, "Failed to write synthesized span record to sink")
+            }
+        }
+    }
+    tracing::debug!(lines = written, reason, "Handed synthesized spans to sink");
+    written
+}
+
+/// Encodes captured log records into otlp-stdout lines and hands them to the sink.
+/// Returns the number of lines written to the sink.
+pub async fn export_logs_to_sink(
+    resource: &Resource,
+    sink: &dyn Sink,
+    records: Vec<LogRecord>,
+    reason: &str,
+) -> usize {
+    if records.is_empty() {
+        return 0;
+    }
+
+    let record_count = records.len();
+    let lines = match logs::encode_log_lines(records, resource) {
+        Ok(lines) => lines,
+        Err(e) => {
+            tracing::error!(count = record_count, reason, error = %e, "Failed to encode log records");
+            return 0;
+        }
+    };
+
+    let mut written = 0;
+    for line in lines {
+        match sink.write_line(line).await {
+            Ok(()) => written += 1,
+            Err(e) => {
+                tracing::error!(error = %e, sink = sink.name(), reason, "Failed to write log record to sink")
+            }
+        }
+    }
+    tracing::debug!(
+        count = record_count,
+        lines = written,
+        reason,
+        "Handed log records to sink"
+    );
+    written
+}
+
+/// Encodes collected metrics into an otlp-stdout line and hands it to the sink.
+/// Returns whether the line was written to the sink.
+pub async fn export_metrics_to_sink(
+    resource: &Resource,
+    sink: &dyn Sink,
+    collected: Vec<Metric>,
+    reason: &str,
+) -> bool {
+    if collected.is_empty() {
+        return false;
+    }
+
+    let metric_count = collected.len();
+    let line = match metrics::encode_metrics_line(collected, resource) {
+        Ok(line) => line,
+        Err(e) => {
+            tracing::error!(count = metric_count, reason, error = %e, "Failed to encode metrics");
+            return false;
+        }
+    };
+
+    match sink.write_line(line).await {
+        Ok(()) => {
+            tracing::debug!(count = metric_count, reason, "Handed metrics to sink");
+            true
+        }
+        Err(e) => {
+            tracing::error!(error = %e, sink = sink.name(), reason, "Failed to write metrics record to sink");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregation::{self, SpanAggregator};
+    use crate::events::{ParsedPlatformEvent, PlatformEventData};
+    use crate::otlp_parsing;
+    use crate::sink::MemorySink;
+    use crate::trace_context::{ParentContext, TraceContextPrecedence};
+    use chrono::{TimeZone, Utc};
+    use lambda_extension::Status as LambdaStatus;
+    use opentelemetry::trace::{SpanId, TraceId};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn exporter() -> (OtlpStdoutSpanExporter, Arc<BufferOutput>) {
+        let buffer = Arc::new(BufferOutput::new());
+        let exporter = OtlpStdoutSpanExporter::builder()
+            .output(buffer.clone())
+            .build();
+        (exporter, buffer)
+    }
+
+    /// An aggregator that has seen `platform.start`, and optionally the events completing it.
+    fn aggregator(request_id: &str, complete: bool) -> SpanAggregator {
+        let timestamp = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut agg = SpanAggregator::new(request_id.to_string(), timestamp);
+        agg.set_parent_context(
+            ParentContext::from_entry_span(
+                TraceId::from_hex("0102030405060708090a0b0c0d0e0f10").unwrap(),
+                SpanId::from_hex("0102030405060708").unwrap(),
+            ),
+            TraceContextPrecedence::default(),
+        );
+        let mut events = vec![PlatformEventData::Start {
+            version: None,
+            trace_context: None,
+        }];
+        if complete {
+            events.push(PlatformEventData::RuntimeDone {
+                status: LambdaStatus::Success,
+                error_type: None,
+                metrics: HashMap::new(),
+                spans: Vec::new(),
+                trace_context: None,
+            });
+            events.push(PlatformEventData::Report {
+                status: LambdaStatus::Success,
+                error_type: None,
+                metrics: HashMap::new(),
+                spans: Vec::new(),
+                trace_context: None,
+            });
+        }
+        for data in events {
+            agg.update_from_event(&ParsedPlatformEvent {
+                timestamp,
+                request_id: request_id.to_string(),
+                data,
+            });
+        }
+        agg
+    }
+
+    async fn exported_span_names(sink: &MemorySink) -> Vec<String> {
+        let lines = sink.lines.lock().await;
+        lines
+            .iter()
+            .filter_map(|line| otlp_parsing::decode_trace_request(line).unwrap())
+            .flat_map(|request| request.resource_spans)
+            .flat_map(|rs| rs.scope_spans)
+            .flat_map(|ss| ss.spans)
+            .map(|span| span.name)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_completed_aggregation_reaches_sink() {
+        let (exporter, buffer) = exporter();
+        let sink = MemorySink::default();
+        let mut agg = aggregator("req-complete", true);
+        assert!(agg.is_complete());
+
+        let written = export_to_sink(&exporter, &buffer, &sink, agg.take_spans(), "invoke").await;
+
+        assert_eq!(written, 1);
+        assert_eq!(exported_span_names(&sink).await, vec!["Lambda/Invoke"]);
+        assert!(buffer.take_lines().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_aggregation_reaches_sink() {
+        let (exporter, buffer) = exporter();
+        let sink = MemorySink::default();
+        let mut aggregations = HashMap::new();
+        aggregations.insert("req-stuck".to_string(), aggregator("req-stuck", false));
+
+        let agg_start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let (spans, request_ids) = aggregation::drain_timed_out(
+            &mut aggregations,
+            agg_start + chrono::Duration::minutes(31),
+            chrono::Duration::minutes(30),
+        );
+        export_to_sink(&exporter, &buffer, &sink, spans, "timeout").await;
+
+        assert_eq!(request_ids, vec!["req-stuck"]);
+        assert!(aggregations.is_empty());
+        assert_eq!(exported_span_names(&sink).await, vec!["Lambda/Invoke"]);
+    }
+
+    #[test]
+    fn test_recent_aggregation_is_not_timed_out() {
+        let mut aggregations = HashMap::new();
+        aggregations.insert("req-active".to_string(), aggregator("req-active", false));
+
+        let agg_start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let (spans, request_ids) = aggregation::drain_timed_out(
+            &mut aggregations,
+            agg_start + chrono::Duration::minutes(1),
+            chrono::Duration::minutes(30),
+        );
+
+        assert!(spans.is_empty());
+        assert!(request_ids.is_empty());
+        assert_eq!(aggregations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drain_reaches_sink() {
+        let (exporter, buffer) = exporter();
+        let sink = MemorySink::default();
+        let mut aggregations = HashMap::new();
+        aggregations.insert("req-1".to_string(), aggregator("req-1", false));
+        aggregations.insert("req-2".to_string(), aggregator("req-2", false));
+
+        let spans = aggregation::drain_all(&mut aggregations);
+        export_to_sink(&exporter, &buffer, &sink, spans, "shutdown").await;
+
+        assert!(aggregations.is_empty());
+        assert_eq!(
+            exported_span_names(&sink).await,
+            vec!["Lambda/Invoke", "Lambda/Invoke"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nothing_to_export() {
+        let (exporter, buffer) = exporter();
+        let sink = MemorySink::default();
+        assert_eq!(
+            export_to_sink(&exporter, &buffer, &sink, Vec::new(), "invoke").await,
+            0
+        );
+        assert!(sink.lines.lock().await.is_empty());
+    }
+}
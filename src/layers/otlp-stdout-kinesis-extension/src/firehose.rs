@@ -0,0 +1,360 @@
+use crate::batching::{self, ChunkOutcome, RetryPolicy};
+use crate::otlp_parsing;
+use crate::sink::Sink;
+use async_trait::async_trait;
+use aws_sdk_firehose::Client as FirehoseClient;
+use aws_sdk_firehose::primitives::Blob;
+use aws_sdk_firehose::types::{PutRecordBatchResponseEntry, Record};
+use lambda_extension::{Error, tracing};
+use tokio::sync::Mutex;
+
+// Firehose limit for a single record
+pub const MAX_RECORD_SIZE_BYTES: usize = 1_024_000; // 1000KB per record
+
+// Firehose limits for a single PutRecordBatch request
+pub const MAX_RECORDS_PER_REQUEST: usize = 500;
+pub const MAX_REQUEST_SIZE_BYTES: usize = 4 * 1_048_576; // 4MB per request
+
+// Terminates every record so objects delivered to S3 are newline-delimited JSON
+pub const RECORD_DELIMITER: char = '\n';
+
+/// Splits records into groups that each fit in a single PutRecordBatch request.
+pub fn split_into_requests(records: Vec<Record>) -> Vec<Vec<Record>> {
+    batching::split_into_requests(
+        records,
+        MAX_RECORDS_PER_REQUEST,
+        MAX_REQUEST_SIZE_BYTES,
+        |record| record.data.as_ref().len(),
+    )
+}
+
+/// Describes the failure of each rejected entry in a PutRecordBatch response.
+pub fn result_failures(results: &[PutRecordBatchResponseEntry]) -> Vec<Option<String>> {
+    results
+        .iter()
+        .map(|result| {
+            result.error_code.as_ref().map(|error_code| {
+                format!(
+                    "{} - {}",
+                    error_code,
+                    result
+                        .error_message
+                        .as_deref()
+                        .unwrap_or("No error message")
+                )
+            })
+        })
+        .collect()
+}
+
+/// Sends one chunk with PutRecordBatch, resubmitting only the rejected entries.
+pub async fn put_record_batch_with_retry(
+    client: &FirehoseClient,
+    delivery_stream_name: &str,
+    records: Vec<Record>,
+    policy: &RetryPolicy,
+) -> ChunkOutcome<Record> {
+    batching::send_with_retry(records, policy, |pending| {
+        // The SDK takes the request entries by value
+        let entries = pending.to_vec();
+        async move {
+            let output = client
+                .put_record_batch()
+                .delivery_stream_name(delivery_stream_name)
+                .set_records(Some(entries))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(result_failures(output.request_responses()))
+        }
+    })
+    .await
+}
+
+/// Sends lines to a Kinesis Data Firehose delivery stream with PutRecordBatch.
+pub struct FirehoseSink {
+    client: FirehoseClient,
+    delivery_stream_name: String,
+    batch: Mutex<FirehoseBatch>,
+    retry_policy: RetryPolicy,
+    max_concurrency: usize,
+}
+
+impl FirehoseSink {
+    pub fn new(
+        client: FirehoseClient,
+        delivery_stream_name: String,
+        batch: FirehoseBatch,
+        retry_policy: RetryPolicy,
+        max_concurrency: usize,
+    ) -> Self {
+        Self {
+            client,
+            delivery_stream_name,
+            batch: Mutex::new(batch),
+            retry_policy,
+            max_concurrency: max_concurrency.max(1),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for FirehoseSink {
+    fn name(&self) -> &'static str {
+        "firehose"
+    }
+
+    async fn write_line(&self, line: String) -> Result<(), Error> {
+        self.batch.lock().await.add_record(line)
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        let mut batch = self.batch.lock().await;
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let record_count = batch.len();
+        let chunks = split_into_requests(batch.take_records()?);
+        let chunk_count = chunks.len();
+        tracing::debug!(
+            "Sending batch of {} records to Firehose delivery stream {} in {} PutRecordBatch request(s)",
+            record_count,
+            self.delivery_stream_name,
+            chunk_count
+        );
+
+        let summary = batching::deliver_chunks(chunks, self.max_concurrency, |chunk| {
+            let client = self.client.clone();
+            let delivery_stream_name = self.delivery_stream_name.clone();
+            let retry_policy = self.retry_policy.clone();
+            async move {
+                put_record_batch_with_retry(&client, &delivery_stream_name, chunk, &retry_policy)
+                    .await
+            }
+        })
+        .await;
+
+        tracing::debug!(
+            "Firehose flush finished: delivered={}, given_up={}, failed_requests={}/{}",
+            summary.delivered,
+            summary.given_up.len(),
+            summary.failed_chunks,
+            chunk_count
+        );
+        if !summary.given_up.is_empty() {
+            tracing::error!(
+                "Giving up on {} records after retries to Firehose delivery stream {}",
+                summary.given_up.len(),
+                self.delivery_stream_name
+            );
+        }
+
+        // Keep the records of failed requests so the next flush can pick them up
+        batch.records = summary.unsent;
+        if summary.failed_chunks > 0 {
+            return Err(Error::from(format!(
+                "{} of {} PutRecordBatch requests to Firehose failed",
+                summary.failed_chunks, chunk_count
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Records waiting to be sent to Firehose.
+///
+/// Every record ends with a newline, so that lines stay separated once Firehose
+/// concatenates records into S3 objects. With aggregation enabled, consecutive lines
+/// are packed into records of up to the target size, which also reduces the number
+/// of billed 5KB record increments.
+#[derive(Default)]
+pub struct FirehoseBatch {
+    pub records: Vec<Record>,
+    /// When set, lines are packed into records of up to this many bytes.
+    aggregation_target_bytes: Option<usize>,
+    open_record: Option<String>,
+}
+
+impl FirehoseBatch {
+    pub fn with_aggregation(mut self, target_bytes: usize) -> Self {
+        self.aggregation_target_bytes = Some(target_bytes.min(MAX_RECORD_SIZE_BYTES));
+        self
+    }
+
+    pub fn add_record(&mut self, record: String) -> Result<(), Error> {
+        // Leave room for the trailing delimiter
+        let max_line_bytes = MAX_RECORD_SIZE_BYTES - 1;
+        if record.len() > max_line_bytes {
+            // Re-split the OTLP payload by resource/scope/span rather than losing all of it
+            match otlp_parsing::split_json_line(&record, max_line_bytes) {
+                Ok(parts) => {
+                    tracing::debug!(
+                        "Split record of {} bytes into {} records",
+                        record.len(),
+                        parts.len()
+                    );
+                    for part in parts {
+                        self.push_line(part)?;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Record size {} bytes exceeds maximum size of {} bytes and could not be split, skipping: {}",
+                        record.len(),
+                        max_line_bytes,
+                        e
+                    );
+                }
+            }
+            return Ok(());
+        }
+
+        self.push_line(record)
+    }
+
+    fn push_line(&mut self, mut line: String) -> Result<(), Error> {
+        line.push(RECORD_DELIMITER);
+        let Some(target_bytes) = self.aggregation_target_bytes else {
+            return self.push_entry(line);
+        };
+
+        if let Some(open) = self.open_record.as_mut() {
+            if open.len() + line.len() <= target_bytes {
+                open.push_str(&line);
+                return Ok(());
+            }
+            self.seal_open_record()?;
+        }
+
+        if line.len() >= target_bytes {
+            self.push_entry(line)
+        } else {
+            self.open_record = Some(line);
+            Ok(())
+        }
+    }
+
+    fn seal_open_record(&mut self) -> Result<(), Error> {
+        match self.open_record.take() {
+            Some(data) => self.push_entry(data),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes all records ready to be sent, including a partially filled aggregated record.
+    pub fn take_records(&mut self) -> Result<Vec<Record>, Error> {
+        self.seal_open_record()?;
+        Ok(std::mem::take(&mut self.records))
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len() + usize::from(self.open_record.is_some())
+    }
+
+    fn push_entry(&mut self, data: String) -> Result<(), Error> {
+        match Record::builder().data(Blob::new(data)).build() {
+            Ok(record) => {
+                self.records.push(record);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("Failed to build Firehose record: {}", e);
+                Err(Error::from(e))
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.open_record.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_entry(error_code: Option<&str>) -> PutRecordBatchResponseEntry {
+        PutRecordBatchResponseEntry::builder()
+            .set_error_code(error_code.map(String::from))
+            .build()
+    }
+
+    #[test]
+    fn test_add_record_appends_delimiter() {
+        let mut batch = FirehoseBatch::default();
+        batch
+            .add_record("{\"key\": \"value\"}".to_string())
+            .unwrap();
+
+        assert_eq!(batch.records.len(), 1);
+        assert_eq!(batch.records[0].data.as_ref(), b"{\"key\": \"value\"}\n");
+    }
+
+    #[test]
+    fn test_add_record_too_large() {
+        let mut batch = FirehoseBatch::default();
+        let result = batch.add_record("a".repeat(MAX_RECORD_SIZE_BYTES));
+
+        // Not an OTLP payload, so it cannot be split and is skipped
+        assert!(result.is_ok());
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn test_add_record_at_limit() {
+        let mut batch = FirehoseBatch::default();
+        batch
+            .add_record("a".repeat(MAX_RECORD_SIZE_BYTES - 1))
+            .unwrap();
+
+        assert_eq!(batch.records.len(), 1);
+        assert_eq!(batch.records[0].data.as_ref().len(), MAX_RECORD_SIZE_BYTES);
+    }
+
+    #[test]
+    fn test_aggregation_packs_lines_up_to_target() {
+        let mut batch = FirehoseBatch::default().with_aggregation(12);
+        for line in ["aaa", "bbb", "ccc", "ddd"] {
+            batch.add_record(line.to_string()).unwrap();
+        }
+
+        let records = batch.take_records().unwrap();
+        let data: Vec<&[u8]> = records.iter().map(|r| r.data.as_ref()).collect();
+        assert_eq!(data, vec![&b"aaa\nbbb\nccc\n"[..], &b"ddd\n"[..]]);
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn test_split_into_requests_payload_size_limit() {
+        // Five records just under 1000KB each exceed 4MB together
+        let records = (0..5)
+            .map(|_| {
+                Record::builder()
+                    .data(Blob::new(vec![b'a'; MAX_RECORD_SIZE_BYTES]))
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let chunks = split_into_requests(records);
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![4, 1]);
+    }
+
+    #[test]
+    fn test_failed_entries_keeps_only_rejected_records() {
+        let mut batch = FirehoseBatch::default();
+        for line in ["ok", "throttled", "ok"] {
+            batch.add_record(line.to_string()).unwrap();
+        }
+        let results = vec![
+            response_entry(None),
+            response_entry(Some("ServiceUnavailableException")),
+            response_entry(None),
+        ];
+
+        let failed = batching::failed_entries(batch.records, &result_failures(&results));
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].data.as_ref(), b"throttled\n");
+    }
+}
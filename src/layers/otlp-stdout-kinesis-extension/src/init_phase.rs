@@ -0,0 +1,637 @@
+use crate::aggregation::AttributeNaming;
+use crate::events::{ParsedPlatformEvent, PlatformEventData};
+use lambda_extension::{InitPhase, InitType, Status as LambdaStatus, tracing};
+use opentelemetry::{
+    InstrumentationScope, KeyValue,
+    trace::{SpanContext, SpanId, SpanKind, Status as OtelStatus, TraceFlags, TraceId, TraceState},
+};
+use opentelemetry_sdk::trace::{SpanData, SpanEvents, SpanLinks};
+use opentelemetry_semantic_conventions::attribute as semconv;
+use rand::Rng;
+use std::str::FromStr;
+use std::time::{Duration as StdDuration, SystemTime};
+
+const INIT_PHASE_NAME: &str = "Lambda/Init";
+const RESTORE_PHASE_NAME: &str = "Lambda/Restore";
+
+/// How an execution environment got ready for its first invocation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StartupKind {
+    /// Initialized from scratch (a cold start, or provisioned concurrency).
+    #[default]
+    Init,
+    /// Resumed from a SnapStart snapshot.
+    Restore,
+}
+
+impl StartupKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Init => "init",
+            Self::Restore => "restore",
+        }
+    }
+
+    fn span_name(&self) -> &'static str {
+        match self {
+            Self::Init => INIT_PHASE_NAME,
+            Self::Restore => RESTORE_PHASE_NAME,
+        }
+    }
+}
+
+/// Where the `Lambda/Init` and `Lambda/Restore` spans are placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InitSpanMode {
+    /// Linked for provisioned concurrency, whose init can run minutes before the first
+    /// invocation, and a child of the first invocation's trace otherwise.
+    #[default]
+    Auto,
+    /// Always a child of the function's root span in the first invocation's trace.
+    Child,
+    /// Always the root of its own trace, linked from the first `Lambda/Invoke` span.
+    Linked,
+}
+
+impl InitSpanMode {
+    /// Whether the span of `init` is emitted as its own trace.
+    pub fn is_linked(&self, init: &InitPhaseInfo) -> bool {
+        match self {
+            Self::Auto => init.initialization_type == Some(InitType::ProvisionedConcurrency),
+            Self::Child => false,
+            Self::Linked => true,
+        }
+    }
+}
+
+impl FromStr for InitSpanMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "child" => Ok(Self::Child),
+            "linked" | "link" => Ok(Self::Linked),
+            _ => Err(format!("Invalid init span mode: {s}")),
+        }
+    }
+}
+
+/// One initialization of the execution environment, assembled from `platform.initStart`,
+/// `platform.initRuntimeDone` and `platform.initReport`, or one SnapStart restore,
+/// assembled from the matching `platform.restore*` events.
+///
+/// A failed init in the `init` phase is retried during the first invoke (phase `invoke`),
+/// so an environment can go through more than one of these before its first invocation.
+#[derive(Debug, Clone, Default)]
+pub struct InitPhaseInfo {
+    pub kind: StartupKind,
+    pub start_time: Option<SystemTime>,
+    pub end_time: Option<SystemTime>,
+    pub duration_ms: Option<f64>,
+    pub initialization_type: Option<InitType>,
+    pub phase: Option<InitPhase>,
+    pub runtime_version: Option<String>,
+    pub runtime_version_arn: Option<String>,
+    pub status: Option<LambdaStatus>,
+    pub error_type: Option<String>,
+    runtime_done: bool,
+    reported: bool,
+}
+
+impl InitPhaseInfo {
+    /// Whether the runtime finished initializing or restoring, successfully or not. The
+    /// report follows the runtime done event in the same batch, before any invocation event.
+    pub fn is_finished(&self) -> bool {
+        self.runtime_done || self.reported
+    }
+
+    fn update_from_event(&mut self, event: &ParsedPlatformEvent) {
+        match &event.data {
+            PlatformEventData::InitStart {
+                initialization_type,
+                phase,
+                runtime_version,
+                runtime_version_arn,
+            } => {
+                self.start_time = Some(event.timestamp.into());
+                self.initialization_type = Some(initialization_type.clone());
+                self.phase = Some(phase.clone());
+                self.runtime_version = runtime_version.clone();
+                self.runtime_version_arn = runtime_version_arn.clone();
+            }
+            PlatformEventData::InitRuntimeDone {
+                initialization_type,
+                phase,
+                status,
+                error_type,
+            } => {
+                self.runtime_done = true;
+                self.initialization_type
+                    .get_or_insert_with(|| initialization_type.clone());
+                if let Some(phase) = phase {
+                    self.phase.get_or_insert_with(|| phase.clone());
+                }
+                self.status = Some(status.clone());
+                self.error_type = error_type.clone();
+                self.end_time.get_or_insert(event.timestamp.into());
+            }
+            PlatformEventData::InitReport {
+                initialization_type,
+                phase,
+                duration_ms,
+            } => {
+                self.reported = true;
+                self.initialization_type
+                    .get_or_insert_with(|| initialization_type.clone());
+                self.phase.get_or_insert_with(|| phase.clone());
+                self.duration_ms = Some(*duration_ms);
+                // The report covers the whole init phase, extensions included
+                self.end_time = Some(event.timestamp.into());
+            }
+            PlatformEventData::RestoreStart {
+                runtime_version,
+                runtime_version_arn,
+            } => {
+                self.kind = StartupKind::Restore;
+                self.start_time = Some(event.timestamp.into());
+                self.runtime_version = runtime_version.clone();
+                self.runtime_version_arn = runtime_version_arn.clone();
+            }
+            PlatformEventData::RestoreRuntimeDone { status, error_type } => {
+                self.kind = StartupKind::Restore;
+                self.runtime_done = true;
+                // A failure here means a runtime hook (e.g. afterRestore) failed
+                self.status = Some(status.clone());
+                self.error_type = error_type.clone();
+                self.end_time.get_or_insert(event.timestamp.into());
+            }
+            PlatformEventData::RestoreReport {
+                status,
+                error_type,
+                duration_ms,
+            } => {
+                self.kind = StartupKind::Restore;
+                self.reported = true;
+                // Keep the runtime hook's error if the report doesn't repeat it
+                if self.status.is_none() || *status != LambdaStatus::Success {
+                    self.status = Some(status.clone());
+                }
+                if error_type.is_some() {
+                    self.error_type = error_type.clone();
+                }
+                self.duration_ms = *duration_ms;
+                self.end_time = Some(event.timestamp.into());
+            }
+            _ => {}
+        }
+    }
+
+    /// Builds the `Lambda/Init` or `Lambda/Restore` span as a child of `parent_span_id` in
+    /// `trace_id`, with attributes named per `naming`. Returns `None` if neither the start
+    /// nor the duration is known.
+    pub fn to_span_data(
+        &self,
+        trace_id: TraceId,
+        parent_span_id: SpanId,
+        trace_flags: TraceFlags,
+        naming: AttributeNaming,
+    ) -> Option<SpanData> {
+        let duration = self
+            .duration_ms
+            .map(|ms| StdDuration::from_secs_f64(ms.max(0.0) / 1000.0));
+        let (start_time, end_time) = match (self.start_time, self.end_time, duration) {
+            (Some(start), _, Some(duration)) => (start, start + duration),
+            (Some(start), Some(end), None) => (start, end.max(start)),
+            (None, Some(end), Some(duration)) => (end.checked_sub(duration)?, end),
+            _ => return None,
+        };
+
+        let mut rng = rand::rng();
+        let span_context = SpanContext::new(
+            trace_id,
+            SpanId::from_bytes(rng.random::<[u8; 8]>()),
+            trace_flags,
+            false,
+            TraceState::default(),
+        );
+
+        Some(SpanData {
+            span_context,
+            parent_span_id,
+            span_kind: SpanKind::Internal,
+            name: self.kind.span_name().into(),
+            start_time,
+            end_time,
+            attributes: self.attributes(naming),
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status: self.otel_status(),
+            dropped_attributes_count: 0,
+            instrumentation_scope: InstrumentationScope::default(),
+        })
+    }
+
+    /// Builds the `Lambda/Init` or `Lambda/Restore` span as the root of a new trace, to be
+    /// linked from the first invocation rather than placed in its trace.
+    pub fn to_root_span_data(&self, naming: AttributeNaming) -> Option<SpanData> {
+        let trace_id = TraceId::from_bytes(rand::rng().random::<[u8; 16]>());
+        self.to_span_data(trace_id, SpanId::INVALID, TraceFlags::SAMPLED, naming)
+    }
+
+    fn attributes(&self, naming: AttributeNaming) -> Vec<KeyValue> {
+        let prefix = format!("lambda.{}", self.kind.as_str());
+        // Our own keys are snake_case under the semantic conventions, camelCase in legacy naming
+        let key = |semconv_name: &str, legacy_name: &str| match naming {
+            AttributeNaming::SemConv => format!("{prefix}.{semconv_name}"),
+            AttributeNaming::Legacy => format!("{prefix}.{legacy_name}"),
+        };
+        let mut attributes = Vec::new();
+        if let Some(initialization_type) = &self.initialization_type {
+            attributes.push(KeyValue::new(
+                format!("{prefix}.type"),
+                init_type_str(initialization_type),
+            ));
+        }
+        if let Some(phase) = &self.phase {
+            attributes.push(KeyValue::new(
+                format!("{prefix}.phase"),
+                init_phase_str(phase),
+            ));
+        }
+        if let Some(version) = &self.runtime_version {
+            attributes.push(KeyValue::new(
+                key("runtime_version", "runtimeVersion"),
+                version.clone(),
+            ));
+        }
+        if let Some(arn) = &self.runtime_version_arn {
+            attributes.push(KeyValue::new(
+                key("runtime_version_arn", "runtimeVersionArn"),
+                arn.clone(),
+            ));
+        }
+        if let Some(status) = &self.status {
+            attributes.push(KeyValue::new(
+                format!("{prefix}.status"),
+                status_str(status),
+            ));
+        }
+        if let Some(error_type) = &self.error_type {
+            let error_key = match naming {
+                AttributeNaming::SemConv => semconv::ERROR_TYPE.to_string(),
+                AttributeNaming::Legacy => format!("{prefix}.errorType"),
+            };
+            attributes.push(KeyValue::new(error_key, error_type.clone()));
+        }
+        if let Some(duration_ms) = self.duration_ms {
+            attributes.push(KeyValue::new(format!("{prefix}.durationMs"), duration_ms));
+        }
+        attributes
+    }
+
+    fn otel_status(&self) -> OtelStatus {
+        match &self.status {
+            Some(LambdaStatus::Success) => OtelStatus::Ok,
+            Some(LambdaStatus::Failure | LambdaStatus::Error | LambdaStatus::Timeout) => {
+                OtelStatus::Error {
+                    description: self
+                        .error_type
+                        .clone()
+                        .unwrap_or_else(|| format!("Lambda {} error", self.kind.as_str()))
+                        .into(),
+                }
+            }
+            // Without initRuntimeDone the outcome is unknown
+            None => OtelStatus::Unset,
+        }
+    }
+}
+
+/// Records an init or restore event into the attempts seen so far. `platform.initStart`
+/// and `platform.restoreStart` begin a new attempt; the other events complete the latest
+/// unfinished one.
+pub fn record_event(inits: &mut Vec<InitPhaseInfo>, event: &ParsedPlatformEvent) {
+    let starts_new = matches!(
+        event.data,
+        PlatformEventData::InitStart { .. } | PlatformEventData::RestoreStart { .. }
+    );
+    let ongoing = match inits.last_mut() {
+        Some(info) if !starts_new && !info.reported => info,
+        _ => {
+            inits.push(InitPhaseInfo::default());
+            inits.last_mut().expect("just pushed")
+        }
+    };
+    ongoing.update_from_event(event);
+    tracing::debug!(init = ?ongoing, "Recorded init phase event");
+}
+
+/// Removes and returns the init attempts that finished, leaving any still in progress.
+pub fn take_finished(inits: &mut Vec<InitPhaseInfo>) -> Vec<InitPhaseInfo> {
+    let (finished, ongoing) = std::mem::take(inits)
+        .into_iter()
+        .partition(InitPhaseInfo::is_finished);
+    *inits = ongoing;
+    finished
+}
+
+pub fn init_type_str(initialization_type: &InitType) -> &'static str {
+    match initialization_type {
+        InitType::OnDemand => "on-demand",
+        InitType::ProvisionedConcurrency => "provisioned-concurrency",
+        InitType::SnapStart => "snap-start",
+    }
+}
+
+fn init_phase_str(phase: &InitPhase) -> &'static str {
+    match phase {
+        InitPhase::Init => "init",
+        InitPhase::Invoke => "invoke",
+    }
+}
+
+pub fn status_str(status: &LambdaStatus) -> &'static str {
+    match status {
+        LambdaStatus::Success => "success",
+        LambdaStatus::Error => "error",
+        LambdaStatus::Failure => "failure",
+        LambdaStatus::Timeout => "timeout",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+    use opentelemetry::Value as OtelValue;
+
+    fn ts(ms: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::milliseconds(ms)
+    }
+
+    fn event(ms: i64, data: PlatformEventData) -> ParsedPlatformEvent {
+        ParsedPlatformEvent {
+            timestamp: ts(ms),
+            request_id: String::new(),
+            data,
+        }
+    }
+
+    fn init_start(ms: i64, phase: InitPhase) -> ParsedPlatformEvent {
+        event(
+            ms,
+            PlatformEventData::InitStart {
+                initialization_type: InitType::OnDemand,
+                phase,
+                runtime_version: Some("nodejs:20.v30".to_string()),
+                runtime_version_arn: None,
+            },
+        )
+    }
+
+    fn init_runtime_done(ms: i64, status: LambdaStatus) -> ParsedPlatformEvent {
+        let error_type = (status != LambdaStatus::Success).then(|| "Runtime.ExitError".to_string());
+        event(
+            ms,
+            PlatformEventData::InitRuntimeDone {
+                initialization_type: InitType::OnDemand,
+                phase: None,
+                status,
+                error_type,
+            },
+        )
+    }
+
+    fn init_report(ms: i64, phase: InitPhase, duration_ms: f64) -> ParsedPlatformEvent {
+        event(
+            ms,
+            PlatformEventData::InitReport {
+                initialization_type: InitType::OnDemand,
+                phase,
+                duration_ms,
+            },
+        )
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<OtelValue> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn test_successful_init_span() {
+        let mut inits = Vec::new();
+        record_event(&mut inits, &init_start(0, InitPhase::Init));
+        assert!(take_finished(&mut inits).is_empty());
+        record_event(&mut inits, &init_runtime_done(240, LambdaStatus::Success));
+        record_event(&mut inits, &init_report(250, InitPhase::Init, 250.0));
+
+        let finished = take_finished(&mut inits);
+        assert!(inits.is_empty());
+        assert_eq!(finished.len(), 1);
+
+        let span = finished[0]
+            .to_span_data(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([2; 8]),
+                TraceFlags::SAMPLED,
+                AttributeNaming::SemConv,
+            )
+            .unwrap();
+        assert_eq!(span.name, INIT_PHASE_NAME);
+        assert_eq!(span.parent_span_id, SpanId::from_bytes([2; 8]));
+        assert_eq!(span.start_time, SystemTime::from(ts(0)));
+        assert_eq!(span.end_time, SystemTime::from(ts(250)));
+        assert_eq!(span.status, OtelStatus::Ok);
+        assert_eq!(
+            attribute(&span, "lambda.init.type"),
+            Some(OtelValue::from("on-demand"))
+        );
+        assert_eq!(
+            attribute(&span, "lambda.init.phase"),
+            Some(OtelValue::from("init"))
+        );
+        assert_eq!(
+            attribute(&span, "lambda.init.runtime_version"),
+            Some(OtelValue::from("nodejs:20.v30"))
+        );
+        assert_eq!(
+            attribute(&span, "lambda.init.status"),
+            Some(OtelValue::from("success"))
+        );
+
+        // Legacy naming keeps the camelCase keys of earlier versions
+        let legacy = finished[0]
+            .to_span_data(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([2; 8]),
+                TraceFlags::SAMPLED,
+                AttributeNaming::Legacy,
+            )
+            .unwrap();
+        assert_eq!(
+            attribute(&legacy, "lambda.init.runtimeVersion"),
+            Some(OtelValue::from("nodejs:20.v30"))
+        );
+        assert_eq!(attribute(&legacy, "lambda.init.runtime_version"), None);
+    }
+
+    #[test]
+    fn test_failed_init_is_an_error_span_followed_by_retry() {
+        let mut inits = Vec::new();
+        record_event(&mut inits, &init_start(0, InitPhase::Init));
+        record_event(&mut inits, &init_runtime_done(100, LambdaStatus::Error));
+        record_event(&mut inits, &init_report(110, InitPhase::Init, 110.0));
+        // Lambda retries the init during the first invoke
+        record_event(&mut inits, &init_start(500, InitPhase::Invoke));
+        record_event(&mut inits, &init_runtime_done(700, LambdaStatus::Success));
+
+        let finished = take_finished(&mut inits);
+        assert_eq!(finished.len(), 2);
+
+        let trace_id = TraceId::from_bytes([1; 16]);
+        let parent = SpanId::from_bytes([2; 8]);
+        let failed = finished[0]
+            .to_span_data(
+                trace_id,
+                parent,
+                TraceFlags::SAMPLED,
+                AttributeNaming::SemConv,
+            )
+            .unwrap();
+        assert_eq!(
+            failed.status,
+            OtelStatus::Error {
+                description: "Runtime.ExitError".into()
+            }
+        );
+        assert_eq!(
+            attribute(&failed, semconv::ERROR_TYPE),
+            Some(OtelValue::from("Runtime.ExitError"))
+        );
+        let legacy = finished[0]
+            .to_span_data(
+                trace_id,
+                parent,
+                TraceFlags::SAMPLED,
+                AttributeNaming::Legacy,
+            )
+            .unwrap();
+        assert_eq!(
+            attribute(&legacy, "lambda.init.errorType"),
+            Some(OtelValue::from("Runtime.ExitError"))
+        );
+        assert_eq!(attribute(&legacy, semconv::ERROR_TYPE), None);
+
+        // Without initReport the span ends at initRuntimeDone
+        let retry = finished[1]
+            .to_span_data(
+                trace_id,
+                parent,
+                TraceFlags::SAMPLED,
+                AttributeNaming::SemConv,
+            )
+            .unwrap();
+        assert_eq!(retry.start_time, SystemTime::from(ts(500)));
+        assert_eq!(retry.end_time, SystemTime::from(ts(700)));
+        assert_eq!(
+            attribute(&retry, "lambda.init.phase"),
+            Some(OtelValue::from("invoke"))
+        );
+    }
+
+    #[test]
+    fn test_init_without_start_uses_reported_duration() {
+        let mut inits = Vec::new();
+        record_event(&mut inits, &init_report(300, InitPhase::Init, 200.0));
+
+        let finished = take_finished(&mut inits);
+        let span = finished[0]
+            .to_span_data(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([2; 8]),
+                TraceFlags::SAMPLED,
+                AttributeNaming::SemConv,
+            )
+            .unwrap();
+        assert_eq!(span.start_time, SystemTime::from(ts(100)));
+        assert_eq!(span.status, OtelStatus::Unset);
+    }
+
+    #[test]
+    fn test_restore_span_with_runtime_hook_error() {
+        let mut inits = Vec::new();
+        record_event(
+            &mut inits,
+            &event(
+                0,
+                PlatformEventData::RestoreStart {
+                    runtime_version: Some("java:21.v20".to_string()),
+                    runtime_version_arn: None,
+                },
+            ),
+        );
+        record_event(
+            &mut inits,
+            &event(
+                80,
+                PlatformEventData::RestoreRuntimeDone {
+                    status: LambdaStatus::Failure,
+                    error_type: Some("Runtime.AfterRestoreHookError".to_string()),
+                },
+            ),
+        );
+        record_event(
+            &mut inits,
+            &event(
+                90,
+                PlatformEventData::RestoreReport {
+                    status: LambdaStatus::Failure,
+                    error_type: None,
+                    duration_ms: Some(90.0),
+                },
+            ),
+        );
+
+        let finished = take_finished(&mut inits);
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].kind, StartupKind::Restore);
+
+        let span = finished[0]
+            .to_span_data(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([2; 8]),
+                TraceFlags::SAMPLED,
+                AttributeNaming::SemConv,
+            )
+            .unwrap();
+        assert_eq!(span.name, RESTORE_PHASE_NAME);
+        assert_eq!(span.end_time, SystemTime::from(ts(90)));
+        assert_eq!(
+            span.status,
+            OtelStatus::Error {
+                description: "Runtime.AfterRestoreHookError".into()
+            }
+        );
+        assert_eq!(
+            attribute(&span, "lambda.restore.durationMs"),
+            Some(OtelValue::F64(90.0))
+        );
+        assert_eq!(
+            attribute(&span, "lambda.restore.runtime_version"),
+            Some(OtelValue::from("java:21.v20"))
+        );
+        assert_eq!(attribute(&span, "lambda.init.type"), None);
+    }
+
+    #[test]
+    fn test_init_span_mode_from_str() {
+        assert_eq!("auto".parse::<InitSpanMode>(), Ok(InitSpanMode::Auto));
+        assert_eq!("Child".parse::<InitSpanMode>(), Ok(InitSpanMode::Child));
+        assert_eq!("linked".parse::<InitSpanMode>(), Ok(InitSpanMode::Linked));
+        assert!("sibling".parse::<InitSpanMode>().is_err());
+    }
+}
@@ -1,30 +1,394 @@
+use crate::batching::{self, ChunkOutcome, RetryPolicy};
+use crate::otlp_parsing;
+use crate::partition_key::PartitionKeyGenerator;
+use crate::sink::Sink;
+use crate::spool::{Spool, SpoolEntry};
+use async_trait::async_trait;
+use aws_sdk_kinesis::Client as KinesisClient;
 use aws_sdk_kinesis::primitives::Blob;
-use aws_sdk_kinesis::types::PutRecordsRequestEntry;
+use aws_sdk_kinesis::types::{PutRecordsRequestEntry, PutRecordsResultEntry};
 use lambda_extension::{Error, tracing};
-use uuid::Uuid;
+use tokio::sync::Mutex;
 
 // Kinesis limit for a single record
 pub const MAX_RECORD_SIZE_BYTES: usize = 1_048_576; // 1MB per record
 
+// Kinesis limits for a single PutRecords request
+pub const MAX_RECORDS_PER_REQUEST: usize = 500;
+pub const MAX_REQUEST_SIZE_BYTES: usize = 5 * 1_048_576; // 5MB per request
+
+/// A record on its way to Kinesis, with the time it was first spooled if it was replayed.
+#[derive(Debug, Clone)]
+pub struct PendingRecord {
+    pub entry: PutRecordsRequestEntry,
+    pub spooled_at_ms: Option<u64>,
+}
+
+impl From<PutRecordsRequestEntry> for PendingRecord {
+    fn from(entry: PutRecordsRequestEntry) -> Self {
+        Self {
+            entry,
+            spooled_at_ms: None,
+        }
+    }
+}
+
+impl PendingRecord {
+    /// Rebuilds a spooled record, keeping when it was first spooled.
+    fn from_spool(entry: SpoolEntry) -> Result<Self, Error> {
+        let spooled_at_ms = entry.spooled_at_ms;
+        let entry = PutRecordsRequestEntry::builder()
+            .data(Blob::new(entry.data))
+            .partition_key(entry.partition_key)
+            .build()?;
+        Ok(Self {
+            entry,
+            spooled_at_ms: Some(spooled_at_ms),
+        })
+    }
+
+    /// Turns an undelivered record into a spool entry. A replayed record keeps its
+    /// original age, so the spool's age cap still applies to records that keep failing.
+    fn into_spool_entry(self) -> SpoolEntry {
+        let mut spool_entry =
+            SpoolEntry::new(self.entry.partition_key, self.entry.data.into_inner());
+        if let Some(spooled_at_ms) = self.spooled_at_ms {
+            spool_entry.spooled_at_ms = spooled_at_ms;
+        }
+        spool_entry
+    }
+}
+
+/// Splits records into groups that each fit in a single PutRecords request.
+/// Both the data blob and the partition key count towards the request payload size.
+pub fn split_into_requests(records: Vec<PendingRecord>) -> Vec<Vec<PendingRecord>> {
+    batching::split_into_requests(
+        records,
+        MAX_RECORDS_PER_REQUEST,
+        MAX_REQUEST_SIZE_BYTES,
+        |record| record.entry.data.as_ref().len() + record.entry.partition_key.len(),
+    )
+}
+
+/// Describes the failure of each rejected entry in a PutRecords response.
+pub fn result_failures(results: &[PutRecordsResultEntry]) -> Vec<Option<String>> {
+    results
+        .iter()
+        .map(|result| {
+            result.error_code.as_ref().map(|error_code| {
+                format!(
+                    "{} - {}",
+                    error_code,
+                    result
+                        .error_message
+                        .as_deref()
+                        .unwrap_or("No error message")
+                )
+            })
+        })
+        .collect()
+}
+
+/// Sends one chunk with PutRecords, resubmitting only the rejected entries.
+pub async fn put_records_with_retry(
+    client: &KinesisClient,
+    stream_name: &str,
+    records: Vec<PendingRecord>,
+    policy: &RetryPolicy,
+) -> ChunkOutcome<PendingRecord> {
+    batching::send_with_retry(records, policy, |pending| {
+        // The SDK takes the request entries by value
+        let entries = pending.iter().map(|record| record.entry.clone()).collect();
+        async move {
+            let output = client
+                .put_records()
+                .stream_name(stream_name)
+                .set_records(Some(entries))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(result_failures(output.records()))
+        }
+    })
+    .await
+}
+
+/// Sends lines to a Kinesis Data Stream with PutRecords.
+pub struct KinesisSink {
+    client: KinesisClient,
+    stream_name: String,
+    batch: Mutex<KinesisBatch>,
+    retry_policy: RetryPolicy,
+    max_concurrency: usize,
+    spool: Option<Spool>,
+}
+
+impl KinesisSink {
+    pub fn new(
+        client: KinesisClient,
+        stream_name: String,
+        batch: KinesisBatch,
+        retry_policy: RetryPolicy,
+        max_concurrency: usize,
+    ) -> Self {
+        Self {
+            client,
+            stream_name,
+            batch: Mutex::new(batch),
+            retry_policy,
+            max_concurrency: max_concurrency.max(1),
+            spool: None,
+        }
+    }
+
+    /// Keeps undelivered records in an on-disk spool and replays them on the next flush.
+    pub fn with_spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Takes the records waiting in the spool, oldest first.
+    async fn replay_spooled(&self, spool: &Spool) -> Vec<PendingRecord> {
+        let entries = match spool.drain().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::error!("Failed to read Kinesis spool: {}", e);
+                return Vec::new();
+            }
+        };
+        entries
+            .into_iter()
+            .filter_map(|entry| {
+                PendingRecord::from_spool(entry)
+                    .inspect_err(|e| tracing::warn!("Dropping unusable spooled record: {}", e))
+                    .ok()
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Sink for KinesisSink {
+    fn name(&self) -> &'static str {
+        "kinesis"
+    }
+
+    async fn write_line(&self, line: String) -> Result<(), Error> {
+        self.batch.lock().await.add_record(line)
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        let mut batch = self.batch.lock().await;
+        let mut records = match &self.spool {
+            Some(spool) => self.replay_spooled(spool).await,
+            None => Vec::new(),
+        };
+        if batch.is_empty() && records.is_empty() {
+            if let Some(spool) = &self.spool {
+                // The replay may have held nothing but expired records
+                if let Err(e) = spool.commit_replay().await {
+                    tracing::error!("Failed to clear Kinesis spool replay: {}", e);
+                }
+            }
+            return Ok(());
+        }
+
+        let replayed_count = records.len();
+        let record_count = replayed_count + batch.len();
+        records.extend(batch.take_records()?.into_iter().map(PendingRecord::from));
+        let chunks = split_into_requests(records);
+        let chunk_count = chunks.len();
+        tracing::debug!(
+            "Sending batch of {} records ({} replayed from spool) to Kinesis stream {} in {} PutRecords request(s)",
+            record_count,
+            replayed_count,
+            self.stream_name,
+            chunk_count
+        );
+
+        let summary = batching::deliver_chunks(chunks, self.max_concurrency, |chunk| {
+            let client = self.client.clone();
+            let stream_name = self.stream_name.clone();
+            let retry_policy = self.retry_policy.clone();
+            async move { put_records_with_retry(&client, &stream_name, chunk, &retry_policy).await }
+        })
+        .await;
+
+        tracing::debug!(
+            "Kinesis flush finished: delivered={}, given_up={}, failed_requests={}/{}",
+            summary.delivered,
+            summary.given_up.len(),
+            summary.failed_chunks,
+            chunk_count
+        );
+        if !summary.given_up.is_empty() {
+            tracing::error!(
+                "Giving up on {} records after retries to Kinesis stream {}",
+                summary.given_up.len(),
+                self.stream_name
+            );
+        }
+
+        match &self.spool {
+            Some(spool) => {
+                let undelivered: Vec<SpoolEntry> = summary
+                    .unsent
+                    .into_iter()
+                    .chain(summary.given_up)
+                    .map(PendingRecord::into_spool_entry)
+                    .collect();
+                // Keep the replay until the undelivered records are safely back in the spool
+                match spool.append(undelivered).await {
+                    Ok(()) => {
+                        if let Err(e) = spool.commit_replay().await {
+                            tracing::error!("Failed to clear Kinesis spool replay: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to spool undelivered Kinesis records: {}", e)
+                    }
+                }
+                let stats = spool.stats();
+                tracing::debug!(
+                    "Kinesis spool: spooled={}, replayed={}, evicted={}",
+                    stats.spooled,
+                    stats.replayed,
+                    stats.evicted
+                );
+            }
+            None => {
+                // Keep the records of failed requests so the next flush can pick them up
+                batch.records = summary
+                    .unsent
+                    .into_iter()
+                    .map(|record| record.entry)
+                    .collect();
+            }
+        }
+        if summary.failed_chunks > 0 {
+            return Err(Error::from(format!(
+                "{} of {} PutRecords requests to Kinesis failed",
+                summary.failed_chunks, chunk_count
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Separator between otlp-stdout lines packed into one aggregated record
+pub const AGGREGATED_RECORD_DELIMITER: char = '\n';
+
+/// Aggregated record still accepting lines, with the affinity key its lines share.
+struct OpenRecord {
+    data: String,
+    affinity_key: Option<String>,
+}
+
 #[derive(Default)]
 pub struct KinesisBatch {
     pub records: Vec<PutRecordsRequestEntry>,
+    partition_keys: PartitionKeyGenerator,
+    /// When set, lines are packed into records of up to this many bytes.
+    aggregation_target_bytes: Option<usize>,
+    open_record: Option<OpenRecord>,
 }
 
 impl KinesisBatch {
+    pub fn new(partition_keys: PartitionKeyGenerator) -> Self {
+        Self {
+            partition_keys,
+            ..Default::default()
+        }
+    }
+
+    /// Packs multiple otlp-stdout lines into each record.
+    ///
+    /// Aggregated records are newline-delimited: each line is a complete otlp-stdout
+    /// JSON document, which never contains a raw newline since JSON escapes them.
+    /// A record holding a single line is therefore identical to a non-aggregated one.
+    /// Lines are only packed together when they map to the same partition key affinity.
+    pub fn with_aggregation(mut self, target_bytes: usize) -> Self {
+        self.aggregation_target_bytes = Some(target_bytes.min(MAX_RECORD_SIZE_BYTES));
+        self
+    }
+
     pub fn add_record(&mut self, record: String) -> Result<(), Error> {
         if record.len() > MAX_RECORD_SIZE_BYTES {
-            tracing::warn!(
-                "Record size {} bytes exceeds maximum size of {} bytes, skipping",
-                record.len(),
-                MAX_RECORD_SIZE_BYTES
-            );
+            // Re-split the OTLP payload by resource/scope/span rather than losing all of it
+            match otlp_parsing::split_json_line(&record, MAX_RECORD_SIZE_BYTES) {
+                Ok(parts) => {
+                    tracing::debug!(
+                        "Split record of {} bytes into {} records",
+                        record.len(),
+                        parts.len()
+                    );
+                    for part in parts {
+                        self.push_line(part)?;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Record size {} bytes exceeds maximum size of {} bytes and could not be split, skipping: {}",
+                        record.len(),
+                        MAX_RECORD_SIZE_BYTES,
+                        e
+                    );
+                }
+            }
             return Ok(());
         }
 
+        self.push_line(record)
+    }
+
+    fn push_line(&mut self, line: String) -> Result<(), Error> {
+        let affinity_key = self.partition_keys.affinity_key(&line);
+        let Some(target_bytes) = self.aggregation_target_bytes else {
+            return self.push_entry(line, affinity_key);
+        };
+
+        if let Some(open) = self.open_record.as_mut() {
+            if open.affinity_key == affinity_key && open.data.len() + 1 + line.len() <= target_bytes
+            {
+                open.data.push(AGGREGATED_RECORD_DELIMITER);
+                open.data.push_str(&line);
+                return Ok(());
+            }
+            self.seal_open_record()?;
+        }
+
+        if line.len() >= target_bytes {
+            self.push_entry(line, affinity_key)
+        } else {
+            self.open_record = Some(OpenRecord {
+                data: line,
+                affinity_key,
+            });
+            Ok(())
+        }
+    }
+
+    fn seal_open_record(&mut self) -> Result<(), Error> {
+        match self.open_record.take() {
+            Some(open) => self.push_entry(open.data, open.affinity_key),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes all records ready to be sent, including a partially filled aggregated record.
+    pub fn take_records(&mut self) -> Result<Vec<PutRecordsRequestEntry>, Error> {
+        self.seal_open_record()?;
+        Ok(std::mem::take(&mut self.records))
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len() + usize::from(self.open_record.is_some())
+    }
+
+    fn push_entry(&mut self, record: String, affinity_key: Option<String>) -> Result<(), Error> {
         match PutRecordsRequestEntry::builder()
             .data(Blob::new(record))
-            .partition_key(Uuid::new_v4().to_string())
+            .partition_key(self.partition_keys.next_key(affinity_key))
             .build()
         {
             Ok(entry) => {
@@ -39,17 +403,20 @@ impl KinesisBatch {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.records.is_empty()
+        self.records.is_empty() && self.open_record.is_none()
     }
 
+    #[cfg(test)]
     pub fn clear(&mut self) {
         self.records.clear();
+        self.open_record = None;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*; // Import items from outer module
+    use crate::partition_key::PartitionKeyStrategy;
     use uuid::Uuid;
 
     #[test]
@@ -80,6 +447,68 @@ mod tests {
         // Ideally, capture logs to verify the warning was logged, but that's harder in basic unit tests.
     }
 
+    #[test]
+    fn test_add_record_splits_oversized_otlp_payload() {
+        use base64::{Engine, engine::general_purpose};
+        use flate2::{Compression, write::GzEncoder};
+        use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+        use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue, any_value};
+        use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span};
+        use prost::Message;
+        use rand::Rng;
+        use std::io::Write;
+
+        // Random attribute values so that gzip cannot shrink the payload below the limit
+        let mut rng = rand::rng();
+        let spans: Vec<Span> = (0..300u64)
+            .map(|i| Span {
+                trace_id: vec![1; 16],
+                span_id: (i + 1).to_be_bytes().to_vec(),
+                name: format!("span-{i}"),
+                attributes: vec![KeyValue {
+                    key: "blob".to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(hex::encode(
+                            (0..4096).map(|_| rng.random::<u8>()).collect::<Vec<u8>>(),
+                        ))),
+                    }),
+                }],
+                ..Default::default()
+            })
+            .collect();
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans {
+                    spans,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&request.encode_to_vec()).unwrap();
+        let line = serde_json::json!({
+            "__otel_otlp_stdout": "otlp-stdout-span-exporter@0.16.0",
+            "source": "test-service",
+            "endpoint": "http://localhost:4318/v1/traces",
+            "method": "POST",
+            "content-type": "application/x-protobuf",
+            "content-encoding": "gzip",
+            "payload": general_purpose::STANDARD.encode(encoder.finish().unwrap()),
+            "base64": true
+        })
+        .to_string();
+        assert!(line.len() > MAX_RECORD_SIZE_BYTES);
+
+        let mut batch = KinesisBatch::default();
+        batch.add_record(line).unwrap();
+
+        assert!(batch.records.len() > 1);
+        for entry in &batch.records {
+            assert!(entry.data.as_ref().len() <= MAX_RECORD_SIZE_BYTES);
+        }
+    }
+
     #[test]
     fn test_add_record_at_limit() {
         let mut batch = KinesisBatch::default();
@@ -93,6 +522,242 @@ mod tests {
         assert_eq!(entry.data.as_ref(), limit_record_data.as_bytes());
     }
 
+    fn result_entry(error_code: Option<&str>) -> PutRecordsResultEntry {
+        PutRecordsResultEntry::builder()
+            .set_error_code(error_code.map(String::from))
+            .build()
+    }
+
+    #[test]
+    fn test_failed_entries_keeps_only_rejected_records() {
+        let mut batch = KinesisBatch::default();
+        batch.add_record("ok-1".to_string()).unwrap();
+        batch.add_record("throttled".to_string()).unwrap();
+        batch.add_record("ok-2".to_string()).unwrap();
+        batch.add_record("internal".to_string()).unwrap();
+
+        let results = vec![
+            result_entry(None),
+            result_entry(Some("ProvisionedThroughputExceededException")),
+            result_entry(None),
+            result_entry(Some("InternalFailure")),
+        ];
+
+        let failed = batching::failed_entries(batch.records, &result_failures(&results));
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].data.as_ref(), b"throttled");
+        assert_eq!(failed[1].data.as_ref(), b"internal");
+    }
+
+    #[test]
+    fn test_result_failures_describe_rejections() {
+        let failures =
+            result_failures(&[result_entry(None), result_entry(Some("InternalFailure"))]);
+        assert_eq!(
+            failures,
+            vec![None, Some("InternalFailure - No error message".to_string())]
+        );
+    }
+
+    fn entry(size: usize) -> PendingRecord {
+        PutRecordsRequestEntry::builder()
+            .data(Blob::new(vec![b'a'; size]))
+            .partition_key("k")
+            .build()
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_split_into_requests_record_count_limit() {
+        let records = (0..1201).map(|_| entry(10)).collect();
+        let chunks = split_into_requests(records);
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![500, 500, 201]);
+    }
+
+    #[test]
+    fn test_split_into_requests_payload_size_limit() {
+        // Six records just under 1MB each (including the partition key) exceed 5MB together
+        let records = (0..6).map(|_| entry(MAX_RECORD_SIZE_BYTES - 1)).collect();
+        let chunks = split_into_requests(records);
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![5, 1]);
+        for chunk in &chunks {
+            let total: usize = chunk
+                .iter()
+                .map(|r| r.entry.data.as_ref().len() + r.entry.partition_key.len())
+                .sum();
+            assert!(total <= MAX_REQUEST_SIZE_BYTES);
+        }
+    }
+
+    #[test]
+    fn test_aggregation_packs_lines_up_to_target() {
+        let mut batch = KinesisBatch::default().with_aggregation(25);
+        for line in ["line-one", "line-two", "line-three", "line-four"] {
+            batch.add_record(line.to_string()).unwrap();
+        }
+        assert_eq!(batch.len(), 2);
+
+        let records = batch.take_records().unwrap();
+        assert!(batch.is_empty());
+        let data: Vec<&[u8]> = records.iter().map(|r| r.data.as_ref()).collect();
+        assert_eq!(
+            data,
+            vec![&b"line-one\nline-two"[..], &b"line-three\nline-four"[..]]
+        );
+    }
+
+    #[test]
+    fn test_aggregation_large_line_gets_own_record() {
+        let mut batch = KinesisBatch::default().with_aggregation(16);
+        batch.add_record("small".to_string()).unwrap();
+        batch.add_record("a".repeat(32)).unwrap();
+        batch.add_record("tail".to_string()).unwrap();
+
+        let records = batch.take_records().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].data.as_ref(), b"small");
+        assert_eq!(records[1].data.as_ref().len(), 32);
+        assert_eq!(records[2].data.as_ref(), b"tail");
+    }
+
+    #[test]
+    fn test_aggregation_target_capped_at_record_limit() {
+        let mut batch = KinesisBatch::default().with_aggregation(usize::MAX);
+        let line = "a".repeat(MAX_RECORD_SIZE_BYTES / 2);
+        batch.add_record(line.clone()).unwrap();
+        batch.add_record(line).unwrap();
+
+        let records = batch.take_records().unwrap();
+        assert_eq!(records.len(), 2);
+        for record in &records {
+            assert!(record.data.as_ref().len() <= MAX_RECORD_SIZE_BYTES);
+        }
+    }
+
+    #[test]
+    fn test_clear_discards_open_aggregated_record() {
+        let mut batch = KinesisBatch::default().with_aggregation(1024);
+        batch.add_record("pending".to_string()).unwrap();
+        assert!(!batch.is_empty());
+        batch.clear();
+        assert!(batch.is_empty());
+        assert!(batch.take_records().unwrap().is_empty());
+    }
+
+    // Builds an uncompressed otlp-stdout line holding one span of the given trace
+    fn otlp_line(trace_id: [u8; 16], name: &str) -> String {
+        use base64::{Engine, engine::general_purpose};
+        use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+        use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span};
+        use prost::Message;
+
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans {
+                    spans: vec![Span {
+                        trace_id: trace_id.to_vec(),
+                        span_id: vec![1; 8],
+                        name: name.to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        serde_json::json!({
+            "payload": general_purpose::STANDARD.encode(request.encode_to_vec()),
+            "base64": true,
+            "content-type": "application/x-protobuf"
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_trace_id_partition_keys() {
+        let generator = PartitionKeyGenerator::new(PartitionKeyStrategy::TraceId, 1);
+        let mut batch = KinesisBatch::new(generator);
+        batch.add_record(otlp_line([0xab; 16], "a")).unwrap();
+        batch.add_record(otlp_line([0xab; 16], "b")).unwrap();
+        batch.add_record("no trace here".to_string()).unwrap();
+
+        let keys: Vec<&str> = batch.records.iter().map(|r| r.partition_key()).collect();
+        assert_eq!(keys[0], "ab".repeat(16));
+        assert_eq!(keys[1], "ab".repeat(16));
+        assert!(Uuid::parse_str(keys[2]).is_ok());
+    }
+
+    #[test]
+    fn test_aggregation_groups_lines_by_trace() {
+        let generator = PartitionKeyGenerator::new(PartitionKeyStrategy::TraceId, 1);
+        let mut batch = KinesisBatch::new(generator).with_aggregation(64 * 1024);
+        batch.add_record(otlp_line([0x01; 16], "a")).unwrap();
+        batch.add_record(otlp_line([0x01; 16], "b")).unwrap();
+        batch.add_record(otlp_line([0x02; 16], "c")).unwrap();
+
+        let records = batch.take_records().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].partition_key(), "01".repeat(16));
+        assert_eq!(
+            std::str::from_utf8(records[0].data.as_ref())
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        assert_eq!(records[1].partition_key(), "02".repeat(16));
+    }
+
+    // Client whose requests fail right away: nothing listens on the endpoint
+    fn unreachable_client() -> KinesisClient {
+        use aws_sdk_kinesis::config::retry::RetryConfig;
+        use aws_sdk_kinesis::config::{BehaviorVersion, Credentials, Region};
+
+        let config = aws_sdk_kinesis::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("akid", "secret", None, None, "test"))
+            .endpoint_url("http://127.0.0.1:1")
+            .retry_config(RetryConfig::disabled())
+            .build();
+        KinesisClient::from_conf(config)
+    }
+
+    #[tokio::test]
+    async fn test_replayed_record_keeps_its_age_when_spooled_again() {
+        use std::time::Duration;
+
+        let path = std::env::temp_dir().join(format!(
+            "otlp-stdout-kinesis-extension-test-{}.spool",
+            Uuid::new_v4()
+        ));
+        let spool = Spool::new(path.clone(), 1_048_576, Duration::from_secs(60));
+        let mut stale = SpoolEntry::new("k".to_string(), b"stale".to_vec());
+        stale.spooled_at_ms -= 45_000;
+        spool.append(vec![stale]).await.unwrap();
+
+        let sink = KinesisSink::new(
+            unreachable_client(),
+            "stream".to_string(),
+            KinesisBatch::default(),
+            RetryPolicy::new(1, 0),
+            1,
+        )
+        .with_spool(spool);
+        // Replayed, fails again and goes back to the spool
+        assert!(sink.flush().await.is_err());
+        let stats = sink.spool.as_ref().unwrap().stats();
+        assert_eq!((stats.replayed, stats.spooled), (1, 2));
+
+        // Still 45s old, so a 30s age cap evicts it instead of replaying it
+        let shorter_age = Spool::new(path, 1_048_576, Duration::from_secs(30));
+        assert!(shorter_age.drain().await.unwrap().is_empty());
+        assert_eq!(shorter_age.stats().evicted, 1);
+    }
+
     #[test]
     fn test_clear_batch() {
         let mut batch = KinesisBatch::default();
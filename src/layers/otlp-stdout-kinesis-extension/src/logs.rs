@@ -0,0 +1,360 @@
+use crate::aggregation::{AttributeNaming, LogContext};
+use crate::otlp_parsing::{self, Signal};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use opentelemetry::KeyValue;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{AnyValue, InstrumentationScope, any_value};
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs, SeverityNumber};
+use opentelemetry_proto::transform::common::tonic::Attributes;
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::attribute as semconv;
+use prost::Message;
+use serde::Deserialize;
+use std::str::FromStr;
+
+// Upper bound on the encoded log records of a single line, well below the sink record limits
+const MAX_LOG_PAYLOAD_BYTES: usize = 512 * 1024;
+
+// Marker of otlp-stdout lines, which are never turned into log records themselves
+const OTLP_STDOUT_MARKER: &str = "__otel_otlp_stdout";
+
+/// The Telemetry API log streams that can be captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSource {
+    /// Logs the function writes to stdout and stderr.
+    Function,
+    /// Logs of the extensions, this one included.
+    Extension,
+}
+
+impl LogSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Function => "function",
+            Self::Extension => "extension",
+        }
+    }
+}
+
+impl FromStr for LogSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "function" => Ok(Self::Function),
+            "extension" => Ok(Self::Extension),
+            _ => Err(format!("Invalid log type: {s}")),
+        }
+    }
+}
+
+/// A `function` or `extension` record from the Telemetry API.
+///
+/// The record is a string with the text log format and an object with the JSON one, so it
+/// is kept as it came.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelemetryLog {
+    pub time: DateTime<Utc>,
+    #[serde(rename = "type")]
+    pub source: LogSource,
+    pub record: serde_json::Value,
+}
+
+impl TelemetryLog {
+    /// The request the line was logged for, if the line tells.
+    ///
+    /// JSON lines carry it in `requestId`. Text lines have no fields, but the runtimes
+    /// write it in one of their tab-separated columns.
+    pub fn request_id(&self) -> Option<&str> {
+        match &self.record {
+            serde_json::Value::Object(fields) => fields.get("requestId")?.as_str(),
+            serde_json::Value::String(line) => line
+                .split('\t')
+                .map(str::trim)
+                .find(|column| is_uuid(column)),
+            _ => None,
+        }
+    }
+
+    /// Whether the line is itself an otlp-stdout record, e.g. spans written to stdout or
+    /// this extension's stdout sink, which must not be captured again as a log.
+    pub fn is_otlp_stdout(&self) -> bool {
+        match &self.record {
+            serde_json::Value::String(line) => line.contains(OTLP_STDOUT_MARKER),
+            serde_json::Value::Object(fields) => fields.contains_key(OTLP_STDOUT_MARKER),
+            _ => false,
+        }
+    }
+
+    fn level(&self) -> Option<&str> {
+        match &self.record {
+            serde_json::Value::Object(fields) => fields.get("level")?.as_str(),
+            // e.g. "2024-01-01T00:00:00.000Z\t<request id>\tINFO\tmessage" (Node.js)
+            // or "[INFO]\t2024-01-01T00:00:00.000Z\t<request id>\tmessage" (Python)
+            serde_json::Value::String(line) => line
+                .split('\t')
+                .take(4)
+                .map(|column| column.trim().trim_start_matches('[').trim_end_matches(']'))
+                .find(|column| severity_number(column).is_some()),
+            _ => None,
+        }
+    }
+
+    fn body(&self) -> String {
+        match &self.record {
+            serde_json::Value::String(line) => line.trim_end().to_string(),
+            serde_json::Value::Object(fields) => match fields.get("message") {
+                Some(serde_json::Value::String(message)) => message.clone(),
+                Some(message) => message.to_string(),
+                None => self.record.to_string(),
+            },
+            other => other.to_string(),
+        }
+    }
+
+    /// Converts the line into an OTLP log record, stamped with `trace_context` (the trace
+    /// and span ID the invocation's logs belong to, and its trace flags) when known. `naming` picks the key of
+    /// the request ID attribute, as on the `Lambda/Invoke` span.
+    pub fn to_log_record(
+        &self,
+        trace_context: Option<LogContext>,
+        naming: AttributeNaming,
+    ) -> LogRecord {
+        let time_unix_nano = self
+            .time
+            .timestamp_nanos_opt()
+            .and_then(|nanos| u64::try_from(nanos).ok())
+            .unwrap_or_default();
+        let level = self.level();
+
+        let mut attributes = vec![KeyValue::new("lambda.log.type", self.source.as_str())];
+        if let Some(request_id) = self.request_id() {
+            let key = match naming {
+                AttributeNaming::SemConv => semconv::FAAS_INVOCATION_ID,
+                AttributeNaming::Legacy => "faas.execution",
+            };
+            attributes.push(KeyValue::new(key, request_id.to_string()));
+        }
+
+        let mut record = LogRecord {
+            time_unix_nano,
+            observed_time_unix_nano: time_unix_nano,
+            severity_number: level
+                .and_then(severity_number)
+                .unwrap_or(SeverityNumber::Unspecified) as i32,
+            severity_text: level.unwrap_or_default().to_string(),
+            body: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(self.body())),
+            }),
+            attributes: Attributes::from(attributes).0,
+            ..Default::default()
+        };
+        if let Some((trace_id, span_id, trace_flags)) = trace_context {
+            record.trace_id = trace_id.to_bytes().to_vec();
+            record.span_id = span_id.to_bytes().to_vec();
+            record.flags = u32::from(trace_flags.to_u8());
+        }
+        record
+    }
+}
+
+fn severity_number(level: &str) -> Option<SeverityNumber> {
+    match level.to_uppercase().as_str() {
+        "TRACE" => Some(SeverityNumber::Trace),
+        "DEBUG" => Some(SeverityNumber::Debug),
+        "INFO" => Some(SeverityNumber::Info),
+        "WARN" | "WARNING" => Some(SeverityNumber::Warn),
+        "ERROR" => Some(SeverityNumber::Error),
+        "FATAL" | "CRITICAL" => Some(SeverityNumber::Fatal),
+        _ => None,
+    }
+}
+
+// Request IDs are UUIDs: 8-4-4-4-12 hex digits
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Encodes log records into otlp-stdout lines tagged as logs, splitting them so that no
+/// line carries more than `MAX_LOG_PAYLOAD_BYTES` of records.
+pub fn encode_log_lines(records: Vec<LogRecord>, resource: &Resource) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+    for record in records {
+        let record_bytes = record.encoded_len();
+        if !chunk.is_empty() && chunk_bytes + record_bytes > MAX_LOG_PAYLOAD_BYTES {
+            lines.push(encode_log_line(std::mem::take(&mut chunk), resource)?);
+            chunk_bytes = 0;
+        }
+        chunk_bytes += record_bytes;
+        chunk.push(record);
+    }
+    if !chunk.is_empty() {
+        lines.push(encode_log_line(chunk, resource)?);
+    }
+    Ok(lines)
+}
+
+fn encode_log_line(log_records: Vec<LogRecord>, resource: &Resource) -> Result<String> {
+    let request = ExportLogsServiceRequest {
+        resource_logs: vec![ResourceLogs {
+            resource: Some(otlp_parsing::proto_resource(resource)),
+            scope_logs: vec![ScopeLogs {
+                scope: Some(InstrumentationScope {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    ..Default::default()
+                }),
+                log_records,
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }],
+    };
+    otlp_parsing::encode_line(Signal::Logs, &request.encode_to_vec(), resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use opentelemetry::trace::{SpanId, TraceFlags, TraceId};
+
+    const REQUEST_ID: &str = "8f507cfc-6e84-4a6f-9e1c-2d4c3f0b6d7a";
+
+    fn log(record: serde_json::Value) -> TelemetryLog {
+        TelemetryLog {
+            time: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            source: LogSource::Function,
+            record,
+        }
+    }
+
+    #[test]
+    fn test_text_line_to_log_record() {
+        let line = format!("2024-01-01T00:00:00.000Z\t{REQUEST_ID}\tWARN\tdisk almost full\n");
+        let log = log(serde_json::Value::String(line));
+        assert_eq!(log.request_id(), Some(REQUEST_ID));
+
+        let trace_id = TraceId::from_bytes([1; 16]);
+        let span_id = SpanId::from_bytes([2; 8]);
+        let record = log.to_log_record(
+            Some((trace_id, span_id, TraceFlags::SAMPLED)),
+            AttributeNaming::default(),
+        );
+
+        assert_eq!(record.severity_number, SeverityNumber::Warn as i32);
+        assert_eq!(record.severity_text, "WARN");
+        assert_eq!(record.trace_id, vec![1; 16]);
+        assert_eq!(record.span_id, vec![2; 8]);
+        assert_eq!(record.flags, 1);
+        assert_eq!(record.time_unix_nano, 1_704_067_200_000_000_000);
+        assert!(matches!(
+            record.body.and_then(|body| body.value),
+            Some(any_value::Value::StringValue(body)) if body.ends_with("disk almost full")
+        ));
+    }
+
+    #[test]
+    fn test_log_record_carries_context_trace_flags() {
+        let line = format!("2024-01-01T00:00:00.000Z\t{REQUEST_ID}\tINFO\tnot sampled\n");
+        let log = log(serde_json::Value::String(line));
+        let context = (
+            TraceId::from_bytes([1; 16]),
+            SpanId::from_bytes([2; 8]),
+            TraceFlags::NOT_SAMPLED,
+        );
+
+        let record = log.to_log_record(Some(context), AttributeNaming::default());
+        assert_eq!(record.trace_id, vec![1; 16]);
+        assert_eq!(record.flags, 0);
+    }
+
+    #[test]
+    fn test_json_line_to_log_record() {
+        let log = log(serde_json::json!({
+            "timestamp": "2024-01-01T00:00:00Z",
+            "level": "ERROR",
+            "requestId": REQUEST_ID,
+            "message": "boom",
+        }));
+        assert_eq!(log.request_id(), Some(REQUEST_ID));
+
+        let record = log.to_log_record(None, AttributeNaming::SemConv);
+        assert_eq!(record.severity_number, SeverityNumber::Error as i32);
+        assert!(record.trace_id.is_empty());
+        assert!(matches!(
+            record.body.and_then(|body| body.value),
+            Some(any_value::Value::StringValue(body)) if body == "boom"
+        ));
+        assert!(
+            record
+                .attributes
+                .iter()
+                .any(|kv| kv.key == semconv::FAAS_INVOCATION_ID)
+        );
+
+        // Legacy naming keeps the key existing log dashboards filter on
+        let record = log.to_log_record(None, AttributeNaming::Legacy);
+        let keys: Vec<&str> = record.attributes.iter().map(|kv| kv.key.as_str()).collect();
+        assert!(keys.contains(&"faas.execution"));
+        assert!(!keys.contains(&semconv::FAAS_INVOCATION_ID));
+    }
+
+    #[test]
+    fn test_line_without_request_id_or_level() {
+        let log = log(serde_json::Value::String("START of something".to_string()));
+        assert_eq!(log.request_id(), None);
+        let record = log.to_log_record(None, AttributeNaming::default());
+        assert_eq!(record.severity_number, SeverityNumber::Unspecified as i32);
+    }
+
+    #[test]
+    fn test_otlp_stdout_lines_are_recognized() {
+        let line = r#"{"__otel_otlp_stdout":"0.16.0","payload":""}"#;
+        assert!(log(serde_json::Value::String(line.to_string())).is_otlp_stdout());
+        assert!(!log(serde_json::Value::String("hello".to_string())).is_otlp_stdout());
+    }
+
+    #[test]
+    fn test_encode_log_lines() {
+        let resource = Resource::builder_empty()
+            .with_attribute(KeyValue::new("service.name", "test"))
+            .build();
+        let records = (0..3)
+            .map(|_| {
+                log(serde_json::Value::String("hello".to_string()))
+                    .to_log_record(None, AttributeNaming::default())
+            })
+            .collect();
+
+        let lines = encode_log_lines(records, &resource).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(otlp_parsing::detect_signal(&lines[0]), Signal::Logs);
+
+        let payload = otlp_parsing::decode_otlp_payload(&lines[0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload.signal, Signal::Logs);
+        let request = ExportLogsServiceRequest::decode(payload.bytes.as_slice()).unwrap();
+        assert_eq!(request.resource_logs[0].scope_logs[0].log_records.len(), 3);
+
+        let big_record = LogRecord {
+            body: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(
+                    "x".repeat(MAX_LOG_PAYLOAD_BYTES / 3),
+                )),
+            }),
+            ..Default::default()
+        };
+        let lines = encode_log_lines(vec![big_record; 3], &resource).unwrap();
+        assert_eq!(lines.len(), 2);
+    }
+}
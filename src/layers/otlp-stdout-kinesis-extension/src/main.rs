@@ -1,10 +1,9 @@
-use aws_sdk_kinesis::Client as KinesisClient;
 use lambda_extension::{
     Error, Extension, LambdaEvent, LambdaTelemetry, LambdaTelemetryRecord, LogBuffering, NextEvent,
-    SharedService, service_fn, tracing,
+    service_fn, tracing,
 };
-use opentelemetry::{Value as OtelValue, trace::SpanId};
-use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+use opentelemetry::Value as OtelValue;
+use opentelemetry::trace::TraceFlags;
 
 // Add nix for mkfifo (Re-add these)
 use nix::errno::Errno;
@@ -13,30 +12,51 @@ use nix::unistd::mkfifo;
 use std::path::Path;
 
 use lambda_otel_lite::resource::get_lambda_resource;
-use opentelemetry::trace::TraceId;
+use opentelemetry_sdk::Resource;
 use otlp_stdout_span_exporter::{BufferOutput, OtlpStdoutSpanExporter};
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Instant, SystemTime};
-use tokio::sync::{Mutex, mpsc};
-
-// Import for pipe reading
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+use tokio::sync::{Mutex, mpsc, watch};
 
 // Add the modules
 mod aggregation;
+mod batching;
 mod config;
 mod events;
+mod export;
+mod firehose;
+mod init_phase;
 mod kinesis;
+mod logs;
+mod metrics;
+mod otlp_http;
 mod otlp_parsing;
+mod partition_key;
+mod pipe;
+mod proto_scan;
+mod sink;
+mod spool;
+mod telemetry_api;
+mod trace_context;
+mod trace_map;
 mod types;
 
 // Use the types from the modules
-use aggregation::SpanAggregator;
+use aggregation::{LogContext, SpanAggregator};
 use config::Config;
 use events::{ParsedPlatformEvent, PlatformEventData, TelemetrySpan};
-use kinesis::KinesisBatch;
+use export::{export_logs_to_sink, export_metrics_to_sink, export_to_sink};
+use init_phase::InitPhaseInfo;
+use logs::LogSource;
+use metrics::MetricsRecorder;
+use otlp_parsing::Signal;
+use pipe::PipeEvent;
+use sink::Sink;
+use telemetry_api::TelemetryRecord;
+use trace_context::ParentContext;
+use trace_map::TraceContextMap;
 use types::ProcessorInput;
 
 // Re-add chrono for timeout logic
@@ -47,232 +67,257 @@ const PIPE_PATH: &str = "/tmp/otlp-stdout-span-exporter.pipe";
 
 // Application state
 struct AppState {
-    kinesis_client: KinesisClient,
-    stream_name: Option<String>,
-    batch: Mutex<KinesisBatch>,
+    sink: Box<dyn Sink>,
     aggregations: Mutex<HashMap<String, SpanAggregator>>,
+    emitted_requests: Mutex<aggregation::Tombstones>,
     exporter: OtlpStdoutSpanExporter,
     internal_exporter_buffer: Arc<BufferOutput>,
+    resource: Resource,
     processor_input_rx: Mutex<mpsc::Receiver<ProcessorInput>>,
-    execution_trace_map: Mutex<HashMap<String, (TraceId, SpanId, Instant)>>,
-    init_start_time: Mutex<Option<SystemTime>>,
+    pipe_rx: Mutex<mpsc::Receiver<PipeEvent>>,
+    // Set once a writer opened the named pipe
+    pipe_writer_seen: Arc<AtomicBool>,
+    // Request ID of the latest platform.runtimeDone, which ends the pipe wait of functions
+    // that don't write to the pipe
+    runtime_done_rx: watch::Receiver<Option<String>>,
+    runtime_done_subscribed: bool,
+    execution_trace_map: Mutex<TraceContextMap>,
+    // Trace context each invocation's log records are stamped with, once its parent is resolved
+    log_trace_map: Mutex<TraceContextMap<LogContext>>,
+    invoked_arns: Mutex<aggregation::InvokedArns>,
+    init_phases: Mutex<Vec<InitPhaseInfo>>,
+    max_aggregations: usize,
+    aggregation_options: aggregation::AggregationOptions,
+    lifetimes: config::Lifetimes,
+    // Set from the first INVOKE's deadline
+    observed_function_timeout_secs: OnceLock<u64>,
+    // Present when metrics are enabled
+    metrics: Option<Mutex<MetricsRecorder>>,
+    dropped_telemetry: Arc<AtomicU64>,
 }
-impl AppState {
-    async fn flush_batch(&self) -> Result<(), Error> {
-        if self.stream_name.is_none() {
-            tracing::debug!("Kinesis stream not configured, skipping flush.");
-            let mut batch = self.batch.lock().await;
-            if !batch.is_empty() {
-                tracing::warn!(
-                    "Clearing {} records from Kinesis batch because Kinesis is disabled.",
-                    batch.records.len()
-                );
-                batch.clear();
-            }
-            return Ok(());
-        }
-        let stream_name = self.stream_name.as_ref().unwrap();
 
-        let mut batch = self.batch.lock().await;
-        if batch.is_empty() {
-            return Ok(());
+/// Hands an input to the processor without waiting, so a slow processor never stalls
+/// Telemetry API deliveries. Inputs that don't fit in the channel are dropped and counted.
+fn forward_to_processor(
+    tx: &mpsc::Sender<ProcessorInput>,
+    input: ProcessorInput,
+    dropped: &AtomicU64,
+) {
+    match tx.try_send(input) {
+        Ok(()) => {}
+        Err(mpsc::error::TrySendError::Full(input)) => {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("Processor channel full, dropping {:?}", input);
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => {
+            tracing::error!("Failed to send to processor channel: channel closed");
         }
+    }
+}
 
-        tracing::debug!(
-            "Sending batch of {} records to Kinesis stream {}",
-            batch.records.len(),
-            stream_name
-        );
-
-        let result = self
-            .kinesis_client
-            .put_records()
-            .stream_name(stream_name)
-            .set_records(Some(batch.records.clone()))
-            .send()
-            .await
-            .map_err(|e| {
-                tracing::error!("Kinesis batch error: {}", e);
-                Error::from(format!("Failed to send records to Kinesis: {}", e))
-            })?;
-
-        let failed_count = result.failed_record_count.unwrap_or(0);
-        if failed_count > 0 {
-            tracing::warn!("Failed to put {} records", failed_count);
-            let records = result.records();
-            for (i, record) in records.iter().enumerate() {
-                if let Some(error_code) = &record.error_code {
-                    tracing::warn!(
-                        "Record {} failed with error: {} - {}",
-                        i,
-                        error_code,
-                        record
-                            .error_message
-                            .as_deref()
-                            .unwrap_or("No error message")
-                    );
+/// The trace context a log record of `request_id` is stamped with: the one its aggregation
+/// resolved, or the function's entry span while no platform event has resolved one yet.
+fn log_trace_context(
+    log_map: &TraceContextMap<LogContext>,
+    trace_map: &TraceContextMap,
+    request_id: &str,
+) -> Option<LogContext> {
+    log_map.get(request_id).or_else(|| {
+        // The entry span was exported through the pipe, so its trace was sampled
+        trace_map
+            .get(request_id)
+            .map(|(trace_id, span_id)| (trace_id, span_id, TraceFlags::SAMPLED))
+    })
+}
+
+fn telemetry_handler(
+    mut records: Vec<TelemetryRecord>,
+    tx: &mpsc::Sender<ProcessorInput>,
+    runtime_done: &watch::Sender<Option<String>>,
+    dropped: &AtomicU64,
+) {
+    // A batch isn't guaranteed to be in order; aggregation expects each report last
+    records.sort_by_key(TelemetryRecord::time);
+    for record in records {
+        let input = match record {
+            // Not logged one by one: with extension logs captured, each line would come back
+            // as another log record
+            TelemetryRecord::Log(log) if log.is_otlp_stdout() => None,
+            TelemetryRecord::Log(log) => Some(ProcessorInput::Log(log)),
+            TelemetryRecord::Lambda(event) => {
+                tracing::debug!("Received event: {:?}", event);
+                let parsed = parse_platform_event(event);
+                if let Some(event) = &parsed {
+                    if matches!(event.data, PlatformEventData::RuntimeDone { .. }) {
+                        runtime_done.send_replace(Some(event.request_id.clone()));
+                    }
                 }
+                parsed.map(ProcessorInput::PlatformTelemetry)
             }
-        } else {
-            tracing::debug!("Successfully sent all records to Kinesis");
-        }
+            TelemetryRecord::Restore(event) => {
+                tracing::debug!("Received event: {:?}", event);
+                Some(ProcessorInput::PlatformTelemetry(event.into()))
+            }
+        };
 
-        batch.clear();
-        Ok(())
+        // Send the message (if any was created)
+        if let Some(input) = input {
+            forward_to_processor(tx, input, dropped);
+        }
     }
 }
 
-async fn telemetry_handler(
-    events: Vec<LambdaTelemetry>,
-    tx: mpsc::Sender<ProcessorInput>,
-) -> Result<(), Error> {
-    for event in events {
-        let timestamp = event.time;
-        tracing::debug!("Received event: {:?}", event);
-        let parsed_event_opt = match event.record {
-            // --- Add Case for PlatformInitStart --- START ---
-            LambdaTelemetryRecord::PlatformInitStart {
-                initialization_type:_, // Ignore initialization_type for now
-                phase:_, // Ignore phase for now
-                .. // Ignore other fields like runtime_version for now
-            } => {
-                // TODO removed - InitStart variant needs no fields currently
-                Some(ParsedPlatformEvent {
-                    timestamp,
-                    // PlatformInitStart doesn't have a request_id, use an empty string for now.
-                    request_id: "".to_string(),
-                    data: PlatformEventData::InitStart {
-                        // No fields needed
-                     },
-                })
-            }
-            // --- Add Case for PlatformInitStart --- END ---
-            LambdaTelemetryRecord::PlatformStart {
-                request_id,
-                version,
-                tracing: _, // Ignore tracing field
-            } => {
-                // No need to parse X-Ray header since we'll correlate via the execution_trace_map
-                Some(ParsedPlatformEvent {
-                    timestamp,
-                    request_id,
-                    data: PlatformEventData::Start {
-                        version,
-                        // trace_context field removed
-                    },
-                })
-            }
-            LambdaTelemetryRecord::PlatformRuntimeDone {
-                request_id,
+fn parse_platform_event(event: LambdaTelemetry) -> Option<ParsedPlatformEvent> {
+    let timestamp = event.time;
+    match event.record {
+        // --- Init phase events (no request_id, use an empty string) --- START ---
+        LambdaTelemetryRecord::PlatformInitStart {
+            initialization_type,
+            phase,
+            runtime_version,
+            runtime_version_arn,
+        } => Some(ParsedPlatformEvent {
+            timestamp,
+            request_id: "".to_string(),
+            data: PlatformEventData::InitStart {
+                initialization_type,
+                phase,
+                runtime_version,
+                runtime_version_arn,
+            },
+        }),
+        LambdaTelemetryRecord::PlatformInitRuntimeDone {
+            initialization_type,
+            phase,
+            status,
+            error_type,
+            spans: _, // Ignore spans field
+        } => Some(ParsedPlatformEvent {
+            timestamp,
+            request_id: "".to_string(),
+            data: PlatformEventData::InitRuntimeDone {
+                initialization_type,
+                phase,
                 status,
                 error_type,
-                metrics,
-                spans,
-                tracing: _, // Ignore tracing field
-            } => {
-                // No need to parse X-Ray header since we'll correlate via the execution_trace_map
-                let telemetry_spans = spans.into_iter().map(TelemetrySpan::from).collect();
-                let mut attributes = HashMap::new();
-                if let Some(m) = metrics {
+            },
+        }),
+        LambdaTelemetryRecord::PlatformInitReport {
+            initialization_type,
+            phase,
+            metrics,
+            spans: _, // Ignore spans field
+        } => Some(ParsedPlatformEvent {
+            timestamp,
+            request_id: "".to_string(),
+            data: PlatformEventData::InitReport {
+                initialization_type,
+                phase,
+                duration_ms: metrics.duration_ms,
+            },
+        }),
+        // --- Init phase events --- END ---
+        LambdaTelemetryRecord::PlatformStart {
+            request_id,
+            version,
+            tracing,
+        } => Some(ParsedPlatformEvent {
+            timestamp,
+            request_id,
+            data: PlatformEventData::Start {
+                version,
+                trace_context: tracing
+                    .as_ref()
+                    .and_then(ParentContext::from_lambda_tracing),
+            },
+        }),
+        LambdaTelemetryRecord::PlatformRuntimeDone {
+            request_id,
+            status,
+            error_type,
+            metrics,
+            spans,
+            tracing,
+        } => {
+            let telemetry_spans = spans.into_iter().map(TelemetrySpan::from).collect();
+            let mut attributes = HashMap::new();
+            if let Some(m) = metrics {
+                attributes.insert(
+                    "runtime.durationMs".to_string(),
+                    OtelValue::F64(m.duration_ms),
+                );
+                if let Some(pb) = m.produced_bytes {
                     attributes.insert(
-                        "runtime.durationMs".to_string(),
-                        OtelValue::F64(m.duration_ms),
+                        "runtime.producedBytes".to_string(),
+                        OtelValue::I64(pb as i64),
                     );
-                    if let Some(pb) = m.produced_bytes {
-                        attributes.insert(
-                            "runtime.producedBytes".to_string(),
-                            OtelValue::I64(pb as i64),
-                        );
-                    }
                 }
-
-                Some(ParsedPlatformEvent {
-                    timestamp,
-                    request_id,
-                    data: PlatformEventData::RuntimeDone {
-                        status,
-                        error_type,
-                        metrics: attributes,
-                        spans: telemetry_spans,
-                        // trace_context field removed
-                    },
-                })
             }
-            LambdaTelemetryRecord::PlatformReport {
-                request_id,
-                status,
-                error_type,
-                metrics,
-                spans,
-                tracing: _, // Ignore tracing field
-            } => {
-                // Check for init duration metric BEFORE creating the ParsedPlatformEvent
-                let init_duration_ms_opt = metrics.init_duration_ms;
-
-                // Create the normal ParsedPlatformEvent
-                let telemetry_spans = spans.into_iter().map(TelemetrySpan::from).collect();
-                let mut attributes = HashMap::new();
-                attributes.insert(
-                    "report.durationMs".to_string(),
-                    OtelValue::F64(metrics.duration_ms),
-                );
-                attributes.insert(
-                    "report.billedDurationMs".to_string(),
-                    OtelValue::I64(metrics.billed_duration_ms as i64),
-                );
-                attributes.insert(
-                    "report.memorySizeMB".to_string(),
-                    OtelValue::I64(metrics.memory_size_mb as i64),
-                );
-                attributes.insert(
-                    "report.maxMemoryUsedMB".to_string(),
-                    OtelValue::I64(metrics.max_memory_used_mb as i64),
-                );
-                if let Some(id) = metrics.init_duration_ms {
-                    attributes.insert("report.initDurationMs".to_string(), OtelValue::F64(id));
-                }
-                if let Some(rd) = metrics.restore_duration_ms {
-                    attributes.insert("report.restoreDurationMs".to_string(), OtelValue::F64(rd));
-                }
 
-                let parsed_event = ParsedPlatformEvent {
-                    timestamp,
-                    request_id: request_id.clone(), // Clone request_id here
-                    data: PlatformEventData::Report {
-                        status,
-                        error_type,
-                        metrics: attributes,
-                        spans: telemetry_spans,
-                    },
-                };
-
-                // Send the InitDataAvailable message IF init duration was present
-                if let Some(init_duration_ms) = init_duration_ms_opt {
-                     tracing::debug!(request_id = %request_id, init_duration_ms, "Found init duration in report, sending InitDataAvailable message");
-                     if let Err(e) = tx.send(ProcessorInput::InitDataAvailable { request_id: request_id.clone(), init_duration_ms }).await {
-                         tracing::error!("Failed to send InitDataAvailable to processor channel: {}", e);
-                     }
-                 }
-
-                // Return the normal parsed event to be sent as PlatformTelemetry
-                Some(parsed_event)
+            Some(ParsedPlatformEvent {
+                timestamp,
+                request_id,
+                data: PlatformEventData::RuntimeDone {
+                    status,
+                    error_type,
+                    metrics: attributes,
+                    spans: telemetry_spans,
+                    trace_context: tracing
+                        .as_ref()
+                        .and_then(ParentContext::from_lambda_tracing),
+                },
+            })
+        }
+        LambdaTelemetryRecord::PlatformReport {
+            request_id,
+            status,
+            error_type,
+            metrics,
+            spans,
+            tracing,
+        } => {
+            let telemetry_spans = spans.into_iter().map(TelemetrySpan::from).collect();
+            let mut attributes = HashMap::new();
+            attributes.insert(
+                "report.durationMs".to_string(),
+                OtelValue::F64(metrics.duration_ms),
+            );
+            attributes.insert(
+                "report.billedDurationMs".to_string(),
+                OtelValue::I64(metrics.billed_duration_ms as i64),
+            );
+            attributes.insert(
+                "report.memorySizeMB".to_string(),
+                OtelValue::I64(metrics.memory_size_mb as i64),
+            );
+            attributes.insert(
+                "report.maxMemoryUsedMB".to_string(),
+                OtelValue::I64(metrics.max_memory_used_mb as i64),
+            );
+            if let Some(id) = metrics.init_duration_ms {
+                attributes.insert("report.initDurationMs".to_string(), OtelValue::F64(id));
             }
-            // Ignore all init phase and other events
-            _ => None,
-        };
-
-        // Send the PlatformTelemetry message (if any was created)
-        if let Some(parsed_event) = parsed_event_opt {
-            if let Err(e) = tx
-                .send(ProcessorInput::PlatformTelemetry(parsed_event))
-                .await
-            {
-                tracing::error!("Failed to send platform event to processor channel: {}", e);
+            if let Some(rd) = metrics.restore_duration_ms {
+                attributes.insert("report.restoreDurationMs".to_string(), OtelValue::F64(rd));
             }
+
+            Some(ParsedPlatformEvent {
+                timestamp,
+                request_id,
+                data: PlatformEventData::Report {
+                    status,
+                    error_type,
+                    metrics: attributes,
+                    spans: telemetry_spans,
+                    trace_context: tracing
+                        .as_ref()
+                        .and_then(ParentContext::from_lambda_tracing),
+                },
+            })
         }
+        // Ignore all other events
+        _ => None,
     }
-
-    Ok(())
 }
 
 #[tokio::main]
@@ -305,51 +350,84 @@ async fn main() -> Result<(), Error> {
     }
     // --- Create Named Pipe --- END ---
 
+    // Read the pipe continuously so the function never blocks on a full pipe between INVOKEs
+    let pipe_writer_seen = Arc::new(AtomicBool::new(false));
+    let pipe_rx = pipe::spawn_reader(pipe_path.to_path_buf(), pipe_writer_seen.clone());
+
     let config = Config::from_env()?;
 
-    let aws_config = aws_config::from_env().load().await;
-    let kinesis_client = KinesisClient::new(&aws_config);
+    let sink = sink::build_sink(&config).await?;
 
     // --- Create Channel for Platform Telemetry ---
-    let (telemetry_tx, telemetry_rx) = mpsc::channel::<ProcessorInput>(2048);
+    let (telemetry_tx, telemetry_rx) =
+        mpsc::channel::<ProcessorInput>(config.telemetry_channel_capacity);
+    let dropped_telemetry = Arc::new(AtomicU64::new(0));
+    let (runtime_done_tx, runtime_done_rx) = watch::channel(None::<String>);
     // --- Create Channel for Platform Telemetry --- END ---
 
     // Create the buffer for the internal exporter
     let internal_exporter_buffer = Arc::new(BufferOutput::new());
 
+    let resource = get_lambda_resource();
     let exporter = OtlpStdoutSpanExporter::builder()
-        .resource(get_lambda_resource())
+        .resource(resource.clone())
         .output(internal_exporter_buffer.clone())
         .build();
 
     let aggregations = Mutex::new(HashMap::<String, SpanAggregator>::new());
-    let execution_trace_map = Mutex::new(HashMap::<String, (TraceId, SpanId, Instant)>::new());
-    let init_start_time = Mutex::new(None::<SystemTime>);
+    let execution_trace_map = Mutex::new(TraceContextMap::new(config.max_trace_map_entries));
+    let init_phases = Mutex::new(Vec::<InitPhaseInfo>::new());
+    let metrics = (config.enable_metrics && config.enable_platform_telemetry).then(|| {
+        let interval = i64::try_from(config.metrics_interval_secs)
+            .ok()
+            .and_then(Duration::try_seconds)
+            .unwrap_or(Duration::MAX);
+        Mutex::new(MetricsRecorder::new(Utc::now(), interval))
+    });
 
     let app_state = Arc::new(AppState {
-        kinesis_client,
-        stream_name: config.kinesis_stream_name.clone(),
-        batch: Mutex::new(KinesisBatch::default()),
+        sink,
         aggregations,
+        emitted_requests: Mutex::new(aggregation::Tombstones::new(
+            aggregation::TOMBSTONE_CAPACITY,
+        )),
         exporter,
         internal_exporter_buffer: internal_exporter_buffer.clone(),
+        resource,
         processor_input_rx: Mutex::new(telemetry_rx),
+        pipe_rx: Mutex::new(pipe_rx),
+        pipe_writer_seen,
+        runtime_done_rx,
+        runtime_done_subscribed: config.enable_platform_telemetry,
         execution_trace_map,
-        init_start_time,
+        log_trace_map: Mutex::new(TraceContextMap::new(config.max_trace_map_entries)),
+        invoked_arns: Mutex::new(aggregation::InvokedArns::new(config.max_trace_map_entries)),
+        init_phases,
+        max_aggregations: config.max_aggregations,
+        aggregation_options: aggregation::AggregationOptions {
+            init_span_mode: config.init_span_mode,
+            trace_context_precedence: config.trace_context_precedence,
+            generate_traces: config.generate_traces,
+            attribute_naming: config.attribute_naming,
+        },
+        lifetimes: config.lifetimes,
+        observed_function_timeout_secs: OnceLock::new(),
+        metrics,
+        dropped_telemetry: dropped_telemetry.clone(),
     });
 
     let telemetry_tx_clone = telemetry_tx.clone();
-    let telemetry_handler_fn = move |events: Vec<LambdaTelemetry>| {
-        let tx = telemetry_tx_clone.clone();
-        async move { telemetry_handler(events, tx).await }
+    let telemetry_handler_fn = move |records: Vec<TelemetryRecord>| {
+        telemetry_handler(
+            records,
+            &telemetry_tx_clone,
+            &runtime_done_tx,
+            &dropped_telemetry,
+        )
     };
 
     let processor_state = app_state.clone();
 
-    // Define timeout duration (e.g., 30 minutes)
-    // TODO: Make this configurable?
-    let aggregation_timeout = Duration::try_minutes(30).unwrap_or(Duration::MAX);
-
     let events_processor = service_fn(move |event: LambdaEvent| {
         let state = processor_state.clone();
 
@@ -358,102 +436,145 @@ async fn main() -> Result<(), Error> {
                 NextEvent::Invoke(invoke_event) => {
                     let current_request_id = invoke_event.request_id.clone(); // Get request_id
                     tracing::debug!(request_id = %current_request_id, "Received INVOKE event, processing pipe data and platform telemetry");
+                    state.sink.set_deadline(invoke_event.deadline_ms);
+                    state.invoked_arns.lock().await.insert(
+                        current_request_id.clone(),
+                        invoke_event.invoked_function_arn.clone(),
+                    );
+
+                    let received_ms = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    if state.observed_function_timeout_secs.get().is_none() {
+                        if let Some(secs) = config::function_timeout_from_deadline(
+                            invoke_event.deadline_ms,
+                            received_ms,
+                        ) {
+                            tracing::debug!(
+                                "Function timeout of {}s observed on first INVOKE",
+                                secs
+                            );
+                            let _ = state.observed_function_timeout_secs.set(secs);
+                        }
+                    }
+                    let (aggregation_timeout_secs, trace_map_ttl_secs) = state
+                        .lifetimes
+                        .resolve(state.observed_function_timeout_secs.get().copied());
+                    let aggregation_timeout = i64::try_from(aggregation_timeout_secs)
+                        .ok()
+                        .and_then(Duration::try_seconds)
+                        .unwrap_or(Duration::MAX);
+                    let trace_map_ttl = std::time::Duration::from_secs(trace_map_ttl_secs);
+
+                    // --- Wait for this invocation's pipe data --- START ---
+                    let timeout = pipe::boundary_timeout(invoke_event.deadline_ms, received_ms);
+                    let wait = pipe::PipeWait::choose(
+                        state.pipe_writer_seen.load(Ordering::Relaxed),
+                        state.aggregation_options.generate_traces,
+                        state.runtime_done_subscribed,
+                    );
+                    let mut runtime_done_rx = state.runtime_done_rx.clone();
+                    let runtime_done = async {
+                        let _ = runtime_done_rx
+                            .wait_for(|id| id.as_deref() == Some(current_request_id.as_str()))
+                            .await;
+                    };
+                    let pipe_batch = {
+                        let mut pipe_rx = state.pipe_rx.lock().await;
+                        pipe::recv_for_invoke(&mut pipe_rx, wait, timeout, runtime_done).await
+                    };
+                    if pipe_batch.complete {
+                        tracing::debug!(
+                            "Flush boundary reached on named pipe for request_id {} - all spans for this invocation processed",
+                            current_request_id
+                        );
+                    } else if wait != pipe::PipeWait::Boundary {
+                        tracing::debug!(
+                            request_id = %current_request_id,
+                            lines = pipe_batch.lines.len(),
+                            ?wait,
+                            "Invocation done without a flush boundary on named pipe"
+                        );
+                    } else {
+                        tracing::warn!(
+                            request_id = %current_request_id,
+                            lines = pipe_batch.lines.len(),
+                            "No flush boundary on named pipe before the deadline, forwarding partial data"
+                        );
+                    }
 
-                    // --- Read from pipe until EOF --- START ---
                     let mut found_trace_info_for_invoke = false; // Flag to parse only once
-                    match File::open(PIPE_PATH).await {
-                        Ok(pipe_file) => {
-                            tracing::debug!("Named pipe opened successfully: {}", PIPE_PATH);
-                            let mut reader = BufReader::new(pipe_file);
-                            let mut line_buffer = String::new();
-
-                            // Read lines from the pipe until EOF
-                            loop {
-                                match reader.read_line(&mut line_buffer).await {
-                                    Ok(0) => {
-                                        tracing::debug!(
-                                            "EOF reached on named pipe for request_id {} - all spans for this invocation processed",
-                                            current_request_id
-                                        );
-                                        break; // Exit the pipe reading loop
-                                    }
-                                    Ok(_) => {
-                                        let line = line_buffer.trim_end();
-                                        if !line.is_empty() {
-                                            // --- Attempt to extract trace info ONCE per invoke --- START ---
-                                            if !found_trace_info_for_invoke {
-                                                match otlp_parsing::extract_trace_info_from_json_line(line) {
-                                                    Ok(Some((trace_id, span_id))) => {
-                                                        tracing::debug!(%trace_id, %span_id, request_id = %current_request_id, "Storing trace info mapping");
-                                                        let mut map = state.execution_trace_map.lock().await;
-                                                        map.insert(current_request_id.clone(), (trace_id, span_id, Instant::now()));
-                                                        drop(map);
-                                                        found_trace_info_for_invoke = true; // Mark as found
-                                                    }
-                                                    Ok(None) => {
-                                                        // Line was valid JSON but not OTLP trace data, or no spans found. Ignore for mapping.
-                                                        tracing::trace!("Line did not yield trace info for mapping.");
-                                                    }
-                                                    Err(e) => {
-                                                        // Parsing/decoding error, log it but don't stop processing lines
-                                                        tracing::warn!(error = %e, request_id = %current_request_id, "Error extracting trace info from line");
-                                                        // Potentially mark found_trace_info_for_invoke = true here too,
-                                                        // if we want to stop trying after the first error?
-                                                        // For now, let's keep trying on subsequent lines just in case.
-                                                    }
-                                                }
-                                            }
-                                            // --- Attempt to extract trace info ONCE per invoke --- END ---
-
-                                            // Existing Kinesis/stdout forwarding logic
-                                            if state.stream_name.is_some() {
-                                                let mut kinesis_batch = state.batch.lock().await;
-                                                if let Err(e) =
-                                                    kinesis_batch.add_record(line.to_string())
-                                                {
-                                                    tracing::error!(error = %e, "Failed to add record to Kinesis batch");
-                                                }
-                                            } else {
-                                                // Maybe use tokio::io::stdout().write_all(line.as_bytes()).await? Careful with async in sync context if not.
-                                                // For simplicity, using println! which is blocking but often acceptable in Lambda extensions for low volume.
-                                                println!("{}", line);
-                                            }
-                                        }
-                                        line_buffer.clear();
-                                    }
-                                    Err(e) => {
-                                        tracing::error!(error = %e, path = PIPE_PATH, "Error reading line from named pipe");
-                                        break; // Break on error
-                                    }
+                    for line in pipe_batch.lines {
+                        // Tag log and metric lines so they are routed to the right endpoint
+                        let (signal, line) = otlp_parsing::classify_line(line);
+
+                        // --- Attempt to extract trace info ONCE per invoke --- START ---
+                        if signal == Signal::Traces && !found_trace_info_for_invoke {
+                            match otlp_parsing::extract_trace_info_from_json_line(&line) {
+                                Ok(Some((trace_id, span_id))) => {
+                                    tracing::debug!(%trace_id, %span_id, request_id = %current_request_id, "Storing trace info mapping");
+                                    let mut map = state.execution_trace_map.lock().await;
+                                    map.insert(current_request_id.clone(), (trace_id, span_id));
+                                    drop(map);
+                                    found_trace_info_for_invoke = true; // Mark as found
+                                }
+                                Ok(None) => {
+                                    // Line was valid JSON but not OTLP trace data, or no spans found. Ignore for mapping.
+                                    tracing::trace!("Line did not yield trace info for mapping.");
+                                }
+                                Err(e) => {
+                                    // Parsing/decoding error, log it but don't stop processing lines
+                                    tracing::warn!(error = %e, request_id = %current_request_id, "Error extracting trace info from line");
                                 }
                             }
                         }
-                        Err(e) => {
-                            tracing::error!(error = %e, path = PIPE_PATH, "Failed to open named pipe for reading");
+                        // --- Attempt to extract trace info ONCE per invoke --- END ---
+
+                        // Hand the line over to the configured sink
+                        if let Err(e) = state.sink.write_line(line).await {
+                            tracing::error!(error = %e, sink = state.sink.name(), "Failed to write record to sink");
                         }
                     }
-                    // --- Read from pipe until EOF --- END ---
+                    // --- Wait for this invocation's pipe data --- END ---
 
                     // --- Process any platform telemetry that was received --- START ---
                     // Drain the platform telemetry channel (non-blocking)
+                    let mut log_records = Vec::new();
                     loop {
                         let mut receiver_guard = state.processor_input_rx.lock().await;
                         match receiver_guard.try_recv() {
+                            Ok(ProcessorInput::Log(log)) => {
+                                drop(receiver_guard);
+                                // Stamp the record with its invocation's trace, when known
+                                let trace_info = match log.request_id() {
+                                    Some(request_id) => {
+                                        let trace_map = state.execution_trace_map.lock().await;
+                                        let log_map = state.log_trace_map.lock().await;
+                                        log_trace_context(&log_map, &trace_map, request_id)
+                                    }
+                                    None => None,
+                                };
+                                log_records.push(log.to_log_record(
+                                    trace_info,
+                                    state.aggregation_options.attribute_naming,
+                                ));
+                            }
                             Ok(ProcessorInput::PlatformTelemetry(parsed_event)) => {
                                 drop(receiver_guard); // Drop lock ASAP
 
-                                // --- Handle InitStart Event --- START ---
-                                if let PlatformEventData::InitStart { .. } = parsed_event.data {
-                                    tracing::debug!(
-                                        "Received InitStart platform event, storing start time."
-                                    );
-                                    let mut init_start_opt = state.init_start_time.lock().await;
-                                    *init_start_opt = Some(parsed_event.timestamp.into());
-                                    drop(init_start_opt);
-                                    // Don't process InitStart further in aggregation
+                                // --- Handle Init Phase Events --- START ---
+                                if parsed_event.data.is_startup_event() {
+                                    let mut init_phases = state.init_phases.lock().await;
+                                    init_phase::record_event(&mut init_phases, &parsed_event);
+                                    // Init events don't belong to an invocation's aggregation
                                     continue;
                                 }
-                                // --- Handle InitStart Event --- END ---
+                                // --- Handle Init Phase Events --- END ---
+
+                                if let Some(metrics) = &state.metrics {
+                                    metrics.lock().await.record_event(&parsed_event);
+                                }
 
                                 tracing::debug!(
                                     "Processing platform telemetry for request_id: {}",
@@ -463,136 +584,62 @@ async fn main() -> Result<(), Error> {
                                 // --- Correlation Logic --- START ---
                                 // Always attempt to look up trace info for this request_id
                                 let map = state.execution_trace_map.lock().await;
-                                let trace_info = map.get(&parsed_event.request_id).cloned();
+                                let trace_info = map.get(&parsed_event.request_id);
                                 drop(map); // Release lock
 
-                                // Pass the trace info to the aggregator during update or creation
+                                // Apply the event, keeping the number of pending aggregations bounded
                                 let mut aggregations_map = state.aggregations.lock().await;
-                                let key = parsed_event.request_id.clone();
-                                let mut completed_spans: Vec<SpanData> = Vec::new();
-
-                                if let Some(agg) = aggregations_map.get_mut(&key) {
-                                    // Pass any found trace info
-                                    if let Some((trace_id, parent_span_id, _)) = trace_info {
-                                        agg.set_trace_context(trace_id, parent_span_id);
-                                    }
-
-                                    agg.update_from_event(&parsed_event);
-                                    if agg.is_complete() {
-                                        if let Some(span_data) = agg.to_otel_span_data() {
-                                            completed_spans.push(span_data);
-                                            completed_spans.append(&mut agg.child_spans_data);
-                                        }
-                                        aggregations_map.remove(&key);
-                                    }
-                                } else {
-                                    let mut new_agg =
-                                        SpanAggregator::new(key, parsed_event.timestamp);
-
-                                    // Pass any found trace info
-                                    if let Some((trace_id, parent_span_id, _)) = trace_info {
-                                        new_agg.set_trace_context(trace_id, parent_span_id);
-                                    }
-
-                                    new_agg.update_from_event(&parsed_event);
-                                    if new_agg.is_complete() {
-                                        if let Some(span_data) = new_agg.to_otel_span_data() {
-                                            completed_spans.push(span_data);
-                                            completed_spans.append(&mut new_agg.child_spans_data);
-                                        }
-                                    } else {
-                                        aggregations_map
-                                            .insert(new_agg.request_id.clone(), new_agg);
-                                    }
+                                let mut tombstones = state.emitted_requests.lock().await;
+                                let mut init_phases = state.init_phases.lock().await;
+                                let invoked_arns = state.invoked_arns.lock().await;
+                                let (mut completed_spans, log_context) = aggregation::process_event(
+                                    &mut aggregations_map,
+                                    &mut tombstones,
+                                    &parsed_event,
+                                    trace_info,
+                                    invoked_arns.get(&parsed_event.request_id),
+                                    &mut init_phases,
+                                    state.aggregation_options,
+                                );
+                                drop(invoked_arns);
+                                drop(init_phases);
+                                if let Some(log_context) = log_context {
+                                    state
+                                        .log_trace_map
+                                        .lock()
+                                        .await
+                                        .insert(parsed_event.request_id.clone(), log_context);
                                 }
-                                drop(aggregations_map); // Drop lock before await
-
-                                // --- Export and Buffer Handling --- START ---
-                                if !completed_spans.is_empty() {
-                                    let span_count = completed_spans.len();
-                                    tracing::debug!(
-                                        count = span_count,
-                                        "Attempting to export completed/aggregated spans via OtlpStdoutSpanExporter"
+                                let (mut evicted_spans, evicted_req_ids) =
+                                    aggregation::evict_oldest(
+                                        &mut aggregations_map,
+                                        state.max_aggregations,
                                     );
-                                    match state.exporter.export(completed_spans).await {
-                                        Ok(_) => {
-                                            tracing::debug!(
-                                                count = span_count,
-                                                "Successfully called exporter. Aggregated spans should now be in the buffer."
-                                            );
-                                            // Note: Output will be processed right after this block.
-                                        }
-                                        Err(e) => {
-                                            tracing::error!(count = span_count, error = ?e, "Failed to export aggregated spans")
-                                        }
-                                    }
-
-                                    // --- Process aggregated spans from the internal exporter's buffer --- START ---
-                                    match state.internal_exporter_buffer.take_lines() {
-                                        // Get lines & clear buffer
-                                        Ok(aggregated_lines) => {
-                                            // Successfully got the lines
-                                            if !aggregated_lines.is_empty() {
-                                                tracing::debug!(
-                                                    "Processing {} line(s) from internal exporter buffer",
-                                                    aggregated_lines.len()
-                                                );
-                                                if state.stream_name.is_some() {
-                                                    // Add to Kinesis batch if Kinesis is enabled
-                                                    let mut kinesis_batch =
-                                                        state.batch.lock().await;
-                                                    for line in aggregated_lines {
-                                                        // Iterate over the Vec<String>
-                                                        if let Err(e) =
-                                                            kinesis_batch.add_record(line)
-                                                        {
-                                                            tracing::error!(error = %e, "Failed to add aggregated span record to Kinesis batch");
-                                                        }
-                                                    }
-                                                    drop(kinesis_batch);
-                                                } else {
-                                                    // Otherwise, print to stdout (CloudWatch Logs)
-                                                    for line in aggregated_lines {
-                                                        // Iterate over the Vec<String>
-                                                        println!("{}", line);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            tracing::error!(
-                                                "Failed to take lines from internal exporter buffer: {:?}",
-                                                e
-                                            );
-                                        }
-                                    }
-                                    // --- Process aggregated spans from the internal exporter's buffer --- END ---
+                                completed_spans.append(&mut evicted_spans);
+                                for req_id in &evicted_req_ids {
+                                    tombstones.insert(req_id.clone());
                                 }
-                                // --- Export and Buffer Handling --- END ---
-                            }
-                            // --- Add Case for InitDataAvailable --- START ---
-                            Ok(ProcessorInput::InitDataAvailable {
-                                request_id,
-                                init_duration_ms,
-                            }) => {
-                                drop(receiver_guard); // Drop lock ASAP
-                                tracing::debug!(request_id = %request_id, init_duration_ms, "Processing InitDataAvailable");
-
-                                let init_start_opt = state.init_start_time.lock().await.take();
+                                drop(tombstones);
+                                drop(aggregations_map); // Drop lock before await
 
-                                if let Some(init_start_time) = init_start_opt {
-                                    let mut aggregations_map = state.aggregations.lock().await;
-                                    if let Some(agg) = aggregations_map.get_mut(&request_id) {
-                                        agg.add_init_phase_span(init_start_time, init_duration_ms);
-                                    } else {
-                                        tracing::warn!(request_id = %request_id, "Aggregator not found when trying to add init phase span. It might have completed or timed out already.");
+                                if !evicted_req_ids.is_empty() {
+                                    let mut map = state.execution_trace_map.lock().await;
+                                    let mut log_map = state.log_trace_map.lock().await;
+                                    for req_id in &evicted_req_ids {
+                                        map.remove(req_id);
+                                        log_map.remove(req_id);
                                     }
-                                    drop(aggregations_map);
-                                } else {
-                                    tracing::warn!(request_id = %request_id, "Received InitDataAvailable but init_start_time was None.");
                                 }
+
+                                export_to_sink(
+                                    &state.exporter,
+                                    &state.internal_exporter_buffer,
+                                    state.sink.as_ref(),
+                                    completed_spans,
+                                    "invoke",
+                                )
+                                .await;
                             }
-                            // --- Add Case for InitDataAvailable --- END ---
                             Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
                                 // No more telemetry to process
                                 drop(receiver_guard);
@@ -606,110 +653,187 @@ async fn main() -> Result<(), Error> {
                             }
                         }
                     }
+                    export_logs_to_sink(
+                        &state.resource,
+                        state.sink.as_ref(),
+                        log_records,
+                        "invoke",
+                    )
+                    .await;
                     // --- Process platform telemetry --- END ---
 
+                    if let Some(metrics) = &state.metrics {
+                        let collected = metrics.lock().await.collect_due(Utc::now());
+                        export_metrics_to_sink(
+                            &state.resource,
+                            state.sink.as_ref(),
+                            collected,
+                            "invoke",
+                        )
+                        .await;
+                    }
+
+                    let dropped_count = state.dropped_telemetry.swap(0, Ordering::Relaxed);
+                    if dropped_count > 0 {
+                        tracing::warn!(
+                            "Dropped {} telemetry events because the processor fell behind",
+                            dropped_count
+                        );
+                    }
+
                     // --- Handle Aggregation Timeouts --- START ---
-                    let mut timed_out_spans: Vec<SpanData> = Vec::new();
-                    let mut timed_out_req_ids: Vec<String> = Vec::new(); // To clean up map later
-                    {
+                    let (timed_out_spans, timed_out_req_ids) = {
                         let mut aggregations_map = state.aggregations.lock().await;
-                        let now = Utc::now();
-
-                        aggregations_map.retain(|key, agg| {
-                            if (now - agg.first_seen_timestamp) > aggregation_timeout {
-                                tracing::warn!(request_id = %key, timeout = ?aggregation_timeout, "Aggregation timed out. Emitting.");
-                                if let Some(span_data) = agg.to_otel_span_data() {
-                                    timed_out_spans.push(span_data);
-                                    timed_out_spans.append(&mut agg.child_spans_data);
-                                }
-                                timed_out_req_ids.push(key.clone()); // Mark for map cleanup
-                                false // Remove from aggregation map
-                            } else {
-                                true // Keep in aggregation map
-                            }
-                        });
-                    } // Aggregation map lock released
+                        let timed_out = aggregation::drain_timed_out(
+                            &mut aggregations_map,
+                            Utc::now(),
+                            aggregation_timeout,
+                        );
+                        // Reports held since the previous drain stop waiting for runtimeDone
+                        let (mut held_spans, held_req_ids) =
+                            aggregation::drain_held(&mut aggregations_map);
+                        // Events still on their way for these requests are late now
+                        let mut tombstones = state.emitted_requests.lock().await;
+                        for req_id in timed_out.1.iter().chain(&held_req_ids) {
+                            tombstones.insert(req_id.clone());
+                        }
+                        let (mut spans, req_ids) = timed_out;
+                        spans.append(&mut held_spans);
+                        (spans, req_ids)
+                    }; // Aggregation map lock released
 
                     // --- TTL Eviction for Execution Trace Map --- START ---
                     {
                         let mut map = state.execution_trace_map.lock().await;
-                        let cutoff = Instant::now()
-                            .checked_sub(std::time::Duration::from_secs(300))
-                            .unwrap_or_else(Instant::now); // Use 5 minutes TTL
-                        let initial_size = map.len();
-                        map.retain(|req_id, (_, _, timestamp)| {
-                            // Also remove entries explicitly marked from aggregation timeout
-                            if timed_out_req_ids.contains(req_id) {
-                                return false;
-                            }
-                            *timestamp >= cutoff
-                        });
-                        let removed_count = initial_size - map.len();
+                        // Also remove the entries of timed out aggregations
+                        let removed_count = map.prune(trace_map_ttl, &timed_out_req_ids);
                         if removed_count > 0 {
                             tracing::debug!(
                                 "Removed {} expired entries from execution trace map",
                                 removed_count
                             );
                         }
+                        let evicted_count = map.take_evicted();
+                        if evicted_count > 0 {
+                            tracing::warn!(
+                                "Evicted {} entries from the full execution trace map; their platform spans won't be correlated",
+                                evicted_count
+                            );
+                        }
+                        let mut log_map = state.log_trace_map.lock().await;
+                        log_map.prune(trace_map_ttl, &timed_out_req_ids);
+                        log_map.take_evicted();
                     }
                     // --- TTL Eviction for Execution Trace Map --- END ---
 
-                    // Export timed-out spans (if any)
-                    if !timed_out_spans.is_empty() {
-                        tracing::debug!("Exporting {} timed-out spans", timed_out_spans.len());
-                        match state.exporter.export(timed_out_spans).await {
-                            Ok(_) => tracing::debug!("Successfully exported timed-out spans"),
-                            Err(e) => tracing::error!("Failed to export timed-out spans: {:?}", e),
-                        }
-                    }
+                    export_to_sink(
+                        &state.exporter,
+                        &state.internal_exporter_buffer,
+                        state.sink.as_ref(),
+                        timed_out_spans,
+                        "timeout",
+                    )
+                    .await;
                     // --- Handle Aggregation Timeouts --- END ---
 
-                    // Flush Kinesis batch
-                    if let Err(e) = state.flush_batch().await {
-                        tracing::error!("Error flushing Kinesis batch on INVOKE: {}", e);
+                    // Flush the sink
+                    if let Err(e) = state.sink.flush().await {
+                        tracing::error!(
+                            "Error flushing {} sink on INVOKE: {}",
+                            state.sink.name(),
+                            e
+                        );
                     }
                 }
-                NextEvent::Shutdown(_) => {
+                NextEvent::Shutdown(shutdown_event) => {
                     tracing::debug!(
-                        "Received SHUTDOWN event, flushing final aggregations and Kinesis batch"
+                        "Received SHUTDOWN event, flushing final aggregations and sink"
                     );
-
-                    // --- Final Aggregation Flush --- START ---
-                    let mut final_spans_to_export: Vec<SpanData> = Vec::new();
-                    {
-                        let mut aggregations_map = state.aggregations.lock().await;
+                    state.sink.set_deadline(shutdown_event.deadline_ms);
+
+                    // Forward whatever the pipe reader has buffered but no INVOKE picked up
+                    let late_lines = {
+                        let mut pipe_rx = state.pipe_rx.lock().await;
+                        pipe::drain_buffered(&mut pipe_rx)
+                    };
+                    if !late_lines.is_empty() {
                         tracing::debug!(
-                            "Draining {} remaining aggregations on shutdown",
-                            aggregations_map.len()
+                            "Forwarding {} buffered pipe lines on shutdown",
+                            late_lines.len()
                         );
-                        // Drain the map, converting each remaining aggregator to spans
-                        for (key, mut agg) in aggregations_map.drain() {
-                            tracing::debug!(
-                                "Flushing remaining agg for request_id '{}' on shutdown",
-                                key
-                            );
-                            if let Some(span_data) = agg.to_otel_span_data() {
-                                final_spans_to_export.push(span_data);
-                                final_spans_to_export.append(&mut agg.child_spans_data);
-                            }
+                    }
+                    for line in late_lines {
+                        let (_, line) = otlp_parsing::classify_line(line);
+                        if let Err(e) = state.sink.write_line(line).await {
+                            tracing::error!(error = %e, sink = state.sink.name(), "Failed to write record to sink");
                         }
-                    } // Aggregations map lock released
+                    }
 
-                    // Export the final spans (if any) via the exporter (writes to pipe)
-                    if !final_spans_to_export.is_empty() {
-                        tracing::debug!(
-                            "Exporting {} final spans on shutdown",
-                            final_spans_to_export.len()
-                        );
-                        match state.exporter.export(final_spans_to_export).await {
-                            Ok(_) => {
-                                tracing::debug!("Successfully exported final spans on shutdown")
-                            }
-                            Err(e) => {
-                                tracing::error!("Failed to export final spans on shutdown: {:?}", e)
+                    // --- Final Log and Metrics Flush --- START ---
+                    // Platform events left in the channel can't complete an invocation anymore,
+                    // but their reports still count in the metrics
+                    let log_records = {
+                        let mut receiver_guard = state.processor_input_rx.lock().await;
+                        let trace_map = state.execution_trace_map.lock().await;
+                        let log_map = state.log_trace_map.lock().await;
+                        let mut log_records = Vec::new();
+                        while let Ok(input) = receiver_guard.try_recv() {
+                            match input {
+                                ProcessorInput::Log(log) => {
+                                    let trace_info = log.request_id().and_then(|request_id| {
+                                        log_trace_context(&log_map, &trace_map, request_id)
+                                    });
+                                    log_records.push(log.to_log_record(
+                                        trace_info,
+                                        state.aggregation_options.attribute_naming,
+                                    ));
+                                }
+                                ProcessorInput::PlatformTelemetry(parsed_event) => {
+                                    if let Some(metrics) = &state.metrics {
+                                        metrics.lock().await.record_event(&parsed_event);
+                                    }
+                                }
                             }
                         }
+                        log_records
+                    };
+                    export_logs_to_sink(
+                        &state.resource,
+                        state.sink.as_ref(),
+                        log_records,
+                        "shutdown",
+                    )
+                    .await;
+                    if let Some(metrics) = &state.metrics {
+                        let collected = metrics.lock().await.collect(Utc::now());
+                        export_metrics_to_sink(
+                            &state.resource,
+                            state.sink.as_ref(),
+                            collected,
+                            "shutdown",
+                        )
+                        .await;
                     }
+                    // --- Final Log and Metrics Flush --- END ---
+
+                    // --- Final Aggregation Flush --- START ---
+                    let final_spans_to_export = {
+                        let mut aggregations_map = state.aggregations.lock().await;
+                        tracing::debug!(
+                            "Draining {} remaining aggregations on shutdown",
+                            aggregations_map.len()
+                        );
+                        aggregation::drain_all(&mut aggregations_map)
+                    }; // Aggregations map lock released
+
+                    export_to_sink(
+                        &state.exporter,
+                        &state.internal_exporter_buffer,
+                        state.sink.as_ref(),
+                        final_spans_to_export,
+                        "shutdown",
+                    )
+                    .await;
                     // --- Final Aggregation Flush --- END ---
 
                     // --- Clear Execution Trace Map on Shutdown --- START ---
@@ -723,24 +847,31 @@ async fn main() -> Result<(), Error> {
                             );
                             map.clear();
                         }
+                        state.log_trace_map.lock().await.clear();
                     }
                     // --- Clear Execution Trace Map on Shutdown --- END ---
 
-                    // --- Clear Init Start Time on Shutdown --- START ---
+                    // --- Clear Init Phases on Shutdown --- START ---
                     {
-                        let mut init_start_opt = state.init_start_time.lock().await;
-                        if init_start_opt.is_some() {
+                        let mut init_phases = state.init_phases.lock().await;
+                        if !init_phases.is_empty() {
+                            // e.g. an init that failed before any invocation could run
                             tracing::debug!(
-                                "Clearing potentially stale init_start_time on shutdown."
+                                "Discarding {} init phases not attached to any invocation on shutdown.",
+                                init_phases.len()
                             );
-                            *init_start_opt = None;
+                            init_phases.clear();
                         }
                     }
-                    // --- Clear Init Start Time on Shutdown --- END ---
-
-                    // Final Kinesis Flush (already implemented)
-                    if let Err(e) = state.flush_batch().await {
-                        tracing::error!("Error flushing Kinesis batch on SHUTDOWN: {}", e);
+                    // --- Clear Init Phases on Shutdown --- END ---
+
+                    // Final sink flush
+                    if let Err(e) = state.sink.flush().await {
+                        tracing::error!(
+                            "Error flushing {} sink on SHUTDOWN: {}",
+                            state.sink.name(),
+                            e
+                        );
                     }
                 }
             }
@@ -749,23 +880,37 @@ async fn main() -> Result<(), Error> {
         }
     });
 
-    // Build and run the extension with appropriate configuration
+    let mut telemetry_types = Vec::new();
     if config.enable_platform_telemetry {
         tracing::debug!("Platform telemetry processing enabled");
-        Extension::new()
+        telemetry_types.push("platform");
+    }
+    telemetry_types.extend(config.log_types.iter().map(LogSource::as_str));
+
+    // Build and run the extension with appropriate configuration
+    if !telemetry_types.is_empty() {
+        let extension = Extension::new()
             .with_events(&["INVOKE", "SHUTDOWN"])
             .with_events_processor(events_processor)
-            .with_telemetry_processor(SharedService::new(service_fn(telemetry_handler_fn)))
-            .with_telemetry_types(&["platform"])
-            .with_telemetry_buffering(LogBuffering {
+            .register()
+            .await?;
+        // Telemetry is received by our own listener rather than lambda-extension's, which
+        // drops whole batches containing record types it doesn't know (e.g. SnapStart restores)
+        telemetry_api::start_listener(telemetry_api::TELEMETRY_PORT, telemetry_handler_fn).await?;
+        telemetry_api::subscribe(
+            &extension.extension_id,
+            &telemetry_types,
+            LogBuffering {
                 timeout_ms: config.buffer_timeout_ms as usize,
                 max_bytes: config.buffer_max_bytes,
                 max_items: config.buffer_max_items,
-            })
-            .run()
-            .await
+            },
+            telemetry_api::TELEMETRY_PORT,
+        )
+        .await?;
+        extension.run().await
     } else {
-        tracing::debug!("Platform telemetry processing disabled");
+        tracing::debug!("Telemetry API subscription disabled");
         Extension::new()
             .with_events(&["INVOKE", "SHUTDOWN"])
             .with_events_processor(events_processor)
@@ -0,0 +1,525 @@
+use crate::events::{ParsedPlatformEvent, PlatformEventData};
+use crate::otlp_parsing::{self, Signal};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use lambda_extension::Status as LambdaStatus;
+use opentelemetry::Value as OtelValue;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::InstrumentationScope;
+use opentelemetry_proto::tonic::metrics::v1::{
+    AggregationTemporality, Gauge, Histogram, HistogramDataPoint, Metric, NumberDataPoint,
+    ResourceMetrics, ScopeMetrics, Sum, metric, number_data_point,
+};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::metric as semconv;
+use prost::Message;
+use std::collections::HashMap;
+
+// Keys of the platform.report metrics, as set when the event is parsed
+const REPORT_DURATION_MS: &str = "report.durationMs";
+const REPORT_BILLED_DURATION_MS: &str = "report.billedDurationMs";
+const REPORT_MEMORY_SIZE_MB: &str = "report.memorySizeMB";
+const REPORT_MAX_MEMORY_USED_MB: &str = "report.maxMemoryUsedMB";
+const REPORT_INIT_DURATION_MS: &str = "report.initDurationMs";
+const REPORT_RESTORE_DURATION_MS: &str = "report.restoreDurationMs";
+
+// Metrics without a semantic convention
+const BILLED_DURATION_METRIC: &str = "aws.lambda.billed_duration";
+const RESTORE_DURATION_METRIC: &str = "aws.lambda.restore_duration";
+const MEMORY_SIZE_METRIC: &str = "aws.lambda.memory_size";
+const MAX_MEMORY_USED_METRIC: &str = "aws.lambda.max_memory_used";
+
+// Bucket bounds of the duration histograms, in seconds: from a few milliseconds up to the
+// 15 minute maximum function timeout
+const DURATION_BOUNDS_SECS: [f64; 18] = [
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0, 30.0, 60.0,
+    300.0, 900.0,
+];
+
+const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
+/// Explicit-bucket histogram of durations, in seconds.
+#[derive(Debug, Clone)]
+struct DurationHistogram {
+    bucket_counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl DurationHistogram {
+    fn new() -> Self {
+        Self {
+            bucket_counts: vec![0; DURATION_BOUNDS_SECS.len() + 1],
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn record_ms(&mut self, duration_ms: f64) {
+        let secs = duration_ms / 1000.0;
+        // Buckets are upper-inclusive: (previous bound, bound]
+        let bucket = DURATION_BOUNDS_SECS.partition_point(|bound| *bound < secs);
+        self.bucket_counts[bucket] += 1;
+        self.count += 1;
+        self.sum += secs;
+        self.min = self.min.min(secs);
+        self.max = self.max.max(secs);
+    }
+
+    fn to_metric(&self, name: &str, description: &str, start: u64, now: u64) -> Option<Metric> {
+        if self.count == 0 {
+            return None;
+        }
+        let data_point = HistogramDataPoint {
+            start_time_unix_nano: start,
+            time_unix_nano: now,
+            count: self.count,
+            sum: Some(self.sum),
+            bucket_counts: self.bucket_counts.clone(),
+            explicit_bounds: DURATION_BOUNDS_SECS.to_vec(),
+            min: Some(self.min),
+            max: Some(self.max),
+            ..Default::default()
+        };
+        Some(Metric {
+            name: name.to_string(),
+            description: description.to_string(),
+            unit: "s".to_string(),
+            data: Some(metric::Data::Histogram(Histogram {
+                data_points: vec![data_point],
+                aggregation_temporality: AggregationTemporality::Cumulative as i32,
+            })),
+            ..Default::default()
+        })
+    }
+}
+
+/// Aggregates the metrics of `platform.report` events into OTLP metrics.
+///
+/// Everything is cumulative since the extension started, the execution environment being
+/// told apart by the `faas.instance` resource attribute. Memory is reported as gauges of
+/// the last report.
+#[derive(Debug)]
+pub struct MetricsRecorder {
+    start_time: DateTime<Utc>,
+    interval: Duration,
+    last_collect: DateTime<Utc>,
+    // Whether a report was recorded since the last collection
+    pending: bool,
+    invoke_duration: DurationHistogram,
+    billed_duration: DurationHistogram,
+    init_duration: DurationHistogram,
+    restore_duration: DurationHistogram,
+    memory_size_mb: Option<f64>,
+    max_memory_used_mb: Option<f64>,
+    invocations: u64,
+    errors: u64,
+    timeouts: u64,
+    coldstarts: u64,
+}
+
+impl MetricsRecorder {
+    /// Creates a recorder whose metrics are collected at most once per `interval`.
+    pub fn new(start_time: DateTime<Utc>, interval: Duration) -> Self {
+        Self {
+            start_time,
+            interval,
+            last_collect: start_time,
+            pending: false,
+            invoke_duration: DurationHistogram::new(),
+            billed_duration: DurationHistogram::new(),
+            init_duration: DurationHistogram::new(),
+            restore_duration: DurationHistogram::new(),
+            memory_size_mb: None,
+            max_memory_used_mb: None,
+            invocations: 0,
+            errors: 0,
+            timeouts: 0,
+            coldstarts: 0,
+        }
+    }
+
+    /// Records the metrics of a `platform.report` event. Other events are ignored.
+    pub fn record_event(&mut self, event: &ParsedPlatformEvent) {
+        let PlatformEventData::Report {
+            status, metrics, ..
+        } = &event.data
+        else {
+            return;
+        };
+
+        self.invocations += 1;
+        match status {
+            LambdaStatus::Error | LambdaStatus::Failure => self.errors += 1,
+            LambdaStatus::Timeout => self.timeouts += 1,
+            _ => {}
+        }
+
+        if let Some(duration_ms) = metric_value(metrics, REPORT_DURATION_MS) {
+            self.invoke_duration.record_ms(duration_ms);
+        }
+        if let Some(billed_ms) = metric_value(metrics, REPORT_BILLED_DURATION_MS) {
+            self.billed_duration.record_ms(billed_ms);
+        }
+        // Only the first invocation of an environment reports its init or restore
+        let init_ms = metric_value(metrics, REPORT_INIT_DURATION_MS);
+        let restore_ms = metric_value(metrics, REPORT_RESTORE_DURATION_MS);
+        if let Some(init_ms) = init_ms {
+            self.init_duration.record_ms(init_ms);
+        }
+        if let Some(restore_ms) = restore_ms {
+            self.restore_duration.record_ms(restore_ms);
+        }
+        if init_ms.is_some() || restore_ms.is_some() {
+            self.coldstarts += 1;
+        }
+        if let Some(memory_size_mb) = metric_value(metrics, REPORT_MEMORY_SIZE_MB) {
+            self.memory_size_mb = Some(memory_size_mb);
+        }
+        if let Some(max_memory_used_mb) = metric_value(metrics, REPORT_MAX_MEMORY_USED_MB) {
+            self.max_memory_used_mb = Some(max_memory_used_mb);
+        }
+        self.pending = true;
+    }
+
+    /// Collects the metrics once the interval has elapsed since the last collection and
+    /// something was recorded since. Returns nothing otherwise.
+    pub fn collect_due(&mut self, now: DateTime<Utc>) -> Vec<Metric> {
+        if now - self.last_collect < self.interval {
+            return Vec::new();
+        }
+        self.collect(now)
+    }
+
+    /// Collects the metrics if something was recorded since the last collection,
+    /// regardless of the interval (e.g. on shutdown).
+    pub fn collect(&mut self, now: DateTime<Utc>) -> Vec<Metric> {
+        if !self.pending {
+            return Vec::new();
+        }
+        self.pending = false;
+        self.last_collect = now;
+
+        let start = unix_nanos(self.start_time);
+        let now = unix_nanos(now);
+        let mut metrics: Vec<Metric> = [
+            self.invoke_duration.to_metric(
+                semconv::FAAS_INVOKE_DURATION,
+                "Duration of the invocations",
+                start,
+                now,
+            ),
+            self.billed_duration.to_metric(
+                BILLED_DURATION_METRIC,
+                "Billed duration of the invocations",
+                start,
+                now,
+            ),
+            self.init_duration.to_metric(
+                semconv::FAAS_INIT_DURATION,
+                "Duration of the function initializations",
+                start,
+                now,
+            ),
+            self.restore_duration.to_metric(
+                RESTORE_DURATION_METRIC,
+                "Duration of the SnapStart restores",
+                start,
+                now,
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        metrics.extend([
+            counter(
+                semconv::FAAS_INVOCATIONS,
+                "Number of invocations",
+                "{invocation}",
+                self.invocations,
+                start,
+                now,
+            ),
+            counter(
+                semconv::FAAS_ERRORS,
+                "Number of invocation errors",
+                "{error}",
+                self.errors,
+                start,
+                now,
+            ),
+            counter(
+                semconv::FAAS_TIMEOUTS,
+                "Number of invocation timeouts",
+                "{timeout}",
+                self.timeouts,
+                start,
+                now,
+            ),
+            counter(
+                semconv::FAAS_COLDSTARTS,
+                "Number of invocation cold starts",
+                "{coldstart}",
+                self.coldstarts,
+                start,
+                now,
+            ),
+        ]);
+
+        if let Some(memory_size_mb) = self.memory_size_mb {
+            metrics.push(gauge(
+                MEMORY_SIZE_METRIC,
+                "Memory allocated to the function",
+                memory_size_mb * BYTES_PER_MB,
+                now,
+            ));
+        }
+        if let Some(max_memory_used_mb) = self.max_memory_used_mb {
+            metrics.push(gauge(
+                MAX_MEMORY_USED_METRIC,
+                "Maximum memory used by the last invocation",
+                max_memory_used_mb * BYTES_PER_MB,
+                now,
+            ));
+        }
+        metrics
+    }
+}
+
+fn metric_value(metrics: &HashMap<String, OtelValue>, key: &str) -> Option<f64> {
+    match metrics.get(key)? {
+        OtelValue::F64(value) => Some(*value),
+        OtelValue::I64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn counter(name: &str, description: &str, unit: &str, value: u64, start: u64, now: u64) -> Metric {
+    Metric {
+        name: name.to_string(),
+        description: description.to_string(),
+        unit: unit.to_string(),
+        data: Some(metric::Data::Sum(Sum {
+            data_points: vec![NumberDataPoint {
+                start_time_unix_nano: start,
+                time_unix_nano: now,
+                value: Some(number_data_point::Value::AsInt(
+                    i64::try_from(value).unwrap_or(i64::MAX),
+                )),
+                ..Default::default()
+            }],
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+            is_monotonic: true,
+        })),
+        ..Default::default()
+    }
+}
+
+fn gauge(name: &str, description: &str, value: f64, now: u64) -> Metric {
+    Metric {
+        name: name.to_string(),
+        description: description.to_string(),
+        unit: "By".to_string(),
+        data: Some(metric::Data::Gauge(Gauge {
+            data_points: vec![NumberDataPoint {
+                time_unix_nano: now,
+                value: Some(number_data_point::Value::AsDouble(value)),
+                ..Default::default()
+            }],
+        })),
+        ..Default::default()
+    }
+}
+
+fn unix_nanos(time: DateTime<Utc>) -> u64 {
+    time.timestamp_nanos_opt()
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .unwrap_or_default()
+}
+
+/// Encodes collected metrics into an otlp-stdout line tagged as metrics.
+pub fn encode_metrics_line(metrics: Vec<Metric>, resource: &Resource) -> Result<String> {
+    let request = ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(otlp_parsing::proto_resource(resource)),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    ..Default::default()
+                }),
+                metrics,
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }],
+    };
+    otlp_parsing::encode_line(Signal::Metrics, &request.encode_to_vec(), resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use opentelemetry::KeyValue;
+
+    fn report(status: LambdaStatus, metrics: &[(&str, OtelValue)]) -> ParsedPlatformEvent {
+        ParsedPlatformEvent {
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 1).unwrap(),
+            request_id: "req-1".to_string(),
+            data: PlatformEventData::Report {
+                status,
+                error_type: None,
+                metrics: metrics
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.clone()))
+                    .collect(),
+                spans: Vec::new(),
+                trace_context: None,
+            },
+        }
+    }
+
+    fn find<'a>(metrics: &'a [Metric], name: &str) -> &'a metric::Data {
+        metrics
+            .iter()
+            .find(|metric| metric.name == name)
+            .and_then(|metric| metric.data.as_ref())
+            .unwrap_or_else(|| panic!("missing metric {name}"))
+    }
+
+    fn sum_value(metrics: &[Metric], name: &str) -> i64 {
+        match find(metrics, name) {
+            metric::Data::Sum(sum) => match sum.data_points[0].value {
+                Some(number_data_point::Value::AsInt(value)) => value,
+                ref other => panic!("unexpected value {other:?}"),
+            },
+            other => panic!("expected a sum, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_record_reports() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut recorder = MetricsRecorder::new(start, Duration::seconds(60));
+
+        recorder.record_event(&report(
+            LambdaStatus::Success,
+            &[
+                (REPORT_DURATION_MS, OtelValue::F64(120.0)),
+                (REPORT_BILLED_DURATION_MS, OtelValue::I64(121)),
+                (REPORT_MEMORY_SIZE_MB, OtelValue::I64(128)),
+                (REPORT_MAX_MEMORY_USED_MB, OtelValue::I64(64)),
+                (REPORT_INIT_DURATION_MS, OtelValue::F64(250.0)),
+            ],
+        ));
+        recorder.record_event(&report(
+            LambdaStatus::Error,
+            &[(REPORT_DURATION_MS, OtelValue::F64(30.0))],
+        ));
+        recorder.record_event(&report(
+            LambdaStatus::Timeout,
+            &[(REPORT_DURATION_MS, OtelValue::F64(3000.0))],
+        ));
+
+        let metrics = recorder.collect(start + Duration::seconds(10));
+        assert_eq!(sum_value(&metrics, semconv::FAAS_INVOCATIONS), 3);
+        assert_eq!(sum_value(&metrics, semconv::FAAS_ERRORS), 1);
+        assert_eq!(sum_value(&metrics, semconv::FAAS_TIMEOUTS), 1);
+        assert_eq!(sum_value(&metrics, semconv::FAAS_COLDSTARTS), 1);
+
+        let metric::Data::Histogram(histogram) = find(&metrics, semconv::FAAS_INVOKE_DURATION)
+        else {
+            panic!("expected a histogram");
+        };
+        let point = &histogram.data_points[0];
+        assert_eq!(point.count, 3);
+        assert_eq!(point.min, Some(0.03));
+        assert_eq!(point.max, Some(3.0));
+        assert_eq!(point.bucket_counts.iter().sum::<u64>(), 3);
+        // 0.12s falls in (0.1, 0.25]
+        assert_eq!(point.bucket_counts[6], 1);
+        assert_eq!(point.start_time_unix_nano, 1_704_067_200_000_000_000);
+
+        assert!(matches!(
+            find(&metrics, MAX_MEMORY_USED_METRIC),
+            metric::Data::Gauge(gauge) if gauge.data_points[0].value
+                == Some(number_data_point::Value::AsDouble(64.0 * BYTES_PER_MB))
+        ));
+        // No restore happened
+        assert!(
+            !metrics
+                .iter()
+                .any(|metric| metric.name == RESTORE_DURATION_METRIC)
+        );
+    }
+
+    #[test]
+    fn test_collect_respects_interval_and_pending_reports() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut recorder = MetricsRecorder::new(start, Duration::seconds(60));
+        assert!(recorder.collect(start).is_empty());
+
+        let event = report(
+            LambdaStatus::Success,
+            &[(REPORT_DURATION_MS, OtelValue::F64(5.0))],
+        );
+        recorder.record_event(&event);
+        assert!(
+            recorder
+                .collect_due(start + Duration::seconds(30))
+                .is_empty()
+        );
+        assert!(
+            !recorder
+                .collect_due(start + Duration::seconds(60))
+                .is_empty()
+        );
+        // Nothing new was recorded
+        assert!(
+            recorder
+                .collect_due(start + Duration::seconds(180))
+                .is_empty()
+        );
+
+        recorder.record_event(&event);
+        let metrics = recorder.collect_due(start + Duration::seconds(180));
+        // Counters keep accumulating across collections
+        assert_eq!(sum_value(&metrics, semconv::FAAS_INVOCATIONS), 2);
+    }
+
+    #[test]
+    fn test_encode_metrics_line() {
+        let resource = Resource::builder_empty()
+            .with_attribute(KeyValue::new("service.name", "test"))
+            .build();
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut recorder = MetricsRecorder::new(start, Duration::seconds(60));
+        recorder.record_event(&report(
+            LambdaStatus::Success,
+            &[(REPORT_DURATION_MS, OtelValue::F64(5.0))],
+        ));
+
+        let line = encode_metrics_line(recorder.collect(start), &resource).unwrap();
+        assert_eq!(otlp_parsing::detect_signal(&line), Signal::Metrics);
+
+        let payload = otlp_parsing::decode_otlp_payload(&line).unwrap().unwrap();
+        let request = ExportMetricsServiceRequest::decode(payload.bytes.as_slice()).unwrap();
+        let resource_metrics = &request.resource_metrics[0];
+        assert!(
+            resource_metrics
+                .resource
+                .as_ref()
+                .unwrap()
+                .attributes
+                .iter()
+                .any(|kv| kv.key == "service.name")
+        );
+        assert!(!resource_metrics.scope_metrics[0].metrics.is_empty());
+    }
+}
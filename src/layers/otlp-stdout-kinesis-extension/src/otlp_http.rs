@@ -0,0 +1,654 @@
+use crate::otlp_parsing::{self, Signal};
+use crate::sink::Sink;
+use async_trait::async_trait;
+use flate2::{Compression, write::GzEncoder};
+use lambda_extension::{Error, tracing};
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::trace::v1::ResourceSpans;
+use prost::Message;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+// Time kept free before the invocation deadline so the extension can still report back
+const DEADLINE_SAFETY_MARGIN_MS: u64 = 100;
+
+// Shortest timeout used for an export, even when the deadline is already close
+const MIN_EXPORT_TIMEOUT_MS: u64 = 10;
+
+// Upper bound on data kept for the next flush after a retryable export failure
+const MAX_PENDING_BYTES: usize = 4 * 1_048_576; // 4MB
+
+/// Parses headers in the `key1=value1,key2=value2` format of `OTEL_EXPORTER_OTLP_HEADERS`.
+/// Malformed entries are skipped.
+pub fn parse_headers(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let key = key.trim();
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Returns the URL of a signal for an OTLP/HTTP endpoint. The endpoint may be the base
+/// URL or the URL of any signal, e.g. `/v1/traces` is replaced with `/v1/logs` for logs.
+pub fn signal_url(endpoint: &str, signal: Signal) -> String {
+    let mut base = endpoint.trim_end_matches('/');
+    for known in [Signal::Traces, Signal::Logs, Signal::Metrics] {
+        if let Some(stripped) = base.strip_suffix(known.path()) {
+            base = stripped;
+            break;
+        }
+    }
+    format!("{base}{}", signal.path())
+}
+
+/// Returns how long an export may take: the configured timeout, shortened so the
+/// request finishes before the invocation deadline (epoch milliseconds, 0 if unknown).
+pub fn export_timeout(max_timeout: Duration, deadline_ms: u64, now: SystemTime) -> Duration {
+    if deadline_ms == 0 {
+        return max_timeout;
+    }
+    let now_ms = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let remaining_ms = deadline_ms
+        .saturating_sub(now_ms)
+        .saturating_sub(DEADLINE_SAFETY_MARGIN_MS)
+        .max(MIN_EXPORT_TIMEOUT_MS);
+    max_timeout.min(Duration::from_millis(remaining_ms))
+}
+
+/// Drops the oldest items until the rest fit in `max_bytes`, returning how many were dropped.
+fn drop_oldest<T>(items: &mut Vec<T>, max_bytes: usize, size_of: impl Fn(&T) -> usize) -> usize {
+    let mut total = 0;
+    let keep = items
+        .iter()
+        .rev()
+        .take_while(|item| {
+            total += size_of(item);
+            total <= max_bytes
+        })
+        .count();
+    let dropped = items.len() - keep;
+    items.drain(..dropped);
+    dropped
+}
+
+/// Why an export request failed, and whether its data is worth keeping.
+enum ExportFailure {
+    /// Throttling, unavailability or a network error; the data may go through later.
+    Retryable(Error),
+    Permanent(Error),
+}
+
+/// Sends telemetry straight to an OTLP/HTTP collector, bypassing Kinesis.
+///
+/// Each otlp-stdout trace line is decoded into its `ExportTraceServiceRequest`, and all
+/// spans received since the last flush are merged into a single protobuf export request.
+/// Log and metric payloads are concatenated per signal instead: their export requests
+/// only hold repeated fields, so concatenated protobuf messages merge into one request.
+///
+/// After a retryable failure, data is kept for the next flush up to `MAX_PENDING_BYTES`;
+/// beyond that the oldest data is dropped and counted.
+pub struct OtlpHttpSink {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: HeaderMap,
+    gzip: bool,
+    max_timeout: Duration,
+    deadline_ms: AtomicU64,
+    pending: Mutex<Vec<ResourceSpans>>,
+    /// Log and metric export requests per signal, one per line, merged when flushed.
+    pending_payloads: Mutex<HashMap<Signal, Vec<Vec<u8>>>>,
+    /// Resource spans and log or metric export requests dropped at the pending limit.
+    dropped: AtomicU64,
+}
+
+impl OtlpHttpSink {
+    pub fn new(
+        endpoint: &str,
+        headers: &[(String, String)],
+        gzip: bool,
+        max_timeout: Duration,
+    ) -> Result<Self, Error> {
+        let mut header_map = HeaderMap::new();
+        for (key, value) in headers {
+            let name = HeaderName::from_bytes(key.as_bytes())
+                .map_err(|e| Error::from(format!("Invalid OTLP header name {key}: {e}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::from(format!("Invalid OTLP header value for {key}: {e}")))?;
+            header_map.insert(name, value);
+        }
+        header_map.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-protobuf"),
+        );
+        if gzip {
+            header_map.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        }
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.to_string(),
+            headers: header_map,
+            gzip,
+            max_timeout,
+            deadline_ms: AtomicU64::new(0),
+            pending: Mutex::new(Vec::new()),
+            pending_payloads: Mutex::new(HashMap::new()),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    fn encode_body(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        if !self.gzip {
+            return Ok(bytes.to_vec());
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn count_dropped(&self, count: usize, what: &str) {
+        if count == 0 {
+            return;
+        }
+        let total = self.dropped.fetch_add(count as u64, Ordering::Relaxed) + count as u64;
+        tracing::warn!(
+            "Dropping {} oldest {} after failed OTLP export, pending limit reached ({} dropped so far)",
+            count,
+            what,
+            total
+        );
+    }
+
+    /// Puts spans back for the next flush, ahead of the ones received since. The oldest
+    /// are dropped when everything doesn't fit in the pending limit.
+    async fn requeue(&self, mut resource_spans: Vec<ResourceSpans>) {
+        let mut pending = self.pending.lock().await;
+        resource_spans.append(&mut pending);
+        let dropped = drop_oldest(&mut resource_spans, MAX_PENDING_BYTES, Message::encoded_len);
+        *pending = resource_spans;
+        drop(pending);
+        self.count_dropped(dropped, "resource spans");
+    }
+
+    /// Puts log or metric export requests back for the next flush, within the same limit.
+    async fn requeue_payloads(&self, signal: Signal, mut payloads: Vec<Vec<u8>>) {
+        let mut pending = self.pending_payloads.lock().await;
+        let queued = pending.entry(signal).or_default();
+        payloads.append(queued);
+        let dropped = drop_oldest(&mut payloads, MAX_PENDING_BYTES, Vec::len);
+        *queued = payloads;
+        drop(pending);
+        self.count_dropped(dropped, &format!("{} export requests", signal.as_str()));
+    }
+
+    /// Posts one export request for a signal.
+    async fn post(&self, signal: Signal, bytes: &[u8]) -> Result<(), ExportFailure> {
+        let url = signal_url(&self.endpoint, signal);
+        let body = self.encode_body(bytes).map_err(ExportFailure::Permanent)?;
+        let timeout = export_timeout(
+            self.max_timeout,
+            self.deadline_ms.load(Ordering::Relaxed),
+            SystemTime::now(),
+        );
+        tracing::debug!(
+            "Exporting {} ({} bytes) to {} with timeout {:?}",
+            signal.as_str(),
+            body.len(),
+            url,
+            timeout
+        );
+
+        let result = self
+            .client
+            .post(&url)
+            .headers(self.headers.clone())
+            .timeout(timeout)
+            .body(body)
+            .send()
+            .await;
+
+        match result {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let error =
+                    Error::from(format!("OTLP export to {url} failed with status {status}"));
+                // Throttling and unavailability are transient; anything else won't succeed later
+                if status.as_u16() == 429 || status.is_server_error() {
+                    Err(ExportFailure::Retryable(error))
+                } else {
+                    Err(ExportFailure::Permanent(error))
+                }
+            }
+            Err(e) => Err(ExportFailure::Retryable(Error::from(format!(
+                "OTLP export to {url} failed: {e}"
+            )))),
+        }
+    }
+
+    async fn flush_spans(&self) -> Result<(), Error> {
+        let resource_spans = std::mem::take(&mut *self.pending.lock().await);
+        if resource_spans.is_empty() {
+            return Ok(());
+        }
+
+        let request = ExportTraceServiceRequest { resource_spans };
+        match self.post(Signal::Traces, &request.encode_to_vec()).await {
+            Ok(()) => Ok(()),
+            Err(ExportFailure::Retryable(e)) => {
+                self.requeue(request.resource_spans).await;
+                Err(e)
+            }
+            Err(ExportFailure::Permanent(e)) => Err(e),
+        }
+    }
+
+    async fn flush_payloads(&self) -> Result<(), Error> {
+        let payloads = std::mem::take(&mut *self.pending_payloads.lock().await);
+        let mut first_error = None;
+        for (signal, payloads) in payloads {
+            if payloads.is_empty() {
+                continue;
+            }
+            let error = match self.post(signal, &payloads.concat()).await {
+                Ok(()) => continue,
+                Err(ExportFailure::Retryable(e)) => {
+                    self.requeue_payloads(signal, payloads).await;
+                    e
+                }
+                Err(ExportFailure::Permanent(e)) => e,
+            };
+            // Only the first error is returned, so report the others here
+            if first_error.is_some() {
+                tracing::error!("{}", error);
+            } else {
+                first_error = Some(error);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+#[async_trait]
+impl Sink for OtlpHttpSink {
+    fn name(&self) -> &'static str {
+        "otlp-http"
+    }
+
+    async fn write_line(&self, line: String) -> Result<(), Error> {
+        let payload = match otlp_parsing::decode_otlp_payload(&line) {
+            Ok(Some(payload)) => payload,
+            Ok(None) => {
+                tracing::debug!("Line is not an OTLP payload, skipping");
+                return Ok(());
+            }
+            Err(e) => return Err(Error::from(format!("Failed to decode OTLP payload: {e}"))),
+        };
+
+        match (payload.signal, payload.format) {
+            (Signal::Traces, _) => {
+                let request = payload
+                    .into_trace_request()
+                    .map_err(|e| Error::from(format!("Failed to decode OTLP payload: {e}")))?;
+                if let Some(request) = request {
+                    self.pending.lock().await.extend(request.resource_spans);
+                }
+            }
+            (signal, _) => {
+                let bytes = payload
+                    .into_protobuf()
+                    .map_err(|e| Error::from(format!("Failed to decode OTLP payload: {e}")))?;
+                self.pending_payloads
+                    .lock()
+                    .await
+                    .entry(signal)
+                    .or_default()
+                    .push(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_deadline(&self, deadline_ms: u64) {
+        self.deadline_ms.store(deadline_ms, Ordering::Relaxed);
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        let spans_result = self.flush_spans().await;
+        let payloads_result = self.flush_payloads().await;
+        spans_result.and(payloads_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine, engine::general_purpose};
+    use flate2::read::GzDecoder;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::trace::v1::{ScopeSpans, Span};
+    use std::io::Read;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    struct CapturedRequest {
+        head: String,
+        body: Vec<u8>,
+    }
+
+    /// Accepts a single HTTP/1.1 request, answers with `status` and hands the request back.
+    async fn mock_collector(status: u16) -> (String, oneshot::Receiver<CapturedRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut data = Vec::new();
+            let mut buf = [0u8; 4096];
+            let head_end = loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                data.extend_from_slice(&buf[..n]);
+                if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&data[..head_end]).to_lowercase();
+            let content_length: usize = head
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .map(|v| v.trim().parse().unwrap())
+                .unwrap_or(0);
+            while data.len() < head_end + content_length {
+                let n = stream.read(&mut buf).await.unwrap();
+                data.extend_from_slice(&buf[..n]);
+            }
+            let response = format!("HTTP/1.1 {status} Mock\r\ncontent-length: 0\r\n\r\n");
+            stream.write_all(response.as_bytes()).await.unwrap();
+            let _ = tx.send(CapturedRequest {
+                head,
+                body: data[head_end..head_end + content_length].to_vec(),
+            });
+        });
+
+        (endpoint, rx)
+    }
+
+    fn otlp_line(name: &str) -> String {
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans {
+                    spans: vec![Span {
+                        trace_id: vec![1; 16],
+                        span_id: vec![2; 8],
+                        name: name.to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        serde_json::json!({
+            "payload": general_purpose::STANDARD.encode(request.encode_to_vec()),
+            "base64": true,
+            "content-type": "application/x-protobuf"
+        })
+        .to_string()
+    }
+
+    fn span_names(request: &ExportTraceServiceRequest) -> Vec<String> {
+        request
+            .resource_spans
+            .iter()
+            .flat_map(|rs| &rs.scope_spans)
+            .flat_map(|ss| &ss.spans)
+            .map(|span| span.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        assert_eq!(
+            parse_headers("x-api-key=secret, x-team = observability,malformed,=empty"),
+            vec![
+                ("x-api-key".to_string(), "secret".to_string()),
+                ("x-team".to_string(), "observability".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(
+            signal_url("http://localhost:4318", Signal::Traces),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            signal_url("http://localhost:4318/v1/traces/", Signal::Traces),
+            "http://localhost:4318/v1/traces"
+        );
+    }
+
+    #[test]
+    fn test_signal_url() {
+        assert_eq!(
+            signal_url("http://localhost:4318", Signal::Logs),
+            "http://localhost:4318/v1/logs"
+        );
+        assert_eq!(
+            signal_url("https://collector/otlp/v1/traces", Signal::Metrics),
+            "https://collector/otlp/v1/metrics"
+        );
+        assert_eq!(
+            signal_url("https://collector/v1/logs/", Signal::Traces),
+            "https://collector/v1/traces"
+        );
+    }
+
+    #[test]
+    fn test_export_timeout_respects_deadline() {
+        let max_timeout = Duration::from_secs(5);
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let now_ms = 1_000_000;
+
+        assert_eq!(export_timeout(max_timeout, 0, now), max_timeout);
+        assert_eq!(
+            export_timeout(max_timeout, now_ms + 60_000, now),
+            max_timeout
+        );
+        assert_eq!(
+            export_timeout(max_timeout, now_ms + 1_100, now),
+            Duration::from_millis(1_000)
+        );
+        assert_eq!(
+            export_timeout(max_timeout, now_ms - 500, now),
+            Duration::from_millis(MIN_EXPORT_TIMEOUT_MS)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_posts_merged_gzip_request() {
+        let (endpoint, captured) = mock_collector(200).await;
+        let headers = vec![("x-api-key".to_string(), "secret".to_string())];
+        let sink = OtlpHttpSink::new(&endpoint, &headers, true, Duration::from_secs(5)).unwrap();
+
+        sink.write_line(otlp_line("handler")).await.unwrap();
+        sink.write_line(otlp_line("Lambda/Invoke")).await.unwrap();
+        sink.write_line("not an otlp line".to_string())
+            .await
+            .unwrap();
+        sink.flush().await.unwrap();
+
+        let request = captured.await.unwrap();
+        assert!(request.head.starts_with("post /v1/traces "));
+        assert!(
+            request
+                .head
+                .contains("content-type: application/x-protobuf")
+        );
+        assert!(request.head.contains("content-encoding: gzip"));
+        assert!(request.head.contains("x-api-key: secret"));
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(request.body.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        let export = ExportTraceServiceRequest::decode(decoded.as_slice()).unwrap();
+        assert_eq!(span_names(&export), vec!["handler", "Lambda/Invoke"]);
+
+        // Nothing left to send
+        assert!(sink.pending.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flush_keeps_spans_on_retryable_status() {
+        let (endpoint, captured) = mock_collector(503).await;
+        let sink = OtlpHttpSink::new(&endpoint, &[], false, Duration::from_secs(5)).unwrap();
+
+        sink.write_line(otlp_line("handler")).await.unwrap();
+        assert!(sink.flush().await.is_err());
+
+        let request = captured.await.unwrap();
+        assert!(!request.head.contains("content-encoding"));
+        let export = ExportTraceServiceRequest::decode(request.body.as_slice()).unwrap();
+        assert_eq!(span_names(&export), vec!["handler"]);
+        assert_eq!(sink.pending.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_requeue_keeps_newest_data_within_pending_limit() {
+        let sink =
+            OtlpHttpSink::new("http://localhost:4318", &[], false, Duration::from_secs(5)).unwrap();
+        let resource_spans = |name: &str| ResourceSpans {
+            scope_spans: vec![ScopeSpans {
+                spans: vec![Span {
+                    name: name.repeat(MAX_PENDING_BYTES / 4),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        // Spans received while the failed export was in flight are the newest
+        sink.pending.lock().await.push(resource_spans("d"));
+        sink.requeue(vec![
+            resource_spans("a"),
+            resource_spans("b"),
+            resource_spans("c"),
+        ])
+        .await;
+
+        let pending = sink.pending.lock().await;
+        let first_chars: Vec<&str> = pending
+            .iter()
+            .map(|rs| &rs.scope_spans[0].spans[0].name[..1])
+            .collect();
+        assert_eq!(first_chars, vec!["b", "c", "d"]);
+        drop(pending);
+        assert_eq!(sink.dropped.load(Ordering::Relaxed), 1);
+
+        let payload = vec![0u8; MAX_PENDING_BYTES / 2];
+        sink.requeue_payloads(Signal::Logs, vec![payload.clone(); 3])
+            .await;
+        assert_eq!(sink.pending_payloads.lock().await[&Signal::Logs].len(), 2);
+        assert_eq!(sink.dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_flush_drops_spans_on_client_error() {
+        let (endpoint, captured) = mock_collector(400).await;
+        let sink = OtlpHttpSink::new(&endpoint, &[], false, Duration::from_secs(5)).unwrap();
+
+        sink.write_line(otlp_line("handler")).await.unwrap();
+        assert!(sink.flush().await.is_err());
+        captured.await.unwrap();
+        assert!(sink.pending.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flush_routes_logs_to_logs_endpoint() {
+        let (endpoint, captured) = mock_collector(200).await;
+        let sink = OtlpHttpSink::new(
+            &format!("{endpoint}/v1/traces"),
+            &[],
+            false,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let logs_line = |payload: &[u8]| {
+            serde_json::json!({
+                "endpoint": "http://localhost:4318/v1/logs",
+                "payload": general_purpose::STANDARD.encode(payload),
+                "base64": true,
+                "content-type": "application/x-protobuf"
+            })
+            .to_string()
+        };
+        sink.write_line(logs_line(b"\x0a\x00")).await.unwrap();
+        sink.write_line(logs_line(b"\x0a\x02\x0a\x00"))
+            .await
+            .unwrap();
+        sink.flush().await.unwrap();
+
+        let request = captured.await.unwrap();
+        assert!(request.head.starts_with("post /v1/logs "));
+        // Both export requests merged into one
+        assert_eq!(request.body, b"\x0a\x00\x0a\x02\x0a\x00");
+        assert!(sink.pending_payloads.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_json_metrics_as_protobuf() {
+        let (endpoint, captured) = mock_collector(200).await;
+        let sink = OtlpHttpSink::new(
+            &format!("{endpoint}/v1/traces"),
+            &[],
+            false,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        let metrics = serde_json::json!({
+            "resourceMetrics": [{"scopeMetrics": [{"metrics": [{"name": "requests"}]}]}]
+        });
+        let line = serde_json::json!({
+            "endpoint": "http://localhost:4318/v1/metrics",
+            "payload": metrics.to_string(),
+            "base64": false,
+            "content-type": "application/json"
+        })
+        .to_string();
+        sink.write_line(line).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let request = captured.await.unwrap();
+        assert!(request.head.starts_with("post /v1/metrics "));
+        assert!(
+            request
+                .head
+                .contains("content-type: application/x-protobuf")
+        );
+        let sent = ExportMetricsServiceRequest::decode(request.body.as_slice()).unwrap();
+        assert_eq!(
+            sent.resource_metrics[0].scope_metrics[0].metrics[0].name,
+            "requests"
+        );
+    }
+}
@@ -1,27 +1,323 @@
-use anyhow::{Context, Result};
+use crate::proto_scan::{self, SpanIds};
+use anyhow::{Context, Result, bail};
 use base64::{Engine, engine::general_purpose};
-use flate2::read::GzDecoder;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use opentelemetry::Key;
 use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
 use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::resource::v1::Resource as ProtoResource;
+use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans};
+use opentelemetry_proto::transform::common::tonic::ResourceAttributesWithSchema;
+use opentelemetry_sdk::Resource;
+use otlp_stdout_span_exporter::ExporterOutput;
 use prost::Message;
 use serde::Deserialize;
-use std::io::Read;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
+use std::str::FromStr;
 
 // OTLP Span Flags constants for remote parent check
 const SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK: u32 = 0x100;
 const SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK: u32 = 0x200;
 
-// A simplified struct matching the relevant fields of otlp_stdout_span_exporter::ExporterOutput
+// Decode buffers larger than this are released after use rather than kept for the next line
+const MAX_RETAINED_BUFFER_BYTES: usize = 4 * 1_048_576; // 4MB
+
+// Envelope field added to log and metric lines, so the forwarder can route them
+// without having to repeat the detection
+pub const SIGNAL_FIELD: &str = "signal";
+
+// Collector the lines written by the extension itself claim to be addressed to
+const EXTENSION_LINE_ENDPOINT: &str = "http://localhost:4318";
+
+/// The OTLP signal carried by an otlp-stdout line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Signal {
+    #[default]
+    Traces,
+    Logs,
+    Metrics,
+}
+
+impl Signal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Traces => "traces",
+            Self::Logs => "logs",
+            Self::Metrics => "metrics",
+        }
+    }
+
+    /// Path of the signal's OTLP/HTTP endpoint.
+    pub fn path(&self) -> &'static str {
+        match self {
+            Self::Traces => "/v1/traces",
+            Self::Logs => "/v1/logs",
+            Self::Metrics => "/v1/metrics",
+        }
+    }
+
+    /// Works out the signal from the envelope: an explicit tag wins, then the path of the
+    /// original endpoint, then the name of the exporter that wrote the line.
+    /// Lines without any hint are traces, as written by the span exporter.
+    fn from_envelope(tag: Option<&str>, endpoint: Option<&str>, version: Option<&str>) -> Self {
+        if let Some(signal) = tag.and_then(|tag| tag.parse().ok()) {
+            return signal;
+        }
+        if let Some(endpoint) = endpoint {
+            let path = endpoint.trim_end_matches('/');
+            for signal in [Self::Traces, Self::Logs, Self::Metrics] {
+                if path.ends_with(signal.path()) {
+                    return signal;
+                }
+            }
+        }
+        match version {
+            Some(version) if version.contains("-log") => Self::Logs,
+            Some(version) if version.contains("-metric") => Self::Metrics,
+            _ => Self::Traces,
+        }
+    }
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "traces" => Ok(Self::Traces),
+            "logs" => Ok(Self::Logs),
+            "metrics" => Ok(Self::Metrics),
+            _ => Err(format!("Invalid OTLP signal: {s}")),
+        }
+    }
+}
+
+// A simplified struct matching the relevant fields of otlp_stdout_span_exporter::ExporterOutput.
+// Fields borrow from the line where possible, so the payload isn't copied before decoding.
 #[derive(Deserialize, Debug)]
-struct OtlpStdoutJsonLine {
-    payload: String,
+struct OtlpStdoutJsonLine<'a> {
+    #[serde(borrow)]
+    payload: Cow<'a, str>,
     #[serde(default)]
     base64: bool,
-    #[serde(rename = "content-encoding", default)]
-    content_encoding: String,
-    #[serde(rename = "content-type", default)]
-    content_type: String,
-    // Other fields like source, endpoint, version are ignored for now
+    #[serde(rename = "content-encoding", borrow, default)]
+    content_encoding: Cow<'a, str>,
+    #[serde(rename = "content-type", borrow, default)]
+    content_type: Cow<'a, str>,
+    #[serde(borrow, default)]
+    endpoint: Option<Cow<'a, str>>,
+    #[serde(rename = "__otel_otlp_stdout", borrow, default)]
+    version: Option<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    signal: Option<Cow<'a, str>>,
+}
+
+impl OtlpStdoutJsonLine<'_> {
+    fn signal(&self) -> Signal {
+        Signal::from_envelope(
+            self.signal.as_deref(),
+            self.endpoint.as_deref(),
+            self.version.as_deref(),
+        )
+    }
+}
+
+// The envelope fields used to detect the signal, read without copying the payload
+#[derive(Deserialize)]
+struct SignalHints<'a> {
+    #[serde(borrow, default)]
+    signal: Option<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    endpoint: Option<Cow<'a, str>>,
+    #[serde(rename = "__otel_otlp_stdout", borrow, default)]
+    version: Option<Cow<'a, str>>,
+}
+
+/// Detects the OTLP signal of an otlp-stdout line. Lines that are not valid JSON are
+/// treated as traces, so they keep going wherever traces go.
+pub fn detect_signal(line: &str) -> Signal {
+    match serde_json::from_str::<SignalHints>(line) {
+        Ok(hints) => Signal::from_envelope(
+            hints.signal.as_deref(),
+            hints.endpoint.as_deref(),
+            hints.version.as_deref(),
+        ),
+        Err(_) => Signal::Traces,
+    }
+}
+
+/// Detects the signal of a line and tags log and metric lines with it.
+///
+/// Trace lines are returned untouched, since records without a tag are treated as traces.
+pub fn classify_line(line: String) -> (Signal, String) {
+    let signal = detect_signal(&line);
+    if signal == Signal::Traces {
+        return (signal, line);
+    }
+    let tagged = match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&line) {
+        Ok(mut envelope) => {
+            envelope.insert(SIGNAL_FIELD.to_string(), signal.as_str().into());
+            serde_json::to_string(&envelope).unwrap_or(line)
+        }
+        Err(_) => line,
+    };
+    (signal, tagged)
+}
+
+/// Buffers for decoding payloads, reused from one line to the next.
+#[derive(Default)]
+struct DecodeBuffers {
+    raw: Vec<u8>,
+    decompressed: Vec<u8>,
+}
+
+impl DecodeBuffers {
+    /// Reverses the envelope's payload encoding into the buffers, like `decode_payload`.
+    fn decode<'b>(
+        &'b mut self,
+        payload: &'b str,
+        base64: bool,
+        content_encoding: &str,
+    ) -> Result<&'b [u8]> {
+        let raw_payload = if base64 {
+            self.raw.clear();
+            general_purpose::STANDARD
+                .decode_vec(payload, &mut self.raw)
+                .context("Failed to decode base64 payload")?;
+            &self.raw[..]
+        } else {
+            payload.as_bytes()
+        };
+        if content_encoding == "gzip" {
+            self.decompressed.clear();
+            GzDecoder::new(raw_payload)
+                .read_to_end(&mut self.decompressed)
+                .context("Failed to decompress Gzip payload")?;
+            Ok(&self.decompressed)
+        } else {
+            Ok(raw_payload)
+        }
+    }
+
+    /// Releases buffers grown by an unusually large payload.
+    fn trim(&mut self) {
+        for buffer in [&mut self.raw, &mut self.decompressed] {
+            if buffer.capacity() > MAX_RETAINED_BUFFER_BYTES {
+                *buffer = Vec::new();
+            }
+        }
+    }
+}
+
+thread_local! {
+    static DECODE_BUFFERS: RefCell<DecodeBuffers> = RefCell::new(DecodeBuffers::default());
+}
+
+/// Visits the spans of an otlp-stdout trace line in order, until `visit` breaks.
+///
+/// This is the fast path used on every pipe line: protobuf payloads are decoded into
+/// reused buffers and scanned in place for span IDs and flags, without materializing
+/// the request. OTLP/JSON payloads are deserialized in full.
+///
+/// Returns `Ok(None)` if the line isn't valid JSON, isn't a trace payload, the payload
+/// is empty, or `visit` never breaks. Returns `Err` for decoding issues.
+fn scan_trace_line<B>(
+    line: &str,
+    mut visit: impl FnMut(SpanIds<'_>) -> ControlFlow<B>,
+) -> Result<Option<B>> {
+    let parsed_line: OtlpStdoutJsonLine = match serde_json::from_str(line) {
+        Ok(p) => p,
+        Err(_) => return Ok(None),
+    };
+    if parsed_line.payload.is_empty() || parsed_line.signal() != Signal::Traces {
+        return Ok(None);
+    }
+
+    match PayloadFormat::from_content_type(&parsed_line.content_type) {
+        Some(PayloadFormat::Protobuf) => DECODE_BUFFERS.with_borrow_mut(|buffers| {
+            let result = buffers
+                .decode(
+                    &parsed_line.payload,
+                    parsed_line.base64,
+                    &parsed_line.content_encoding,
+                )
+                .and_then(|payload| {
+                    proto_scan::scan_spans(payload, &mut visit)
+                        .context("Failed to decode OTLP protobuf payload")
+                });
+            buffers.trim();
+            result
+        }),
+        Some(PayloadFormat::Json) => {
+            let bytes = decode_payload(
+                &parsed_line.payload,
+                parsed_line.base64,
+                &parsed_line.content_encoding,
+            )?;
+            let trace_request: ExportTraceServiceRequest =
+                serde_json::from_slice(&bytes).context("Failed to decode OTLP JSON payload")?;
+            let spans = trace_request
+                .resource_spans
+                .iter()
+                .flat_map(|rs| &rs.scope_spans)
+                .flat_map(|ss| &ss.spans);
+            for span in spans {
+                let ids = SpanIds {
+                    trace_id: &span.trace_id,
+                    span_id: &span.span_id,
+                    parent_span_id: &span.parent_span_id,
+                    flags: span.flags,
+                };
+                if let ControlFlow::Break(value) = visit(ids) {
+                    return Ok(Some(value));
+                }
+            }
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Returns the IDs of a span if it is the function's entry span: either the span with
+/// no parent_span_id or a span indicating a remote parent, with valid IDs.
+pub fn entry_span_ids(span: &SpanIds<'_>) -> Option<(TraceId, SpanId)> {
+    // Check 1: Is it a root span (no parent)?
+    let reason = if span.parent_span_id.is_empty() {
+        "no parent_id"
+    }
+    // Check 2: Does it have a remote parent?
+    else if (span.flags & SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK) != 0
+        && (span.flags & SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK) != 0
+    {
+        "remote parent flag set"
+    } else {
+        return None;
+    };
+
+    match (span.trace_id.try_into(), span.span_id.try_into()) {
+        (Ok(trace_id_bytes), Ok(span_id_bytes)) => {
+            let trace_id = TraceId::from_bytes(trace_id_bytes);
+            let span_id = SpanId::from_bytes(span_id_bytes);
+
+            // Check for invalid IDs
+            if trace_id != TraceId::INVALID && span_id != SpanId::INVALID {
+                tracing::debug!(%trace_id, %span_id, %reason, "Extracted trace info from function entry span");
+                Some((trace_id, span_id))
+            } else {
+                tracing::warn!(%reason, "Found potential entry span with invalid trace_id or span_id, continuing search.");
+                None
+            }
+        }
+        _ => {
+            tracing::warn!(%reason, "Found potential entry span with invalid trace_id or span_id length, continuing search.");
+            None
+        }
+    }
 }
 
 /// Parses an OTLP/stdout JSON line, decodes/decompresses the payload,
@@ -31,6 +327,112 @@ struct OtlpStdoutJsonLine {
 /// Returns `Ok(None)` if the line isn't valid JSON, the payload is empty,
 /// or no suitable entry span is found. Returns `Err` for decoding/decompression issues.
 pub fn extract_trace_info_from_json_line(line: &str) -> Result<Option<(TraceId, SpanId)>> {
+    let trace_info = scan_trace_line(line, |span| match entry_span_ids(&span) {
+        Some(ids) => ControlFlow::Break(ids), // Return the first qualifying span
+        None => ControlFlow::Continue(()),
+    })?;
+    if trace_info.is_none() {
+        tracing::debug!("No suitable entry span found in the OTLP payload.");
+    }
+    Ok(trace_info)
+}
+
+/// Returns the TraceId of the first span in an OTLP/stdout JSON line with a valid trace ID,
+/// regardless of whether it is an entry span.
+///
+/// Returns `Ok(None)` under the same conditions as `extract_trace_info_from_json_line`.
+pub fn extract_trace_id_from_json_line(line: &str) -> Result<Option<TraceId>> {
+    scan_trace_line(line, |span| {
+        match span.trace_id.try_into().map(TraceId::from_bytes) {
+            Ok(trace_id) if trace_id != TraceId::INVALID => ControlFlow::Break(trace_id),
+            _ => ControlFlow::Continue(()),
+        }
+    })
+}
+
+/// How the payload of an otlp-stdout line is serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFormat {
+    Protobuf,
+    /// OTLP/JSON: protobuf's JSON mapping, with hex-encoded trace and span IDs.
+    Json,
+}
+
+impl PayloadFormat {
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        // Ignore parameters such as "; charset=utf-8"
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        match media_type.to_lowercase().as_str() {
+            "application/x-protobuf" => Some(Self::Protobuf),
+            "application/json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The decoded payload of an otlp-stdout line: base64 and gzip removed.
+#[derive(Debug)]
+pub struct OtlpPayload {
+    pub signal: Signal,
+    pub format: PayloadFormat,
+    pub bytes: Vec<u8>,
+}
+
+impl OtlpPayload {
+    /// Deserializes a trace payload, in either format.
+    /// Returns `Ok(None)` if the payload carries another signal.
+    pub fn into_trace_request(self) -> Result<Option<ExportTraceServiceRequest>> {
+        if self.signal != Signal::Traces {
+            return Ok(None);
+        }
+        let trace_request = match self.format {
+            PayloadFormat::Protobuf => ExportTraceServiceRequest::decode(self.bytes.as_slice())
+                .context("Failed to decode OTLP protobuf payload")?,
+            PayloadFormat::Json => {
+                serde_json::from_slice(&self.bytes).context("Failed to decode OTLP JSON payload")?
+            }
+        };
+        Ok(Some(trace_request))
+    }
+
+    /// Returns the payload as protobuf, re-encoding OTLP/JSON with the request type
+    /// of its signal.
+    pub fn into_protobuf(self) -> Result<Vec<u8>> {
+        if self.format == PayloadFormat::Protobuf {
+            return Ok(self.bytes);
+        }
+        let bytes = match self.signal {
+            Signal::Traces => serde_json::from_slice::<ExportTraceServiceRequest>(&self.bytes)
+                .context("Failed to decode OTLP JSON payload")?
+                .encode_to_vec(),
+            Signal::Logs => serde_json::from_slice::<ExportLogsServiceRequest>(&self.bytes)
+                .context("Failed to decode OTLP JSON logs payload")?
+                .encode_to_vec(),
+            Signal::Metrics => serde_json::from_slice::<ExportMetricsServiceRequest>(&self.bytes)
+                .context("Failed to decode OTLP JSON metrics payload")?
+                .encode_to_vec(),
+        };
+        Ok(bytes)
+    }
+}
+
+/// Decodes the trace payload of an OTLP/stdout JSON line, protobuf or OTLP/JSON.
+/// Returns `Ok(None)` if the line isn't valid JSON, isn't a trace payload,
+/// or the payload is empty or in another format.
+///
+/// Only tests need the whole request; the extension itself scans with `scan_trace_line`.
+#[cfg(test)]
+pub fn decode_trace_request(line: &str) -> Result<Option<ExportTraceServiceRequest>> {
+    match decode_otlp_payload(line)? {
+        Some(payload) => payload.into_trace_request(),
+        None => Ok(None),
+    }
+}
+
+/// Returns the signal, format and decoded payload of an OTLP/stdout JSON line.
+/// Returns `Ok(None)` if the line isn't valid JSON, the payload is empty,
+/// or neither protobuf nor OTLP/JSON.
+pub fn decode_otlp_payload(line: &str) -> Result<Option<OtlpPayload>> {
     let parsed_line: OtlpStdoutJsonLine = match serde_json::from_str(line) {
         Ok(p) => p,
         Err(_) => return Ok(None),
@@ -38,80 +440,224 @@ pub fn extract_trace_info_from_json_line(line: &str) -> Result<Option<(TraceId,
     if parsed_line.payload.is_empty() {
         return Ok(None);
     }
-    let raw_payload = if parsed_line.base64 {
+    let Some(format) = PayloadFormat::from_content_type(&parsed_line.content_type) else {
+        return Ok(None);
+    };
+    let signal = parsed_line.signal();
+    let bytes = decode_payload(
+        &parsed_line.payload,
+        parsed_line.base64,
+        &parsed_line.content_encoding,
+    )?;
+    Ok(Some(OtlpPayload {
+        signal,
+        format,
+        bytes,
+    }))
+}
+
+/// Reverses the envelope's payload encoding: base64 first, then gzip if declared.
+fn decode_payload(payload: &str, base64: bool, content_encoding: &str) -> Result<Vec<u8>> {
+    let raw_payload = if base64 {
         general_purpose::STANDARD
-            .decode(&parsed_line.payload)
+            .decode(payload)
             .context("Failed to decode base64 payload")?
     } else {
-        parsed_line.payload.into_bytes()
+        payload.as_bytes().to_vec()
     };
-    let decompressed_payload = if parsed_line.content_encoding == "gzip" {
+    if content_encoding == "gzip" {
         let mut decoder = GzDecoder::new(&raw_payload[..]);
         let mut decompressed_data = Vec::new();
         decoder
             .read_to_end(&mut decompressed_data)
             .context("Failed to decompress Gzip payload")?;
-        decompressed_data
+        Ok(decompressed_data)
     } else {
-        raw_payload
+        Ok(raw_payload)
+    }
+}
+
+/// Applies the envelope's payload encoding to a serialized protobuf message.
+fn encode_payload(bytes: &[u8], base64: bool, content_encoding: &str) -> Result<String> {
+    let encoded = if content_encoding == "gzip" {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(bytes)
+            .context("Failed to compress Gzip payload")?;
+        encoder
+            .finish()
+            .context("Failed to compress Gzip payload")?
+    } else {
+        bytes.to_vec()
     };
-    let trace_request = if parsed_line.content_type == "application/x-protobuf" {
-        ExportTraceServiceRequest::decode(decompressed_payload.as_slice())
-            .context("Failed to decode OTLP protobuf payload")?
+    if base64 {
+        Ok(general_purpose::STANDARD.encode(encoded))
     } else {
-        return Ok(None);
+        String::from_utf8(encoded).context("Payload is neither base64 nor valid UTF-8")
+    }
+}
+
+/// Wraps an encoded OTLP request produced by the extension itself (logs or metrics) into
+/// an otlp-stdout line, shaped like the exporters' lines and tagged with its signal.
+pub fn encode_line(signal: Signal, request: &[u8], resource: &Resource) -> Result<String> {
+    let output = ExporterOutput {
+        version: format!(
+            "{}-{}@{}",
+            env!("CARGO_PKG_NAME"),
+            signal.as_str(),
+            env!("CARGO_PKG_VERSION")
+        ),
+        source: resource
+            .get(&Key::from_static_str("service.name"))
+            .map(|name| name.to_string())
+            .unwrap_or_default(),
+        endpoint: format!("{EXTENSION_LINE_ENDPOINT}{}", signal.path()),
+        method: "POST".to_string(),
+        content_type: "application/x-protobuf".to_string(),
+        content_encoding: "gzip".to_string(),
+        headers: None,
+        payload: encode_payload(request, true, "gzip")?,
+        base64: true,
+        level: None,
     };
+    let mut envelope =
+        serde_json::to_value(output).context("Failed to serialize otlp-stdout line")?;
+    envelope[SIGNAL_FIELD] = signal.as_str().into();
+    serde_json::to_string(&envelope).context("Failed to serialize otlp-stdout line")
+}
 
-    for resource_span in trace_request.resource_spans {
-        for scope_span in resource_span.scope_spans {
-            for span in scope_span.spans {
-                let mut is_entry_span = false;
-                let mut reason = "";
+/// Converts the SDK resource into its OTLP representation.
+pub fn proto_resource(resource: &Resource) -> ProtoResource {
+    ProtoResource {
+        attributes: ResourceAttributesWithSchema::from(resource).attributes.0,
+        ..Default::default()
+    }
+}
 
-                // Check 1: Is it a root span (no parent)?
-                if span.parent_span_id.is_empty() {
-                    is_entry_span = true;
-                    reason = "no parent_id";
-                }
-                // Check 2: Does it have a remote parent?
-                else if (span.flags & SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK) != 0
-                    && (span.flags & SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK) != 0
-                {
-                    is_entry_span = true;
-                    reason = "remote parent flag set";
-                }
+/// Splits an otlp-stdout JSON line whose protobuf payload is too large into several lines,
+/// each at most `max_line_bytes` long. Every part keeps the original envelope and carries a
+/// subset of the spans, with their resource and scope preserved.
+///
+/// Spans that cannot fit on their own are dropped with a warning.
+/// Returns `Err` if the line is not a protobuf trace payload or cannot be decoded.
+pub fn split_json_line(line: &str, max_line_bytes: usize) -> Result<Vec<String>> {
+    let signal = detect_signal(line);
+    if signal != Signal::Traces {
+        bail!("Cannot split {} payload", signal.as_str());
+    }
+    let mut envelope: ExporterOutput =
+        serde_json::from_str(line).context("Failed to parse otlp-stdout JSON line")?;
+    if envelope.content_type != "application/x-protobuf" {
+        bail!(
+            "Cannot split payload with content-type '{}'",
+            envelope.content_type
+        );
+    }
+    let payload = std::mem::take(&mut envelope.payload);
+    let decompressed_payload =
+        decode_payload(&payload, envelope.base64, &envelope.content_encoding)?;
+    let trace_request = ExportTraceServiceRequest::decode(decompressed_payload.as_slice())
+        .context("Failed to decode OTLP protobuf payload")?;
 
-                if is_entry_span {
-                    // Attempt to extract IDs
-                    let trace_id_bytes_res = span.trace_id.as_slice().try_into();
-                    let span_id_bytes_res = span.span_id.as_slice().try_into();
-
-                    match (trace_id_bytes_res, span_id_bytes_res) {
-                        (Ok(trace_id_bytes), Ok(span_id_bytes)) => {
-                            let trace_id = TraceId::from_bytes(trace_id_bytes);
-                            let span_id = SpanId::from_bytes(span_id_bytes);
-
-                            // Check for invalid IDs
-                            if trace_id != TraceId::INVALID && span_id != SpanId::INVALID {
-                                tracing::debug!(%trace_id, %span_id, %reason, "Extracted trace info from function entry span");
-                                return Ok(Some((trace_id, span_id))); // Return the first qualifying span
-                            } else {
-                                tracing::warn!(%reason, "Found potential entry span with invalid trace_id or span_id, continuing search.");
-                                // Continue searching in case of invalid IDs
-                            }
-                        }
-                        _ => {
-                            tracing::warn!(%reason, "Found potential entry span with invalid trace_id or span_id length, continuing search.");
-                            // Continue searching if ID conversion fails
-                        }
-                    }
-                }
+    let mut lines = Vec::new();
+    split_request(trace_request, &mut envelope, max_line_bytes, &mut lines)?;
+    Ok(lines)
+}
+
+fn split_request(
+    request: ExportTraceServiceRequest,
+    envelope: &mut ExporterOutput,
+    max_line_bytes: usize,
+    lines: &mut Vec<String>,
+) -> Result<()> {
+    envelope.payload = encode_payload(
+        &request.encode_to_vec(),
+        envelope.base64,
+        &envelope.content_encoding,
+    )?;
+    let line = serde_json::to_string(envelope).context("Failed to serialize otlp-stdout line")?;
+    if line.len() <= max_line_bytes {
+        lines.push(line);
+        return Ok(());
+    }
+
+    match halve_request(request) {
+        Some((first, second)) => {
+            split_request(first, envelope, max_line_bytes, lines)?;
+            split_request(second, envelope, max_line_bytes, lines)
+        }
+        None => {
+            tracing::warn!(
+                "Single span payload of {} bytes exceeds maximum size of {} bytes, dropping",
+                line.len(),
+                max_line_bytes
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Splits a request into two requests with roughly half of the spans each.
+/// Returns `None` if the request holds fewer than two spans.
+fn halve_request(
+    request: ExportTraceServiceRequest,
+) -> Option<(ExportTraceServiceRequest, ExportTraceServiceRequest)> {
+    let span_count: usize = request
+        .resource_spans
+        .iter()
+        .flat_map(|rs| &rs.scope_spans)
+        .map(|ss| ss.spans.len())
+        .sum();
+    if span_count < 2 {
+        return None;
+    }
+
+    let mut remaining = span_count / 2;
+    let mut first = ExportTraceServiceRequest::default();
+    let mut second = ExportTraceServiceRequest::default();
+
+    for resource_spans in request.resource_spans {
+        let mut first_scopes = Vec::new();
+        let mut second_scopes = Vec::new();
+
+        for scope_spans in resource_spans.scope_spans {
+            let mut spans = scope_spans.spans;
+            let second_spans = spans.split_off(remaining.min(spans.len()));
+            remaining -= spans.len();
+
+            if !spans.is_empty() {
+                first_scopes.push(ScopeSpans {
+                    scope: scope_spans.scope.clone(),
+                    spans,
+                    schema_url: scope_spans.schema_url.clone(),
+                });
             }
+            if !second_spans.is_empty() {
+                second_scopes.push(ScopeSpans {
+                    scope: scope_spans.scope,
+                    spans: second_spans,
+                    schema_url: scope_spans.schema_url,
+                });
+            }
+        }
+
+        if !first_scopes.is_empty() {
+            first.resource_spans.push(ResourceSpans {
+                resource: resource_spans.resource.clone(),
+                scope_spans: first_scopes,
+                schema_url: resource_spans.schema_url.clone(),
+            });
+        }
+        if !second_scopes.is_empty() {
+            second.resource_spans.push(ResourceSpans {
+                resource: resource_spans.resource,
+                scope_spans: second_scopes,
+                schema_url: resource_spans.schema_url,
+            });
         }
     }
 
-    tracing::debug!("No suitable entry span found in the OTLP payload.");
-    Ok(None)
+    Some((first, second))
 }
 
 #[cfg(test)]
@@ -121,8 +667,9 @@ mod tests {
     use flate2::{Compression, write::GzEncoder};
     use opentelemetry_proto::tonic::{
         // Import OTLP types for creating test data
+        common::v1::{AnyValue, InstrumentationScope, KeyValue, any_value},
         resource::v1::Resource,
-        trace::v1::{ResourceSpans, ScopeSpans, Span, Status, span::SpanKind, status::StatusCode},
+        trace::v1::{Span, Status, span::SpanKind, status::StatusCode},
     };
     use prost::Message;
     use std::io::Write;
@@ -401,4 +948,657 @@ mod tests {
             "Expected None when entry span has invalid IDs"
         );
     }
+
+    // --- OTLP/JSON Test Cases ---
+
+    // Helper to create an OTLP/JSON span, as serialized by the Node and Python exporters
+    fn create_json_span(
+        trace_id: &str,
+        span_id: &str,
+        parent_span_id: Option<&str>,
+        name: &str,
+        flags: Option<u32>,
+    ) -> serde_json::Value {
+        let mut span = serde_json::json!({
+            "traceId": trace_id,
+            "spanId": span_id,
+            "name": name,
+            "kind": 2,
+            "startTimeUnixNano": "1704067200000000000",
+            "endTimeUnixNano": "1704067201000000000",
+            "attributes": [
+                {"key": "http.status_code", "value": {"intValue": 200}},
+                {"key": "faas.coldstart", "value": {"boolValue": true}}
+            ],
+            "droppedAttributesCount": 0,
+            "events": [],
+            "links": [],
+            "status": {"code": 1}
+        });
+        if let Some(parent_span_id) = parent_span_id {
+            span["parentSpanId"] = parent_span_id.into();
+        }
+        if let Some(flags) = flags {
+            span["flags"] = flags.into();
+        }
+        span
+    }
+
+    // Helper to wrap OTLP/JSON spans into a gzipped, base64-encoded otlp-stdout line
+    fn create_otlp_json_line(spans: Vec<serde_json::Value>) -> String {
+        let request = serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        {"key": "service.name", "value": {"stringValue": "node-client"}}
+                    ],
+                    "droppedAttributesCount": 0
+                },
+                "scopeSpans": [{
+                    "scope": {"name": "@opentelemetry/instrumentation-http", "version": "0.52.0"},
+                    "spans": spans
+                }]
+            }]
+        });
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(request.to_string().as_bytes()).unwrap();
+        let json_data = serde_json::json!({
+            "payload": base64_engine.encode(encoder.finish().unwrap()),
+            "base64": true,
+            "content-encoding": "gzip",
+            "content-type": "application/json"
+        });
+        serde_json::to_string(&json_data).unwrap()
+    }
+
+    #[test]
+    fn test_json_valid_root_span() {
+        let trace_id_hex = "0102030405060708090a0b0c0d0e0f10";
+        let span_id_hex = "1112131415161718";
+        let span = create_json_span(trace_id_hex, span_id_hex, None, "root_span", None);
+        let json_line = create_otlp_json_line(vec![span]);
+
+        let (tid, sid) = extract_trace_info_from_json_line(&json_line)
+            .unwrap()
+            .expect("Expected Some trace info");
+        assert_eq!(format!("{:032x}", tid), trace_id_hex);
+        assert_eq!(format!("{:016x}", sid), span_id_hex);
+    }
+
+    #[test]
+    fn test_json_empty_parent_span_id_is_root() {
+        let span = create_json_span(
+            "0102030405060708090A0B0C0D0E0F10", // Upper case hex is accepted too
+            "1112131415161718",
+            Some(""),
+            "root_span",
+            Some(1),
+        );
+        let json_line = create_otlp_json_line(vec![span]);
+
+        let (tid, _) = extract_trace_info_from_json_line(&json_line)
+            .unwrap()
+            .expect("Expected Some trace info");
+        assert_eq!(format!("{:032x}", tid), "0102030405060708090a0b0c0d0e0f10");
+    }
+
+    #[test]
+    fn test_json_valid_remote_parent_span() {
+        let trace_id_hex = "aabbccddeeff00112233445566778899";
+        let span_id_hex = "aabbccddeeff0011";
+        let flags = SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK | SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK | 1;
+
+        let internal = create_json_span(
+            trace_id_hex,
+            "0000000000000001",
+            Some(span_id_hex),
+            "internal",
+            Some(SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK | 1), // Known local parent
+        );
+        let entry = create_json_span(
+            trace_id_hex,
+            span_id_hex,
+            Some("1122334455667788"),
+            "remote_parent_entry",
+            Some(flags),
+        );
+        let json_line = create_otlp_json_line(vec![internal, entry]);
+
+        let (tid, sid) = extract_trace_info_from_json_line(&json_line)
+            .unwrap()
+            .expect("Expected Some trace info");
+        assert_eq!(format!("{:032x}", tid), trace_id_hex);
+        assert_eq!(format!("{:016x}", sid), span_id_hex);
+    }
+
+    #[test]
+    fn test_json_no_entry_span_found() {
+        let trace_id_hex = "cccccccccccccccccccccccccccccccc";
+        let span1 = create_json_span(
+            trace_id_hex,
+            "aaaaaaaaaaaaaaaa",
+            Some("1111111111111111"),
+            "internal_span_1",
+            None,
+        );
+        let span2 = create_json_span(
+            trace_id_hex,
+            "bbbbbbbbbbbbbbbb",
+            Some("aaaaaaaaaaaaaaaa"),
+            "internal_span_2",
+            None,
+        );
+        let json_line = create_otlp_json_line(vec![span1, span2]);
+
+        assert!(
+            extract_trace_info_from_json_line(&json_line)
+                .unwrap()
+                .is_none()
+        );
+        // The trace ID is still available for partitioning
+        assert_eq!(
+            extract_trace_id_from_json_line(&json_line).unwrap(),
+            Some(TraceId::from_hex(trace_id_hex).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_json_payload_with_no_spans() {
+        let json_line = create_otlp_json_line(vec![]);
+        assert!(
+            extract_trace_info_from_json_line(&json_line)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_json_invalid_ids_in_entry_span() {
+        let span = create_json_span(
+            "00000000000000000000000000000000",
+            "1111111111111111",
+            None,
+            "invalid_trace_id_span",
+            None,
+        );
+        let json_line = create_otlp_json_line(vec![span]);
+        assert!(
+            extract_trace_info_from_json_line(&json_line)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_json_uncompressed_payload() {
+        // Without base64 and gzip the OTLP/JSON document is embedded as a plain string
+        let request = serde_json::json!({
+            "resourceSpans": [{
+                "scopeSpans": [{
+                    "spans": [create_json_span(
+                        "0102030405060708090a0b0c0d0e0f10",
+                        "1112131415161718",
+                        None,
+                        "root_span",
+                        None,
+                    )]
+                }]
+            }]
+        });
+        let json_line = serde_json::json!({
+            "payload": request.to_string(),
+            "base64": false,
+            "content-type": "application/json; charset=utf-8"
+        })
+        .to_string();
+
+        let request = decode_trace_request(&json_line).unwrap().unwrap();
+        let span = &request.resource_spans[0].scope_spans[0].spans[0];
+        assert_eq!(span.name, "root_span");
+        assert_eq!(span.start_time_unix_nano, 1704067200000000000);
+        assert!(
+            extract_trace_info_from_json_line(&json_line)
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_json_invalid_payload() {
+        let json_line = serde_json::json!({
+            "payload": "{\"resourceSpans\": [{\"scopeSpans\": [{\"spans\": [{\"traceId\": \"not hex\"}]}]}]}",
+            "base64": false,
+            "content-type": "application/json"
+        })
+        .to_string();
+        let result = extract_trace_info_from_json_line(&json_line);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Failed to decode OTLP JSON payload")
+        );
+    }
+
+    #[test]
+    fn test_json_round_trips_through_protobuf_types() {
+        let trace_id_bytes = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10")
+            .unwrap()
+            .to_bytes();
+        let span_id_bytes = SpanId::from_hex("1112131415161718").unwrap().to_bytes();
+        let span = create_proto_span(&trace_id_bytes, &span_id_bytes, None, "root_span", Some(1));
+        let request = create_test_request(vec![span]);
+        let json_line = serde_json::json!({
+            "payload": serde_json::to_string(&request).unwrap(),
+            "base64": false,
+            "content-type": "application/json"
+        })
+        .to_string();
+
+        assert_eq!(decode_trace_request(&json_line).unwrap(), Some(request));
+    }
+
+    fn string_attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    // Helper to create a full otlp-stdout envelope, as written by the span exporter
+    fn create_exporter_json_line(request: &ExportTraceServiceRequest) -> String {
+        let json_data = serde_json::json!({
+            "__otel_otlp_stdout": "otlp-stdout-span-exporter@0.16.0",
+            "source": "test-service",
+            "endpoint": "http://localhost:4318/v1/traces",
+            "method": "POST",
+            "content-type": "application/x-protobuf",
+            "content-encoding": "gzip",
+            "payload": encode_payload(&request.encode_to_vec(), true, "gzip").unwrap(),
+            "base64": true
+        });
+        serde_json::to_string(&json_data).unwrap()
+    }
+
+    fn decode_line(line: &str) -> (ExporterOutput, ExportTraceServiceRequest) {
+        let mut output: ExporterOutput = serde_json::from_str(line).unwrap();
+        let payload = std::mem::take(&mut output.payload);
+        let bytes = decode_payload(&payload, output.base64, &output.content_encoding).unwrap();
+        (
+            output,
+            ExportTraceServiceRequest::decode(bytes.as_slice()).unwrap(),
+        )
+    }
+
+    fn multi_resource_request(spans_per_scope: usize) -> ExportTraceServiceRequest {
+        let trace_id = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10")
+            .unwrap()
+            .to_bytes();
+        let resource_spans = ["service-a", "service-b"]
+            .iter()
+            .map(|service| ResourceSpans {
+                resource: Some(Resource {
+                    attributes: vec![string_attribute("service.name", service)],
+                    ..Default::default()
+                }),
+                scope_spans: ["scope-1", "scope-2"]
+                    .iter()
+                    .map(|scope| ScopeSpans {
+                        scope: Some(InstrumentationScope {
+                            name: scope.to_string(),
+                            ..Default::default()
+                        }),
+                        spans: (0..spans_per_scope)
+                            .map(|i| {
+                                let span_id = SpanId::from(i as u64 + 1).to_bytes();
+                                create_proto_span(
+                                    &trace_id,
+                                    &span_id,
+                                    None,
+                                    &format!("{service}/{scope}/{i}"),
+                                    None,
+                                )
+                            })
+                            .collect(),
+                        schema_url: String::new(),
+                    })
+                    .collect(),
+                schema_url: String::new(),
+            })
+            .collect();
+        ExportTraceServiceRequest { resource_spans }
+    }
+
+    #[test]
+    fn test_extract_trace_id_from_child_span() {
+        let trace_id_hex = "0102030405060708090a0b0c0d0e0f10";
+        let trace_id_bytes = TraceId::from_hex(trace_id_hex).unwrap().to_bytes();
+        let span_id_bytes = SpanId::from_hex("aaaaaaaaaaaaaaaa").unwrap().to_bytes();
+        let parent_id_bytes = SpanId::from_hex("1111111111111111").unwrap().to_bytes();
+        // Not an entry span, but still carries the trace ID
+        let span = create_proto_span(
+            &trace_id_bytes,
+            &span_id_bytes,
+            Some(&parent_id_bytes),
+            "child",
+            None,
+        );
+        let json_line = create_test_json_line(create_test_request(vec![span]));
+
+        let trace_id = extract_trace_id_from_json_line(&json_line).unwrap();
+        assert_eq!(
+            trace_id.map(|t| format!("{:032x}", t)),
+            Some(trace_id_hex.to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_trace_id_skips_invalid_ids() {
+        let span_id_bytes = SpanId::from_hex("aaaaaaaaaaaaaaaa").unwrap().to_bytes();
+        let span = create_proto_span(
+            &TraceId::INVALID.to_bytes(),
+            &span_id_bytes,
+            None,
+            "bad",
+            None,
+        );
+        let json_line = create_test_json_line(create_test_request(vec![span]));
+
+        assert!(
+            extract_trace_id_from_json_line(&json_line)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            extract_trace_id_from_json_line("not json")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_split_json_line_preserves_spans_and_envelope() {
+        let request = multi_resource_request(5);
+        let line = create_exporter_json_line(&request);
+        // Room for a few spans per part, but far less than the full payload
+        let max_line_bytes = create_exporter_json_line(&multi_resource_request(1)).len();
+
+        let parts = split_json_line(&line, max_line_bytes).unwrap();
+        assert!(parts.len() >= 2, "Expected the line to be split");
+
+        let mut span_names = Vec::new();
+        for part in &parts {
+            assert!(part.len() <= max_line_bytes);
+            let (output, part_request) = decode_line(part);
+            assert_eq!(output.source, "test-service");
+            assert_eq!(output.endpoint, "http://localhost:4318/v1/traces");
+            for resource_spans in part_request.resource_spans {
+                let service = match &resource_spans.resource.unwrap().attributes[0]
+                    .value
+                    .as_ref()
+                    .unwrap()
+                    .value
+                {
+                    Some(any_value::Value::StringValue(s)) => s.clone(),
+                    other => panic!("Unexpected resource attribute: {:?}", other),
+                };
+                for scope_spans in resource_spans.scope_spans {
+                    let scope = scope_spans.scope.unwrap().name;
+                    for span in scope_spans.spans {
+                        // Each span must still sit under its original resource and scope
+                        assert!(span.name.starts_with(&format!("{service}/{scope}/")));
+                        span_names.push(span.name);
+                    }
+                }
+            }
+        }
+        span_names.sort();
+        let mut expected: Vec<String> = request
+            .resource_spans
+            .iter()
+            .flat_map(|rs| &rs.scope_spans)
+            .flat_map(|ss| &ss.spans)
+            .map(|span| span.name.clone())
+            .collect();
+        expected.sort();
+        assert_eq!(span_names, expected);
+    }
+
+    #[test]
+    fn test_split_json_line_fits_unchanged() {
+        let request = multi_resource_request(1);
+        let line = create_exporter_json_line(&request);
+        let parts = split_json_line(&line, line.len() * 2).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(decode_line(&parts[0]).1, request);
+    }
+
+    #[test]
+    fn test_split_json_line_drops_span_that_cannot_fit() {
+        let trace_id_bytes = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10")
+            .unwrap()
+            .to_bytes();
+        let span_id_bytes = SpanId::from_hex("1112131415161718").unwrap().to_bytes();
+        let span = create_proto_span(&trace_id_bytes, &span_id_bytes, None, "lonely", None);
+        let line = create_exporter_json_line(&create_test_request(vec![span]));
+
+        let parts = split_json_line(&line, 10).unwrap();
+        assert!(parts.is_empty());
+    }
+
+    #[test]
+    fn test_split_json_line_rejects_non_protobuf() {
+        let json_data = serde_json::json!({
+            "__otel_otlp_stdout": "otlp-stdout-span-exporter@0.16.0",
+            "source": "test-service",
+            "endpoint": "http://localhost:4318/v1/traces",
+            "method": "POST",
+            "content-type": "application/json",
+            "content-encoding": "gzip",
+            "payload": "e30=",
+            "base64": true
+        });
+        let line = serde_json::to_string(&json_data).unwrap();
+        assert!(split_json_line(&line, 10).is_err());
+        assert!(split_json_line("not json", 10).is_err());
+    }
+
+    // Helper to create a log line, as written by a logs exporter with the same envelope
+    fn create_logs_json_line(endpoint: &str, version: &str) -> String {
+        let json_data = serde_json::json!({
+            "__otel_otlp_stdout": version,
+            "source": "test-service",
+            "endpoint": endpoint,
+            "method": "POST",
+            "content-type": "application/x-protobuf",
+            "content-encoding": "gzip",
+            "payload": encode_payload(b"\x0a\x00", true, "gzip").unwrap(),
+            "base64": true
+        });
+        serde_json::to_string(&json_data).unwrap()
+    }
+
+    #[test]
+    fn test_detect_signal() {
+        let traces = create_exporter_json_line(&create_test_request(Vec::new()));
+        assert_eq!(detect_signal(&traces), Signal::Traces);
+        assert_eq!(
+            detect_signal(&create_logs_json_line(
+                "http://localhost:4318/v1/logs",
+                "otlp-stdout-span-exporter@0.16.0"
+            )),
+            Signal::Logs
+        );
+        assert_eq!(
+            detect_signal(&create_logs_json_line(
+                "http://localhost:4318/v1/metrics/",
+                "custom"
+            )),
+            Signal::Metrics
+        );
+        // Without a recognizable endpoint, the exporter name decides
+        assert_eq!(
+            detect_signal(&create_logs_json_line(
+                "http://collector",
+                "otlp-stdout-log-exporter@0.1.0"
+            )),
+            Signal::Logs
+        );
+        assert_eq!(
+            detect_signal(
+                r#"{"signal": "metrics", "endpoint": "http://localhost:4318/v1/traces"}"#
+            ),
+            Signal::Metrics
+        );
+        assert_eq!(detect_signal("not json"), Signal::Traces);
+    }
+
+    #[test]
+    fn test_classify_line_tags_only_non_trace_lines() {
+        let traces = create_exporter_json_line(&create_test_request(Vec::new()));
+        let (signal, line) = classify_line(traces.clone());
+        assert_eq!(signal, Signal::Traces);
+        assert_eq!(line, traces);
+
+        let logs = create_logs_json_line("http://localhost:4318/v1/logs", "custom");
+        let (signal, line) = classify_line(logs);
+        assert_eq!(signal, Signal::Logs);
+        let envelope: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(envelope[SIGNAL_FIELD], "logs");
+        assert_eq!(envelope["endpoint"], "http://localhost:4318/v1/logs");
+
+        // Tagging is idempotent
+        let (_, retagged) = classify_line(line.clone());
+        assert_eq!(retagged, line);
+    }
+
+    #[test]
+    fn test_trace_info_is_not_extracted_from_logs() {
+        let logs = create_logs_json_line("http://localhost:4318/v1/logs", "custom");
+        assert!(decode_trace_request(&logs).unwrap().is_none());
+        assert!(extract_trace_info_from_json_line(&logs).unwrap().is_none());
+        assert!(split_json_line(&logs, 10).is_err());
+
+        let payload = decode_otlp_payload(&logs).unwrap().unwrap();
+        assert_eq!(payload.signal, Signal::Logs);
+        assert_eq!(payload.format, PayloadFormat::Protobuf);
+        assert_eq!(payload.bytes, b"\x0a\x00");
+        assert!(payload.into_trace_request().unwrap().is_none());
+    }
+
+    // --- Fast path ---
+
+    type TraceInfoResult = Result<Option<(TraceId, SpanId)>>;
+
+    /// The full-decode implementation the scanner replaced, kept as a reference.
+    fn extract_trace_info_full_decode(line: &str) -> TraceInfoResult {
+        let Some(request) = decode_trace_request(line)? else {
+            return Ok(None);
+        };
+        Ok(request
+            .resource_spans
+            .iter()
+            .flat_map(|rs| &rs.scope_spans)
+            .flat_map(|ss| &ss.spans)
+            .find_map(|span| {
+                entry_span_ids(&SpanIds {
+                    trace_id: &span.trace_id,
+                    span_id: &span.span_id,
+                    parent_span_id: &span.parent_span_id,
+                    flags: span.flags,
+                })
+            }))
+    }
+
+    /// A realistic payload: `span_count` child spans with attributes and events, followed
+    /// by the entry span, so the whole payload has to be scanned.
+    fn large_trace_line(span_count: usize) -> String {
+        let trace_id = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10")
+            .unwrap()
+            .to_bytes();
+        let entry_id = SpanId::from_hex("1112131415161718").unwrap().to_bytes();
+        let mut spans: Vec<Span> = (0..span_count)
+            .map(|i| {
+                let span_id = (i as u64 + 1).to_be_bytes();
+                let mut span =
+                    create_proto_span(&trace_id, &span_id, Some(&entry_id), "child", Some(0x100));
+                span.attributes = (0..10)
+                    .map(|a| string_attribute(&format!("attribute.{a}"), &"v".repeat(40)))
+                    .collect();
+                span.events = vec![opentelemetry_proto::tonic::trace::v1::span::Event {
+                    name: "event".to_string(),
+                    attributes: vec![string_attribute("exception.message", "boom")],
+                    ..Default::default()
+                }];
+                span
+            })
+            .collect();
+        spans.push(create_proto_span(
+            &trace_id,
+            &entry_id,
+            Some(&SpanId::from_hex("2122232425262728").unwrap().to_bytes()),
+            "entry",
+            Some(SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK | SPAN_FLAGS_CONTEXT_IS_REMOTE_MASK),
+        ));
+        create_exporter_json_line(&create_test_request(spans))
+    }
+
+    #[test]
+    fn test_fast_path_matches_full_decode() {
+        let trace_id_bytes = TraceId::from_hex("0102030405060708090a0b0c0d0e0f10")
+            .unwrap()
+            .to_bytes();
+        let ids = |hex: &str| SpanId::from_hex(hex).unwrap().to_bytes();
+        let lines = vec![
+            large_trace_line(50),
+            create_test_json_line(create_test_request(vec![])),
+            create_test_json_line(create_test_request(vec![create_proto_span(
+                &trace_id_bytes,
+                &ids("aaaaaaaaaaaaaaaa"),
+                Some(&ids("bbbbbbbbbbbbbbbb")),
+                "internal",
+                Some(SPAN_FLAGS_CONTEXT_HAS_IS_REMOTE_MASK),
+            )])),
+            create_test_json_line(create_test_request(vec![
+                create_proto_span(&[0; 16], &ids("aaaaaaaaaaaaaaaa"), None, "invalid", None),
+                create_proto_span(
+                    &trace_id_bytes,
+                    &ids("cccccccccccccccc"),
+                    None,
+                    "root",
+                    None,
+                ),
+            ])),
+        ];
+
+        for line in &lines {
+            assert_eq!(
+                extract_trace_info_from_json_line(line).unwrap(),
+                extract_trace_info_full_decode(line).unwrap()
+            );
+        }
+        assert_eq!(
+            extract_trace_info_from_json_line(&lines[0]).unwrap(),
+            Some((
+                TraceId::from_bytes(trace_id_bytes),
+                SpanId::from_hex("1112131415161718").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_buffers_release_large_allocations() {
+        let mut buffers = DecodeBuffers::default();
+        let small = base64_engine.encode(b"small");
+        assert_eq!(buffers.decode(&small, true, "").unwrap(), b"small");
+        buffers.trim();
+        assert!(buffers.raw.capacity() > 0);
+
+        let large = base64_engine.encode(vec![0u8; MAX_RETAINED_BUFFER_BYTES + 1]);
+        buffers.decode(&large, true, "").unwrap();
+        buffers.trim();
+        assert_eq!(buffers.raw.capacity(), 0);
+    }
 }
@@ -0,0 +1,189 @@
+use crate::otlp_parsing;
+use lambda_extension::tracing;
+use std::env;
+use std::str::FromStr;
+use uuid::Uuid;
+
+// Lambda runtime environment variables identifying the function and its execution environment
+const ENV_VAR_FUNCTION_NAME: &str = "AWS_LAMBDA_FUNCTION_NAME";
+const ENV_VAR_LOG_STREAM_NAME: &str = "AWS_LAMBDA_LOG_STREAM_NAME";
+
+/// How the partition key of each Kinesis record is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartitionKeyStrategy {
+    /// A random UUID per record, spreading records evenly across shards.
+    #[default]
+    Random,
+    /// The trace ID found in the payload, so all spans of a trace land on the same shard.
+    TraceId,
+    /// The Lambda function name.
+    FunctionName,
+    /// The execution environment, identified by its log stream name.
+    ExecutionEnvironment,
+}
+
+impl FromStr for PartitionKeyStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "random" => Ok(Self::Random),
+            "trace-id" | "trace_id" => Ok(Self::TraceId),
+            "function" | "function-name" | "function_name" => Ok(Self::FunctionName),
+            "execution-environment" | "execution_environment" => Ok(Self::ExecutionEnvironment),
+            _ => Err(format!("Invalid partition key strategy: {s}")),
+        }
+    }
+}
+
+/// Produces partition keys according to a `PartitionKeyStrategy`.
+///
+/// Function and execution environment keys have very few distinct values, so they are
+/// suffixed with a bucket number rotating over `spread` values to avoid a single hot shard.
+/// Trace IDs are high-cardinality already; records without one fall back to a random key.
+#[derive(Debug, Default)]
+pub struct PartitionKeyGenerator {
+    strategy: PartitionKeyStrategy,
+    static_key: Option<String>,
+    spread: usize,
+    next_bucket: usize,
+}
+
+impl PartitionKeyGenerator {
+    pub fn new(strategy: PartitionKeyStrategy, spread: usize) -> Self {
+        let static_key = match strategy {
+            PartitionKeyStrategy::FunctionName => env::var(ENV_VAR_FUNCTION_NAME).ok(),
+            PartitionKeyStrategy::ExecutionEnvironment => env::var(ENV_VAR_LOG_STREAM_NAME).ok(),
+            PartitionKeyStrategy::Random | PartitionKeyStrategy::TraceId => None,
+        };
+        if static_key.is_none()
+            && matches!(
+                strategy,
+                PartitionKeyStrategy::FunctionName | PartitionKeyStrategy::ExecutionEnvironment
+            )
+        {
+            tracing::warn!(
+                ?strategy,
+                "Partition key source not available, falling back to random partition keys"
+            );
+        }
+
+        Self {
+            strategy,
+            static_key,
+            spread: spread.max(1),
+            next_bucket: 0,
+        }
+    }
+
+    /// Returns the key a line must share with others to be packed into the same record.
+    /// Only the trace-id strategy ties a line to a particular key.
+    pub fn affinity_key(&self, line: &str) -> Option<String> {
+        if self.strategy != PartitionKeyStrategy::TraceId {
+            return None;
+        }
+        match otlp_parsing::extract_trace_id_from_json_line(line) {
+            Ok(trace_id) => trace_id.map(|trace_id| format!("{:032x}", trace_id)),
+            Err(e) => {
+                tracing::debug!(error = %e, "Could not extract trace ID for partition key");
+                None
+            }
+        }
+    }
+
+    /// Returns the partition key for a record, given the affinity key of its lines.
+    pub fn next_key(&mut self, affinity_key: Option<String>) -> String {
+        if let Some(key) = affinity_key {
+            return key;
+        }
+        match &self.static_key {
+            Some(key) if self.spread > 1 => {
+                let bucket = self.next_bucket;
+                self.next_bucket = (self.next_bucket + 1) % self.spread;
+                format!("{key}-{bucket}")
+            }
+            Some(key) => key.clone(),
+            None => Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strategy_from_str() {
+        assert_eq!(
+            "random".parse::<PartitionKeyStrategy>(),
+            Ok(PartitionKeyStrategy::Random)
+        );
+        assert_eq!(
+            "TRACE-ID".parse::<PartitionKeyStrategy>(),
+            Ok(PartitionKeyStrategy::TraceId)
+        );
+        assert_eq!(
+            "function".parse::<PartitionKeyStrategy>(),
+            Ok(PartitionKeyStrategy::FunctionName)
+        );
+        assert_eq!(
+            "execution-environment".parse::<PartitionKeyStrategy>(),
+            Ok(PartitionKeyStrategy::ExecutionEnvironment)
+        );
+        assert!("shard-0".parse::<PartitionKeyStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_random_keys_are_uuids() {
+        let mut generator = PartitionKeyGenerator::default();
+        let key = generator.next_key(None);
+        assert!(Uuid::parse_str(&key).is_ok());
+        assert_ne!(key, generator.next_key(None));
+    }
+
+    #[test]
+    fn test_static_key_rotates_over_spread() {
+        let mut generator = PartitionKeyGenerator {
+            strategy: PartitionKeyStrategy::FunctionName,
+            static_key: Some("my-function".to_string()),
+            spread: 3,
+            next_bucket: 0,
+        };
+        let keys: Vec<String> = (0..4).map(|_| generator.next_key(None)).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "my-function-0",
+                "my-function-1",
+                "my-function-2",
+                "my-function-0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_static_key_without_spread() {
+        let mut generator = PartitionKeyGenerator {
+            strategy: PartitionKeyStrategy::ExecutionEnvironment,
+            static_key: Some("2024/01/01/[$LATEST]abc".to_string()),
+            spread: 1,
+            next_bucket: 0,
+        };
+        assert_eq!(generator.next_key(None), "2024/01/01/[$LATEST]abc");
+    }
+
+    #[test]
+    fn test_trace_id_strategy_falls_back_to_random() {
+        let mut generator = PartitionKeyGenerator::new(PartitionKeyStrategy::TraceId, 1);
+        let affinity = generator.affinity_key("not an otlp line");
+        assert!(affinity.is_none());
+        assert!(Uuid::parse_str(&generator.next_key(affinity)).is_ok());
+    }
+
+    #[test]
+    fn test_affinity_key_wins() {
+        let mut generator = PartitionKeyGenerator::new(PartitionKeyStrategy::TraceId, 1);
+        let key = generator.next_key(Some("0102030405060708090a0b0c0d0e0f10".to_string()));
+        assert_eq!(key, "0102030405060708090a0b0c0d0e0f10");
+    }
+}
@@ -0,0 +1,355 @@
+use lambda_extension::tracing;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+// Number of pipe events buffered between the reader task and the event loop
+pub const PIPE_CHANNEL_CAPACITY: usize = 4096;
+
+// Delay before reopening the pipe after an I/O error
+const REOPEN_DELAY: Duration = Duration::from_millis(100);
+
+// Stop waiting for a flush boundary this long before the invocation deadline
+pub const BOUNDARY_SAFETY_MARGIN_MS: u64 = 200;
+
+// Without platform.runtimeDone to wait for, stop after this long without pipe data
+pub const PIPE_IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// What the background reader saw on the named pipe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipeEvent {
+    /// A non-empty otlp-stdout line.
+    Line(String),
+    /// All writers closed the pipe. The span exporter opens and closes the pipe on every
+    /// flush (even with nothing to write), so this marks the end of an invocation's data.
+    FlushBoundary,
+}
+
+/// Lines read before a flush boundary, or before giving up on one.
+#[derive(Debug, Default)]
+pub struct PipeBatch {
+    pub lines: Vec<String>,
+    /// Whether the batch ended at a flush boundary rather than at the timeout.
+    pub complete: bool,
+}
+
+/// How an INVOKE waits for the function's data on the pipe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeWait {
+    /// Until the flush boundary, close to the deadline at most: the function writes to the
+    /// pipe on every invocation.
+    Boundary,
+    /// Until the flush boundary or the invocation's `platform.runtimeDone`.
+    RuntimeDone,
+    /// Until the flush boundary or [`PIPE_IDLE_TIMEOUT`] without pipe data.
+    Idle,
+}
+
+impl PipeWait {
+    /// Waiting for a boundary only pays off once a writer has opened the pipe. A function
+    /// that never writes, or relies on generated traces, would otherwise be held until its
+    /// deadline on every invocation.
+    pub fn choose(writer_seen: bool, generate_traces: bool, runtime_done_subscribed: bool) -> Self {
+        if writer_seen && !generate_traces {
+            Self::Boundary
+        } else if runtime_done_subscribed {
+            Self::RuntimeDone
+        } else {
+            Self::Idle
+        }
+    }
+}
+
+/// Starts a long-lived task that reads the named pipe continuously, reopening it after
+/// each EOF, so writers never wait for the next INVOKE to have their data consumed.
+/// `writer_seen` is set once a writer opened the pipe.
+pub fn spawn_reader(path: PathBuf, writer_seen: Arc<AtomicBool>) -> mpsc::Receiver<PipeEvent> {
+    let (tx, rx) = mpsc::channel(PIPE_CHANNEL_CAPACITY);
+    tokio::spawn(read_loop(path, tx, writer_seen));
+    rx
+}
+
+async fn read_loop(path: PathBuf, tx: mpsc::Sender<PipeEvent>, writer_seen: Arc<AtomicBool>) {
+    loop {
+        // Opening a FIFO for reading waits until a writer opens it
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::error!(error = %e, path = %path.display(), "Failed to open named pipe for reading");
+                tokio::time::sleep(REOPEN_DELAY).await;
+                continue;
+            }
+        };
+        tracing::trace!("Named pipe opened: {}", path.display());
+        writer_seen.store(true, Ordering::Relaxed);
+
+        let mut reader = BufReader::new(file);
+        let mut line_buffer = String::new();
+        loop {
+            line_buffer.clear();
+            match reader.read_line(&mut line_buffer).await {
+                Ok(0) => {
+                    if tx.send(PipeEvent::FlushBoundary).await.is_err() {
+                        return;
+                    }
+                    break;
+                }
+                Ok(_) => {
+                    let line = line_buffer.trim_end();
+                    if !line.is_empty() && tx.send(PipeEvent::Line(line.to_string())).await.is_err()
+                    {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, path = %path.display(), "Error reading line from named pipe");
+                    tokio::time::sleep(REOPEN_DELAY).await;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// How long an INVOKE may wait for its flush boundary, leaving a margin before the deadline.
+pub fn boundary_timeout(deadline_ms: u64, now_ms: u64) -> Duration {
+    Duration::from_millis(
+        deadline_ms
+            .saturating_sub(now_ms)
+            .saturating_sub(BOUNDARY_SAFETY_MARGIN_MS),
+    )
+}
+
+/// Collects lines until the next flush boundary, waiting at most `timeout`.
+///
+/// A boundary can't be tied to a specific request, so if an earlier wait timed out,
+/// that invocation's late boundary ends the next batch early; the remaining lines are
+/// then picked up by the following call.
+pub async fn recv_until_boundary(
+    rx: &mut mpsc::Receiver<PipeEvent>,
+    timeout: Duration,
+) -> PipeBatch {
+    let mut batch = PipeBatch::default();
+    let wait = async {
+        while let Some(event) = rx.recv().await {
+            match event {
+                PipeEvent::Line(line) => batch.lines.push(line),
+                PipeEvent::FlushBoundary => return true,
+            }
+        }
+        false
+    };
+    batch.complete = tokio::time::timeout(timeout, wait).await.unwrap_or(false);
+    batch
+}
+
+/// Collects the lines of an invocation as `wait` says, waiting at most `timeout`.
+/// `runtime_done` resolves once the invocation's `platform.runtimeDone` arrived.
+pub async fn recv_for_invoke(
+    rx: &mut mpsc::Receiver<PipeEvent>,
+    wait: PipeWait,
+    timeout: Duration,
+    runtime_done: impl Future<Output = ()>,
+) -> PipeBatch {
+    if wait == PipeWait::Boundary {
+        return recv_until_boundary(rx, timeout).await;
+    }
+    let mut batch = PipeBatch::default();
+    let collect = async {
+        tokio::pin!(runtime_done);
+        loop {
+            let idle = async {
+                match wait {
+                    PipeWait::Idle => tokio::time::sleep(PIPE_IDLE_TIMEOUT).await,
+                    _ => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Some(PipeEvent::Line(line)) => batch.lines.push(line),
+                    Some(PipeEvent::FlushBoundary) => return true,
+                    None => return false,
+                },
+                () = &mut runtime_done, if wait == PipeWait::RuntimeDone => return false,
+                () = idle => return false,
+            }
+        }
+    };
+    batch.complete = tokio::time::timeout(timeout, collect)
+        .await
+        .unwrap_or(false);
+    batch
+}
+
+/// Takes the lines that are already buffered, without waiting.
+pub fn drain_buffered(rx: &mut mpsc::Receiver<PipeEvent>) -> Vec<String> {
+    let mut lines = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        if let PipeEvent::Line(line) = event {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::stat::Mode;
+    use nix::unistd::mkfifo;
+    use tokio::io::AsyncWriteExt;
+
+    fn temp_fifo() -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "otlp-stdout-kinesis-extension-test-{}.pipe",
+            uuid::Uuid::new_v4()
+        ));
+        mkfifo(&path, Mode::S_IRWXU).unwrap();
+        path
+    }
+
+    async fn write_and_close(path: &PathBuf, data: &str) {
+        let mut writer = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .await
+            .unwrap();
+        writer.write_all(data.as_bytes()).await.unwrap();
+        writer.flush().await.unwrap();
+    }
+
+    /// Stops the reader, which would otherwise keep the runtime alive waiting for a writer.
+    async fn stop_reader(path: PathBuf, rx: mpsc::Receiver<PipeEvent>) {
+        drop(rx);
+        write_and_close(&path, "").await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reader_streams_lines_and_boundaries() {
+        let path = temp_fifo();
+        let mut rx = spawn_reader(path.clone(), Arc::default());
+
+        write_and_close(&path, "first\n\nsecond\n").await;
+        let batch = recv_until_boundary(&mut rx, Duration::from_secs(5)).await;
+        assert!(batch.complete);
+        assert_eq!(batch.lines, vec!["first", "second"]);
+
+        // The reader reopens the pipe for the next invocation
+        write_and_close(&path, "third\n").await;
+        let batch = recv_until_boundary(&mut rx, Duration::from_secs(5)).await;
+        assert!(batch.complete);
+        assert_eq!(batch.lines, vec!["third"]);
+
+        stop_reader(path, rx).await;
+    }
+
+    #[tokio::test]
+    async fn test_empty_flush_is_a_boundary() {
+        let path = temp_fifo();
+        let writer_seen = Arc::new(AtomicBool::new(false));
+        let mut rx = spawn_reader(path.clone(), writer_seen.clone());
+        assert!(!writer_seen.load(Ordering::Relaxed));
+
+        write_and_close(&path, "").await;
+        let batch = recv_until_boundary(&mut rx, Duration::from_secs(5)).await;
+        assert!(batch.complete);
+        assert!(batch.lines.is_empty());
+        assert!(writer_seen.load(Ordering::Relaxed));
+
+        stop_reader(path, rx).await;
+    }
+
+    #[tokio::test]
+    async fn test_recv_until_boundary_times_out() {
+        let (tx, mut rx) = mpsc::channel(8);
+        tx.send(PipeEvent::Line("partial".to_string()))
+            .await
+            .unwrap();
+
+        let batch = recv_until_boundary(&mut rx, Duration::from_millis(20)).await;
+        assert!(!batch.complete);
+        assert_eq!(batch.lines, vec!["partial"]);
+    }
+
+    #[test]
+    fn test_pipe_wait_choice() {
+        assert_eq!(PipeWait::choose(true, false, true), PipeWait::Boundary);
+        assert_eq!(PipeWait::choose(false, false, true), PipeWait::RuntimeDone);
+        assert_eq!(PipeWait::choose(true, true, true), PipeWait::RuntimeDone);
+        assert_eq!(PipeWait::choose(false, false, false), PipeWait::Idle);
+    }
+
+    #[tokio::test]
+    async fn test_invoke_without_pipe_writer_returns_promptly() {
+        let path = temp_fifo();
+        let writer_seen = Arc::new(AtomicBool::new(false));
+        let mut rx = spawn_reader(path.clone(), writer_seen.clone());
+        let deadline_wait = Duration::from_secs(60);
+        let wait = PipeWait::choose(writer_seen.load(Ordering::Relaxed), false, true);
+
+        // The invocation's runtimeDone ends the wait, not the deadline
+        let started = std::time::Instant::now();
+        let runtime_done = tokio::time::sleep(Duration::from_millis(20));
+        let batch = recv_for_invoke(&mut rx, wait, deadline_wait, runtime_done).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!batch.complete);
+        assert!(batch.lines.is_empty());
+
+        // Without platform telemetry, a quiet pipe ends it
+        let started = std::time::Instant::now();
+        let batch = recv_for_invoke(
+            &mut rx,
+            PipeWait::Idle,
+            deadline_wait,
+            std::future::pending(),
+        )
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(batch.lines.is_empty());
+
+        stop_reader(path, rx).await;
+    }
+
+    #[tokio::test]
+    async fn test_runtime_done_wait_still_ends_at_boundary() {
+        let (tx, mut rx) = mpsc::channel(8);
+        tx.send(PipeEvent::Line("span".to_string())).await.unwrap();
+        tx.send(PipeEvent::FlushBoundary).await.unwrap();
+
+        let batch = recv_for_invoke(
+            &mut rx,
+            PipeWait::RuntimeDone,
+            Duration::from_secs(60),
+            std::future::pending(),
+        )
+        .await;
+        assert!(batch.complete);
+        assert_eq!(batch.lines, vec!["span"]);
+    }
+
+    #[test]
+    fn test_boundary_timeout() {
+        assert_eq!(
+            boundary_timeout(10_000, 7_000),
+            Duration::from_millis(2_800)
+        );
+        assert_eq!(boundary_timeout(10_000, 9_900), Duration::ZERO);
+        assert_eq!(boundary_timeout(10_000, 12_000), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_drain_buffered_skips_boundaries() {
+        let (tx, mut rx) = mpsc::channel(8);
+        tx.send(PipeEvent::Line("a".to_string())).await.unwrap();
+        tx.send(PipeEvent::FlushBoundary).await.unwrap();
+        tx.send(PipeEvent::Line("b".to_string())).await.unwrap();
+
+        assert_eq!(drain_buffered(&mut rx), vec!["a", "b"]);
+    }
+}
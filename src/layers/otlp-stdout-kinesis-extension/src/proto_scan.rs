@@ -0,0 +1,284 @@
+use anyhow::{Result, bail};
+use std::ops::ControlFlow;
+
+// Field numbers along ExportTraceServiceRequest -> ResourceSpans -> ScopeSpans -> Span
+const REQUEST_RESOURCE_SPANS: u32 = 1;
+const RESOURCE_SPANS_SCOPE_SPANS: u32 = 2;
+const SCOPE_SPANS_SPANS: u32 = 2;
+const SPAN_TRACE_ID: u32 = 1;
+const SPAN_SPAN_ID: u32 = 2;
+const SPAN_PARENT_SPAN_ID: u32 = 4;
+const SPAN_FLAGS: u32 = 16;
+
+// Protobuf wire types
+const WIRE_VARINT: u8 = 0;
+const WIRE_I64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_I32: u8 = 5;
+
+/// The identifying fields of a span, borrowed from the encoded payload.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpanIds<'a> {
+    pub trace_id: &'a [u8],
+    pub span_id: &'a [u8],
+    pub parent_span_id: &'a [u8],
+    pub flags: u32,
+}
+
+/// Reads protobuf wire format from a byte slice.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some(&byte) = self.buf.get(self.pos) else {
+                bail!("Truncated varint");
+            };
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Varint longer than 10 bytes")
+    }
+
+    fn key(&mut self) -> Result<(u32, u8)> {
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).unwrap_or(u32::MAX);
+        if field == 0 {
+            bail!("Invalid field number 0");
+        }
+        Ok((field, (key & 0x7) as u8))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len());
+        let Some(end) = end else {
+            bail!(
+                "Field length exceeds the remaining {} bytes",
+                self.buf.len() - self.pos
+            );
+        };
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = usize::try_from(self.varint()?).unwrap_or(usize::MAX);
+        self.take(len)
+    }
+
+    fn fixed32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn skip(&mut self, wire_type: u8) -> Result<()> {
+        match wire_type {
+            WIRE_VARINT => self.varint().map(drop),
+            WIRE_I64 => self.take(8).map(drop),
+            WIRE_LEN => self.bytes().map(drop),
+            WIRE_I32 => self.take(4).map(drop),
+            _ => bail!("Unsupported wire type {wire_type}"),
+        }
+    }
+
+    /// Returns the next embedded message with the given field number, skipping other fields.
+    fn next_message(&mut self, field_number: u32) -> Result<Option<Reader<'a>>> {
+        while !self.is_empty() {
+            let (field, wire_type) = self.key()?;
+            if field == field_number && wire_type == WIRE_LEN {
+                return Ok(Some(Reader::new(self.bytes()?)));
+            }
+            self.skip(wire_type)?;
+        }
+        Ok(None)
+    }
+}
+
+fn read_span<'a>(mut span: Reader<'a>) -> Result<SpanIds<'a>> {
+    let mut ids = SpanIds::default();
+    while !span.is_empty() {
+        match span.key()? {
+            (SPAN_TRACE_ID, WIRE_LEN) => ids.trace_id = span.bytes()?,
+            (SPAN_SPAN_ID, WIRE_LEN) => ids.span_id = span.bytes()?,
+            (SPAN_PARENT_SPAN_ID, WIRE_LEN) => ids.parent_span_id = span.bytes()?,
+            (SPAN_FLAGS, WIRE_I32) => ids.flags = span.fixed32()?,
+            (_, wire_type) => span.skip(wire_type)?,
+        }
+    }
+    Ok(ids)
+}
+
+/// Visits the spans of an encoded `ExportTraceServiceRequest` in order, without
+/// decoding whole messages: only the ID fields and flags of each span are read, and
+/// everything else (resources, attributes, events, ...) is skipped over.
+///
+/// Stops as soon as `visit` breaks and returns its value. Returns `Err` if the payload
+/// isn't valid protobuf up to that point.
+pub fn scan_spans<B>(
+    request: &[u8],
+    visit: &mut impl FnMut(SpanIds<'_>) -> ControlFlow<B>,
+) -> Result<Option<B>> {
+    let mut request = Reader::new(request);
+    while let Some(mut resource_spans) = request.next_message(REQUEST_RESOURCE_SPANS)? {
+        while let Some(mut scope_spans) = resource_spans.next_message(RESOURCE_SPANS_SCOPE_SPANS)? {
+            while let Some(span) = scope_spans.next_message(SCOPE_SPANS_SPANS)? {
+                if let ControlFlow::Break(value) = visit(read_span(span)?) {
+                    return Ok(Some(value));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue, any_value};
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+    use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span, span::Event};
+    use prost::Message;
+
+    fn span(id: u8, parent: Option<u8>, flags: u32) -> Span {
+        Span {
+            trace_id: vec![id; 16],
+            span_id: vec![id; 8],
+            parent_span_id: parent.map_or(Vec::new(), |p| vec![p; 8]),
+            name: format!("span-{id}"),
+            flags,
+            start_time_unix_nano: 1_704_067_200_000_000_000,
+            attributes: vec![KeyValue {
+                key: "key".to_string(),
+                value: Some(AnyValue {
+                    value: Some(any_value::Value::IntValue(-1)),
+                }),
+            }],
+            events: vec![Event {
+                name: "event".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn request(scopes: Vec<Vec<Span>>) -> Vec<u8> {
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource::default()),
+                scope_spans: scopes
+                    .into_iter()
+                    .map(|spans| ScopeSpans {
+                        spans,
+                        ..Default::default()
+                    })
+                    .collect(),
+                schema_url: "https://opentelemetry.io/schemas/1.0.0".to_string(),
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    // Owned copy of the fields of a visited span
+    type OwnedIds = (Vec<u8>, Vec<u8>, Vec<u8>, u32);
+
+    fn collect(bytes: &[u8]) -> Result<Vec<OwnedIds>> {
+        let mut spans = Vec::new();
+        scan_spans::<()>(bytes, &mut |ids| {
+            spans.push((
+                ids.trace_id.to_vec(),
+                ids.span_id.to_vec(),
+                ids.parent_span_id.to_vec(),
+                ids.flags,
+            ));
+            ControlFlow::Continue(())
+        })?;
+        Ok(spans)
+    }
+
+    #[test]
+    fn test_scan_visits_all_spans_in_order() {
+        let bytes = request(vec![
+            vec![span(1, None, 0x301), span(2, Some(1), 0)],
+            vec![span(3, Some(2), 0x100)],
+        ]);
+
+        let spans = collect(&bytes).unwrap();
+        assert_eq!(
+            spans,
+            vec![
+                (vec![1; 16], vec![1; 8], Vec::new(), 0x301),
+                (vec![2; 16], vec![2; 8], vec![1; 8], 0),
+                (vec![3; 16], vec![3; 8], vec![2; 8], 0x100),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_stops_when_visitor_breaks() {
+        let bytes = request(vec![vec![
+            span(1, Some(9), 0),
+            span(2, None, 0),
+            span(3, None, 0),
+        ]]);
+
+        let mut visited = 0;
+        let found = scan_spans(&bytes, &mut |ids| {
+            visited += 1;
+            if ids.parent_span_id.is_empty() {
+                ControlFlow::Break(ids.span_id.to_vec())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+        assert_eq!(found, Some(vec![2; 8]));
+        assert_eq!(visited, 2);
+    }
+
+    #[test]
+    fn test_scan_empty_request() {
+        assert!(collect(&[]).unwrap().is_empty());
+        assert!(collect(&request(vec![vec![]])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_scan_rejects_invalid_payloads() {
+        // Not protobuf: 't' is field 14 with the end-group wire type
+        assert!(collect(b"this is not protobuf data").is_err());
+
+        // Truncated in the middle of a span
+        let bytes = request(vec![vec![span(1, None, 0)]]);
+        assert!(collect(&bytes[..bytes.len() - 3]).is_err());
+
+        // Varint that never terminates
+        assert!(collect(&[0x08, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_varint_decoding() {
+        let mut reader = Reader::new(&[0x96, 0x01, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert_eq!(reader.varint().unwrap(), 150);
+        assert_eq!(reader.varint().unwrap(), u64::from(u32::MAX));
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::batching::RetryPolicy;
+use crate::config::Config;
+use crate::firehose::{FirehoseBatch, FirehoseSink};
+use crate::kinesis::{KinesisBatch, KinesisSink};
+use crate::otlp_http::OtlpHttpSink;
+use crate::partition_key::PartitionKeyGenerator;
+use crate::spool::Spool;
+use async_trait::async_trait;
+use aws_sdk_firehose::Client as FirehoseClient;
+use aws_sdk_kinesis::Client as KinesisClient;
+use lambda_extension::{Error, tracing};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+
+/// Destination for the otlp-stdout lines collected by the extension.
+///
+/// Lines are handed over one at a time with `write_line` and delivered in `flush`,
+/// which the event loop calls once per INVOKE and on SHUTDOWN.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Accepts a single otlp-stdout JSON line.
+    async fn write_line(&self, line: String) -> Result<(), Error>;
+
+    /// Receives the deadline (epoch milliseconds) of the current INVOKE or SHUTDOWN event,
+    /// for sinks that bound their delivery time by it.
+    fn set_deadline(&self, _deadline_ms: u64) {}
+
+    /// Delivers everything accepted since the last flush.
+    async fn flush(&self) -> Result<(), Error>;
+}
+
+/// The available sink implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Kinesis,
+    Firehose,
+    OtlpHttp,
+    Stdout,
+    File,
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "kinesis" => Ok(Self::Kinesis),
+            "firehose" => Ok(Self::Firehose),
+            "otlp" | "otlp-http" | "otlp_http" => Ok(Self::OtlpHttp),
+            "stdout" => Ok(Self::Stdout),
+            "file" => Ok(Self::File),
+            _ => Err(format!("Invalid sink: {s}")),
+        }
+    }
+}
+
+/// Creates the sink selected in the configuration.
+pub async fn build_sink(config: &Config) -> Result<Box<dyn Sink>, Error> {
+    let sink: Box<dyn Sink> = match config.sink {
+        SinkKind::Kinesis => {
+            let stream_name = config.kinesis_stream_name.clone().ok_or_else(|| {
+                Error::from("Kinesis sink selected but no stream name is configured")
+            })?;
+            let aws_config = aws_config::from_env().load().await;
+
+            let partition_keys = PartitionKeyGenerator::new(
+                config.partition_key_strategy,
+                config.partition_key_spread,
+            );
+            let mut batch = KinesisBatch::new(partition_keys);
+            if config.enable_record_aggregation {
+                batch = batch.with_aggregation(config.record_aggregation_target_bytes);
+            }
+
+            let mut sink = KinesisSink::new(
+                KinesisClient::new(&aws_config),
+                stream_name,
+                batch,
+                RetryPolicy::new(config.kinesis_max_attempts, config.kinesis_retry_budget_ms),
+                config.kinesis_max_concurrency,
+            );
+            if config.enable_spool {
+                sink = sink.with_spool(Spool::new(
+                    config.spool_path.clone(),
+                    config.spool_max_bytes,
+                    Duration::from_secs(config.spool_max_age_secs),
+                ));
+            }
+            Box::new(sink)
+        }
+        SinkKind::Firehose => {
+            let delivery_stream_name =
+                config
+                    .firehose_delivery_stream_name
+                    .clone()
+                    .ok_or_else(|| {
+                        Error::from(
+                            "Firehose sink selected but no delivery stream name is configured",
+                        )
+                    })?;
+            let aws_config = aws_config::from_env().load().await;
+
+            let mut batch = FirehoseBatch::default();
+            if config.enable_record_aggregation {
+                batch = batch.with_aggregation(config.record_aggregation_target_bytes);
+            }
+
+            Box::new(FirehoseSink::new(
+                FirehoseClient::new(&aws_config),
+                delivery_stream_name,
+                batch,
+                RetryPolicy::new(
+                    config.firehose_max_attempts,
+                    config.firehose_retry_budget_ms,
+                ),
+                config.firehose_max_concurrency,
+            ))
+        }
+        SinkKind::OtlpHttp => {
+            let endpoint = config.otlp_endpoint.as_deref().ok_or_else(|| {
+                Error::from("OTLP/HTTP sink selected but no endpoint is configured")
+            })?;
+            Box::new(OtlpHttpSink::new(
+                endpoint,
+                &config.otlp_headers,
+                config.otlp_gzip,
+                Duration::from_millis(config.otlp_timeout_ms),
+            )?)
+        }
+        SinkKind::Stdout => Box::new(StdoutSink),
+        SinkKind::File => Box::new(FileSink::open(config.file_sink_path.clone()).await?),
+    };
+    tracing::debug!("Using {} sink", sink.name());
+    Ok(sink)
+}
+
+/// Writes each line to stdout, which ends up in CloudWatch Logs.
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    async fn write_line(&self, line: String) -> Result<(), Error> {
+        // println! is blocking but acceptable for the low volume expected on this path
+        println!("{}", line);
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Appends each line to a local file, one JSON document per line.
+pub struct FileSink {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FileSink {
+    pub async fn open(path: PathBuf) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| Error::from(format!("Failed to open {}: {}", path.display(), e)))?;
+        Ok(Self {
+            path,
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn write_line(&self, line: String) -> Result<(), Error> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(line.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.writer
+            .lock()
+            .await
+            .flush()
+            .await
+            .map_err(|e| Error::from(format!("Failed to flush {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Keeps lines in memory so tests can assert on what the extension emitted.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySink {
+    pub lines: Mutex<Vec<String>>,
+    pub flushes: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Sink for MemorySink {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn write_line(&self, line: String) -> Result<(), Error> {
+        self.lines.lock().await.push(line);
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.flushes
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_kind_from_str() {
+        assert_eq!("kinesis".parse::<SinkKind>(), Ok(SinkKind::Kinesis));
+        assert_eq!("Firehose".parse::<SinkKind>(), Ok(SinkKind::Firehose));
+        assert_eq!("otlp-http".parse::<SinkKind>(), Ok(SinkKind::OtlpHttp));
+        assert_eq!("STDOUT".parse::<SinkKind>(), Ok(SinkKind::Stdout));
+        assert_eq!("file".parse::<SinkKind>(), Ok(SinkKind::File));
+        assert!("s3".parse::<SinkKind>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_appends_lines() {
+        let path = std::env::temp_dir().join(format!(
+            "otlp-stdout-kinesis-extension-test-{}.jsonl",
+            uuid::Uuid::new_v4()
+        ));
+        let sink = FileSink::open(path.clone()).await.unwrap();
+        sink.write_line("{\"a\":1}".to_string()).await.unwrap();
+        sink.write_line("{\"b\":2}".to_string()).await.unwrap();
+        sink.flush().await.unwrap();
+
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents, "{\"a\":1}\n{\"b\":2}\n");
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_memory_sink_records_lines() {
+        let sink = MemorySink::default();
+        sink.write_line("line".to_string()).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(*sink.lines.lock().await, vec!["line".to_string()]);
+        assert_eq!(sink.flushes.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,416 @@
+use lambda_extension::tracing;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+// Marks the start of every frame, so a reader can resynchronise after a damaged frame
+const FRAME_MAGIC: &[u8; 4] = b"OSK1";
+
+// Frame header: magic, payload length (u32 LE), payload CRC32 (u32 LE)
+const FRAME_HEADER_BYTES: usize = 12;
+
+// Payload prefix: spooled-at epoch milliseconds (u64 LE), partition key length (u16 LE)
+const PAYLOAD_PREFIX_BYTES: usize = 10;
+
+/// A record kept on disk until it can be delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpoolEntry {
+    pub partition_key: String,
+    pub data: Vec<u8>,
+    /// When the record was first written to the spool, in epoch milliseconds.
+    pub spooled_at_ms: u64,
+}
+
+impl SpoolEntry {
+    pub fn new(partition_key: String, data: Vec<u8>) -> Self {
+        Self {
+            partition_key,
+            data,
+            spooled_at_ms: now_ms(),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        // Kinesis partition keys are at most 256 characters, well within the u16 length
+        let key = self.partition_key.as_bytes();
+        let mut payload = Vec::with_capacity(PAYLOAD_PREFIX_BYTES + key.len() + self.data.len());
+        payload.extend_from_slice(&self.spooled_at_ms.to_le_bytes());
+        payload.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        payload.extend_from_slice(key);
+        payload.extend_from_slice(&self.data);
+
+        out.extend_from_slice(FRAME_MAGIC);
+        out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        out.extend_from_slice(&payload);
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let spooled_at_ms = u64::from_le_bytes(payload.get(0..8)?.try_into().ok()?);
+        let key_len = u16::from_le_bytes(payload.get(8..10)?.try_into().ok()?) as usize;
+        let key = payload.get(PAYLOAD_PREFIX_BYTES..PAYLOAD_PREFIX_BYTES + key_len)?;
+        Some(Self {
+            partition_key: String::from_utf8(key.to_vec()).ok()?,
+            data: payload[PAYLOAD_PREFIX_BYTES + key_len..].to_vec(),
+            spooled_at_ms,
+        })
+    }
+
+    fn frame_len(&self) -> usize {
+        FRAME_HEADER_BYTES + PAYLOAD_PREFIX_BYTES + self.partition_key.len() + self.data.len()
+    }
+}
+
+/// Decodes all intact frames in `bytes`.
+///
+/// Frames with a bad checksum or an unparsable payload are skipped by scanning ahead for the
+/// next frame marker; a truncated frame at the end (e.g. from an interrupted write) is ignored.
+/// Returns the entries and the number of bytes that had to be skipped.
+fn decode_frames(bytes: &[u8]) -> (Vec<SpoolEntry>, usize) {
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if !rest.starts_with(FRAME_MAGIC) {
+            let next = find_magic(&rest[1..]).map_or(rest.len(), |offset| offset + 1);
+            skipped += next;
+            pos += next;
+            continue;
+        }
+        let Some(header) = rest.get(..FRAME_HEADER_BYTES) else {
+            skipped += rest.len();
+            break;
+        };
+        let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let entry = rest
+            .get(FRAME_HEADER_BYTES..FRAME_HEADER_BYTES + len)
+            .filter(|payload| crc32fast::hash(payload) == crc)
+            .and_then(SpoolEntry::decode);
+        match entry {
+            Some(entry) => {
+                entries.push(entry);
+                pos += FRAME_HEADER_BYTES + len;
+            }
+            None => {
+                // Treat the marker as garbage and look for the next one
+                let next = find_magic(&rest[1..]).map_or(rest.len(), |offset| offset + 1);
+                skipped += next;
+                pos += next;
+            }
+        }
+    }
+    (entries, skipped)
+}
+
+fn find_magic(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .windows(FRAME_MAGIC.len())
+        .position(|window| window == FRAME_MAGIC)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Point-in-time copy of the spool counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpoolStats {
+    pub spooled: u64,
+    pub replayed: u64,
+    pub evicted: u64,
+}
+
+/// Bounded on-disk spool for records that could not be delivered.
+///
+/// Records are appended as checksummed frames to a single file and handed back by `drain`.
+/// Drained records stay on disk in a replay file until `commit_replay` confirms their outcome,
+/// so records are replayed again, rather than lost, if the flush that took them never finishes.
+/// The file never grows beyond `max_bytes` (the oldest records are evicted first), and
+/// records older than `max_age` are evicted instead of being replayed. The age counts from
+/// when a record was first spooled: a replayed record that fails again is written back with
+/// its original `spooled_at_ms`, so a record that keeps failing still ages out. /tmp only
+/// lives as long as the execution environment, so records spooled during SHUTDOWN are lost
+/// with it.
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    spooled: AtomicU64,
+    replayed: AtomicU64,
+    evicted: AtomicU64,
+}
+
+impl Spool {
+    pub fn new(path: PathBuf, max_bytes: u64, max_age: Duration) -> Self {
+        Self {
+            path,
+            max_bytes,
+            max_age,
+            spooled: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> SpoolStats {
+        SpoolStats {
+            spooled: self.spooled.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Appends records to the spool, evicting the oldest ones if the size cap is exceeded.
+    pub async fn append(&self, entries: Vec<SpoolEntry>) -> io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let new_bytes: u64 = entries.iter().map(|e| e.frame_len() as u64).sum();
+        let current_bytes = match fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let count = entries.len() as u64;
+
+        if current_bytes + new_bytes <= self.max_bytes {
+            let mut buf = Vec::with_capacity(new_bytes as usize);
+            for entry in &entries {
+                entry.encode(&mut buf);
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(&buf).await?;
+            file.flush().await?;
+        } else {
+            let mut all = self.read_entries(&self.path).await?;
+            all.extend(entries);
+            let mut total: u64 = all.iter().map(|e| e.frame_len() as u64).sum();
+            let mut evict = 0;
+            while total > self.max_bytes && evict < all.len() {
+                total -= all[evict].frame_len() as u64;
+                evict += 1;
+            }
+            tracing::warn!(
+                "Spool size limit of {} bytes reached, evicting {} oldest records",
+                self.max_bytes,
+                evict
+            );
+            self.evicted.fetch_add(evict as u64, Ordering::Relaxed);
+            self.rewrite(&all[evict..]).await?;
+        }
+
+        self.spooled.fetch_add(count, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Moves all spooled records to the replay file and returns those still within the age cap.
+    ///
+    /// Records left in the replay file by a flush that never committed are returned first.
+    pub async fn drain(&self) -> io::Result<Vec<SpoolEntry>> {
+        let replay_path = self.replay_path();
+        let mut entries = self.read_entries(&replay_path).await?;
+        let spooled = self.read_entries(&self.path).await?;
+        if entries.is_empty() {
+            match fs::rename(&self.path, &replay_path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            entries = spooled;
+        } else if !spooled.is_empty() {
+            entries.extend(spooled);
+            write_atomically(&replay_path, &entries).await?;
+            fs::remove_file(&self.path).await?;
+        }
+
+        let cutoff = now_ms().saturating_sub(self.max_age.as_millis() as u64);
+        let (fresh, expired): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| entry.spooled_at_ms >= cutoff);
+        if !expired.is_empty() {
+            tracing::warn!(
+                "Evicting {} spooled records older than {:?}",
+                expired.len(),
+                self.max_age
+            );
+        }
+        self.evicted
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+        self.replayed
+            .fetch_add(fresh.len() as u64, Ordering::Relaxed);
+        Ok(fresh)
+    }
+
+    /// Removes the records handed out by `drain`, once the undelivered ones have been
+    /// appended back to the spool.
+    pub async fn commit_replay(&self) -> io::Result<()> {
+        match fs::remove_file(self.replay_path()).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn replay_path(&self) -> PathBuf {
+        self.path.with_extension("replaying")
+    }
+
+    async fn read_entries(&self, path: &Path) -> io::Result<Vec<SpoolEntry>> {
+        let bytes = match fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let (entries, skipped) = decode_frames(&bytes);
+        if skipped > 0 {
+            tracing::warn!(
+                "Skipped {} corrupted bytes in spool file {}",
+                skipped,
+                path.display()
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Replaces the spool contents through a temporary file, so a crash never leaves it half written.
+    async fn rewrite(&self, entries: &[SpoolEntry]) -> io::Result<()> {
+        write_atomically(&self.path, entries).await
+    }
+}
+
+async fn write_atomically(path: &Path, entries: &[SpoolEntry]) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        entry.encode(&mut buf);
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, &buf).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_spool(max_bytes: u64, max_age: Duration) -> Spool {
+        let path = std::env::temp_dir().join(format!(
+            "otlp-stdout-kinesis-extension-test-{}.spool",
+            uuid::Uuid::new_v4()
+        ));
+        Spool::new(path, max_bytes, max_age)
+    }
+
+    fn entry(key: &str, data: &str) -> SpoolEntry {
+        SpoolEntry::new(key.to_string(), data.as_bytes().to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_append_and_drain_round_trip() {
+        let spool = temp_spool(1_048_576, Duration::from_secs(60));
+        spool
+            .append(vec![entry("key-1", "first"), entry("key-2", "second")])
+            .await
+            .unwrap();
+        spool.append(vec![entry("key-3", "third")]).await.unwrap();
+
+        let drained = spool.drain().await.unwrap();
+        let data: Vec<&[u8]> = drained.iter().map(|e| e.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"first"[..], b"second", b"third"]);
+        assert_eq!(drained[1].partition_key, "key-2");
+
+        // Committing the replay removes the records
+        spool.commit_replay().await.unwrap();
+        assert!(spool.drain().await.unwrap().is_empty());
+        assert_eq!(
+            spool.stats(),
+            SpoolStats {
+                spooled: 3,
+                replayed: 3,
+                evicted: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uncommitted_replay_is_drained_again() {
+        let spool = temp_spool(1_048_576, Duration::from_secs(60));
+        spool.append(vec![entry("k", "first")]).await.unwrap();
+        assert_eq!(spool.drain().await.unwrap().len(), 1);
+
+        // The flush that took "first" never finished, while "second" was spooled meanwhile
+        spool.append(vec![entry("k", "second")]).await.unwrap();
+        let drained = spool.drain().await.unwrap();
+        let data: Vec<&[u8]> = drained.iter().map(|e| e.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"first"[..], b"second"]);
+
+        spool.commit_replay().await.unwrap();
+        assert!(spool.drain().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_size_cap_evicts_oldest_records() {
+        let frame = entry("k", "0123456789").frame_len() as u64;
+        let spool = temp_spool(frame * 2, Duration::from_secs(60));
+        for data in ["0000000000", "1111111111", "2222222222"] {
+            spool.append(vec![entry("k", data)]).await.unwrap();
+        }
+
+        let drained = spool.drain().await.unwrap();
+        let data: Vec<&[u8]> = drained.iter().map(|e| e.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"1111111111"[..], b"2222222222"]);
+        assert_eq!(spool.stats().evicted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_age_cap_evicts_stale_records() {
+        let spool = temp_spool(1_048_576, Duration::from_secs(60));
+        let mut stale = entry("k", "stale");
+        stale.spooled_at_ms -= 120_000;
+        spool
+            .append(vec![stale, entry("k", "fresh")])
+            .await
+            .unwrap();
+
+        let drained = spool.drain().await.unwrap();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].data, b"fresh");
+        assert_eq!(spool.stats().evicted, 1);
+        assert_eq!(spool.stats().replayed, 1);
+    }
+
+    #[test]
+    fn test_decode_skips_corrupted_and_truncated_frames() {
+        let mut bytes = Vec::new();
+        entry("k", "first").encode(&mut bytes);
+        let second_start = bytes.len();
+        entry("k", "second").encode(&mut bytes);
+        entry("k", "third").encode(&mut bytes);
+        let mut truncated = Vec::new();
+        entry("k", "fourth").encode(&mut truncated);
+        bytes.extend_from_slice(&truncated[..truncated.len() - 3]);
+
+        // Flip a payload byte of the second frame so its checksum no longer matches
+        let last = second_start + entry("k", "second").frame_len() - 1;
+        bytes[last] ^= 0xff;
+        // And put some garbage in front of everything
+        let mut damaged = b"garbage".to_vec();
+        damaged.extend_from_slice(&bytes);
+
+        let (entries, skipped) = decode_frames(&damaged);
+        let data: Vec<&[u8]> = entries.iter().map(|e| e.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"first"[..], b"third"]);
+        assert!(skipped > 0);
+    }
+}
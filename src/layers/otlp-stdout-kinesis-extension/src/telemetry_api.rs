@@ -0,0 +1,317 @@
+use crate::events::{ParsedPlatformEvent, PlatformEventData};
+use crate::logs::TelemetryLog;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use lambda_extension::{Error, LambdaTelemetry, LogBuffering, Status as LambdaStatus, tracing};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+// Port the Telemetry API delivers batches to (same default as lambda-extension)
+pub const TELEMETRY_PORT: u16 = 9003;
+
+// Telemetry API version and schema of the records we understand
+const TELEMETRY_API_PATH: &str = "/2022-07-01/telemetry";
+const TELEMETRY_SCHEMA_VERSION: &str = "2022-12-13";
+
+// Header identifying the registered extension to the Extensions and Telemetry APIs
+const EXTENSION_ID_HEADER: &str = "Lambda-Extension-Identifier";
+
+/// A record received from the Telemetry API.
+///
+/// lambda-extension's own listener rejects a whole batch when one record has a type it
+/// doesn't know, such as the SnapStart `platform.restore*` records, so batches are parsed
+/// here one record at a time. Log records are parsed here too, since lambda-extension
+/// only accepts them as strings and they are objects with the JSON log format.
+#[derive(Debug)]
+pub enum TelemetryRecord {
+    Lambda(LambdaTelemetry),
+    Restore(RestoreTelemetry),
+    Log(TelemetryLog),
+}
+
+impl TelemetryRecord {
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Self::Lambda(telemetry) => telemetry.time,
+            Self::Restore(telemetry) => telemetry.time,
+            Self::Log(log) => log.time,
+        }
+    }
+}
+
+/// A SnapStart restore record, not modelled by lambda-extension.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestoreTelemetry {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub record: RestoreRecord,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", content = "record")]
+pub enum RestoreRecord {
+    #[serde(rename = "platform.restoreStart", rename_all = "camelCase")]
+    Start {
+        runtime_version: Option<String>,
+        runtime_version_arn: Option<String>,
+    },
+    #[serde(rename = "platform.restoreRuntimeDone", rename_all = "camelCase")]
+    RuntimeDone {
+        status: LambdaStatus,
+        error_type: Option<String>,
+    },
+    #[serde(rename = "platform.restoreReport", rename_all = "camelCase")]
+    Report {
+        status: LambdaStatus,
+        error_type: Option<String>,
+        metrics: Option<RestoreReportMetrics>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreReportMetrics {
+    pub duration_ms: f64,
+}
+
+impl From<RestoreTelemetry> for ParsedPlatformEvent {
+    fn from(telemetry: RestoreTelemetry) -> Self {
+        let data = match telemetry.record {
+            RestoreRecord::Start {
+                runtime_version,
+                runtime_version_arn,
+            } => PlatformEventData::RestoreStart {
+                runtime_version,
+                runtime_version_arn,
+            },
+            RestoreRecord::RuntimeDone { status, error_type } => {
+                PlatformEventData::RestoreRuntimeDone { status, error_type }
+            }
+            RestoreRecord::Report {
+                status,
+                error_type,
+                metrics,
+            } => PlatformEventData::RestoreReport {
+                status,
+                error_type,
+                duration_ms: metrics.map(|m| m.duration_ms),
+            },
+        };
+        Self {
+            timestamp: telemetry.time,
+            // Restore events, like init events, aren't tied to a request
+            request_id: "".to_string(),
+            data,
+        }
+    }
+}
+
+/// Parses a Telemetry API batch, skipping the records that can't be parsed instead of
+/// failing the whole batch.
+pub fn parse_batch(body: &[u8]) -> Result<Vec<TelemetryRecord>, serde_json::Error> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body)?;
+    let mut records = Vec::with_capacity(values.len());
+    for value in values {
+        let record_type = value
+            .get("type")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let record = match record_type.as_str() {
+            "function" | "extension" => serde_json::from_value(value).map(TelemetryRecord::Log),
+            t if t.starts_with("platform.restore") => {
+                serde_json::from_value(value).map(TelemetryRecord::Restore)
+            }
+            _ => serde_json::from_value(value).map(TelemetryRecord::Lambda),
+        };
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                tracing::debug!(error = %e, %record_type, "Skipping unsupported telemetry record")
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Starts listening for Telemetry API batches on `port`, handing each parsed batch to
+/// `handler`. Must be called before subscribing, so no batch is sent to a closed port.
+pub async fn start_listener<F>(port: u16, handler: F) -> Result<(), Error>
+where
+    F: Fn(Vec<TelemetryRecord>) + Send + Sync + 'static,
+{
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| Error::from(format!("Failed to bind telemetry listener on {addr}: {e}")))?;
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to accept telemetry connection");
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let handler = handler.clone();
+                    async move { handle_request(req, handler.as_ref()).await }
+                });
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!(error = %e, "Telemetry connection closed with an error");
+                }
+            });
+        }
+    });
+    tracing::debug!("Telemetry listener started on {}", addr);
+    Ok(())
+}
+
+async fn handle_request(
+    req: Request<Incoming>,
+    handler: &(impl Fn(Vec<TelemetryRecord>) + ?Sized),
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let status = match req.into_body().collect().await {
+        Ok(body) => match parse_batch(&body.to_bytes()) {
+            Ok(records) => {
+                handler(records);
+                StatusCode::OK
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to parse telemetry batch");
+                StatusCode::BAD_REQUEST
+            }
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read telemetry request body");
+            StatusCode::BAD_REQUEST
+        }
+    };
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+/// Subscribes the extension to the Telemetry API, delivering to the listener on `port`.
+/// Subscriptions are only accepted during the init phase, before the first `/next` call.
+pub async fn subscribe(
+    extension_id: &str,
+    types: &[&str],
+    buffering: LogBuffering,
+    port: u16,
+) -> Result<(), Error> {
+    let runtime_api = env::var("AWS_LAMBDA_RUNTIME_API")
+        .map_err(|_| Error::from("AWS_LAMBDA_RUNTIME_API is not set"))?;
+    let body = serde_json::json!({
+        "schemaVersion": TELEMETRY_SCHEMA_VERSION,
+        "types": types,
+        "buffering": buffering,
+        "destination": {
+            "protocol": "HTTP",
+            "URI": format!("http://sandbox.localdomain:{port}"),
+        }
+    });
+
+    let response = reqwest::Client::new()
+        .put(format!("http://{runtime_api}{TELEMETRY_API_PATH}"))
+        .header(EXTENSION_ID_HEADER, extension_id)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| Error::from(format!("Telemetry API subscription failed: {e}")))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::from(format!(
+            "Telemetry API subscription failed. Status: {status}. Body: {body}"
+        )));
+    }
+    tracing::debug!("Subscribed to Telemetry API types {:?}", types);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_extension::LambdaTelemetryRecord;
+
+    #[test]
+    fn test_parse_batch_with_restore_and_unknown_records() {
+        let body = r#"[
+            {"time": "2024-01-01T00:00:00.000Z", "type": "platform.restoreStart",
+             "record": {"runtimeVersion": "java:21.v20"}},
+            {"time": "2024-01-01T00:00:00.080Z", "type": "platform.restoreRuntimeDone",
+             "record": {"status": "success", "spans": []}},
+            {"time": "2024-01-01T00:00:00.090Z", "type": "platform.restoreReport",
+             "record": {"status": "success", "metrics": {"durationMs": 90.5}}},
+            {"time": "2024-01-01T00:00:00.100Z", "type": "platform.somethingNew",
+             "record": {}},
+            {"time": "2024-01-01T00:00:00.100Z", "type": "platform.start",
+             "record": {"requestId": "req-1", "version": "$LATEST"}},
+            {"time": "2024-01-01T00:00:00.110Z", "type": "function",
+             "record": "plain text line"},
+            {"time": "2024-01-01T00:00:00.120Z", "type": "function",
+             "record": {"level": "INFO", "requestId": "req-1", "message": "json line"}}
+        ]"#;
+
+        let records = parse_batch(body.as_bytes()).unwrap();
+        assert_eq!(records.len(), 6);
+
+        let events: Vec<ParsedPlatformEvent> = records[..3]
+            .iter()
+            .map(|record| match record {
+                TelemetryRecord::Restore(telemetry) => telemetry.clone().into(),
+                other => panic!("expected a restore record, got {other:?}"),
+            })
+            .collect();
+        assert!(events.iter().all(|event| event.data.is_startup_event()));
+        assert!(matches!(
+            &events[0].data,
+            PlatformEventData::RestoreStart { runtime_version: Some(v), .. } if v == "java:21.v20"
+        ));
+        assert!(matches!(
+            events[2].data,
+            PlatformEventData::RestoreReport {
+                status: LambdaStatus::Success,
+                duration_ms: Some(90.5),
+                ..
+            }
+        ));
+
+        let TelemetryRecord::Lambda(start) = &records[3] else {
+            panic!("expected platform.start");
+        };
+        assert!(matches!(
+            &start.record,
+            LambdaTelemetryRecord::PlatformStart { request_id, .. } if request_id == "req-1"
+        ));
+        assert!(matches!(&records[4], TelemetryRecord::Log(log) if log.record.is_string()));
+        assert!(matches!(
+            &records[5],
+            TelemetryRecord::Log(log) if log.request_id() == Some("req-1")
+        ));
+    }
+
+    #[test]
+    fn test_parse_batch_rejects_non_array() {
+        assert!(parse_batch(b"{}").is_err());
+        assert!(parse_batch(b"[]").unwrap().is_empty());
+    }
+}
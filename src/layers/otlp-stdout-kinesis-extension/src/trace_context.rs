@@ -0,0 +1,232 @@
+use lambda_extension::TraceContext as LambdaTraceContext;
+use opentelemetry::trace::{SpanId, TraceFlags, TraceId};
+use rand::Rng;
+use std::str::FromStr;
+
+/// Where the parent of an invocation's `Lambda/Invoke` span was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceContextSource {
+    /// The entry span the function wrote to the named pipe.
+    Pipe,
+    /// The X-Ray tracing header of the invocation's platform events.
+    XRay,
+    /// A trace minted by the extension for an invocation with no other context.
+    Generated,
+}
+
+/// Which source wins when an invocation has both a pipe and an X-Ray trace context.
+/// The other one is still used when the preferred one is missing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceContextPrecedence {
+    /// The function's own trace, falling back to X-Ray for functions that emit no spans.
+    #[default]
+    Pipe,
+    /// The X-Ray trace, falling back to the function's own trace.
+    XRay,
+}
+
+impl TraceContextPrecedence {
+    pub fn preferred(&self) -> TraceContextSource {
+        match self {
+            Self::Pipe => TraceContextSource::Pipe,
+            Self::XRay => TraceContextSource::XRay,
+        }
+    }
+}
+
+impl FromStr for TraceContextPrecedence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pipe" => Ok(Self::Pipe),
+            "xray" | "x-ray" => Ok(Self::XRay),
+            _ => Err(format!("Invalid trace context precedence: {s}")),
+        }
+    }
+}
+
+/// The context an invocation's `Lambda/Invoke` span is parented to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParentContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    pub trace_flags: TraceFlags,
+    pub source: TraceContextSource,
+}
+
+impl ParentContext {
+    /// The function's entry span, as found on the named pipe.
+    pub fn from_entry_span(trace_id: TraceId, span_id: SpanId) -> Self {
+        Self {
+            trace_id,
+            span_id,
+            trace_flags: TraceFlags::SAMPLED,
+            source: TraceContextSource::Pipe,
+        }
+    }
+
+    /// A new trace, with the `Lambda/Invoke` span as its root.
+    pub fn generated() -> Self {
+        Self {
+            trace_id: TraceId::from_bytes(rand::rng().random::<[u8; 16]>()),
+            span_id: SpanId::INVALID,
+            trace_flags: TraceFlags::SAMPLED,
+            source: TraceContextSource::Generated,
+        }
+    }
+
+    /// Parses an X-Ray tracing header, e.g.
+    /// `Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1`.
+    ///
+    /// The W3C trace ID is the X-Ray one without its version and dashes. Without a
+    /// `Parent`, the span ID is invalid and the `Lambda/Invoke` span becomes a root.
+    pub fn from_xray_header(header: &str) -> Option<Self> {
+        let mut trace_id = None;
+        let mut span_id = SpanId::INVALID;
+        let mut sampled = false;
+        for field in header.split(';') {
+            let Some((key, value)) = field.trim().split_once('=') else {
+                continue;
+            };
+            match key {
+                "Root" => trace_id = xray_trace_id(value),
+                "Parent" => span_id = xray_span_id(value).unwrap_or(SpanId::INVALID),
+                "Sampled" => sampled = value == "1",
+                _ => {}
+            }
+        }
+        Some(Self {
+            trace_id: trace_id?,
+            span_id,
+            trace_flags: if sampled {
+                TraceFlags::SAMPLED
+            } else {
+                TraceFlags::default()
+            },
+            source: TraceContextSource::XRay,
+        })
+    }
+
+    /// Parses the `tracing` field of `platform.start`, `platform.runtimeDone` and
+    /// `platform.report`. Lambda's own span ID stands in for a header without `Parent`.
+    pub fn from_lambda_tracing(tracing: &LambdaTraceContext) -> Option<Self> {
+        let mut context = Self::from_xray_header(&tracing.value)?;
+        if context.span_id == SpanId::INVALID {
+            if let Some(span_id) = tracing.span_id.as_deref().and_then(xray_span_id) {
+                context.span_id = span_id;
+            }
+        }
+        Some(context)
+    }
+}
+
+// X-Ray trace IDs are "1-<8 hex digits of epoch seconds>-<24 hex digits>"
+fn xray_trace_id(root: &str) -> Option<TraceId> {
+    let mut parts = root.split('-');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("1"), Some(epoch), Some(random), None)
+            if epoch.len() == 8 && random.len() == 24 && is_hex(epoch) && is_hex(random) =>
+        {
+            TraceId::from_hex(&format!("{epoch}{random}"))
+                .ok()
+                .filter(|trace_id| *trace_id != TraceId::INVALID)
+        }
+        _ => None,
+    }
+}
+
+fn xray_span_id(value: &str) -> Option<SpanId> {
+    if value.len() != 16 || !is_hex(value) {
+        return None;
+    }
+    SpanId::from_hex(value)
+        .ok()
+        .filter(|span_id| *span_id != SpanId::INVALID)
+}
+
+fn is_hex(value: &str) -> bool {
+    value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_extension::TracingType;
+
+    #[test]
+    fn test_from_xray_header() {
+        let context = ParentContext::from_xray_header(
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1",
+        )
+        .unwrap();
+        assert_eq!(
+            context.trace_id,
+            TraceId::from_hex("5759e988bd862e3fe1be46a994272793").unwrap()
+        );
+        assert_eq!(
+            context.span_id,
+            SpanId::from_hex("53995c3f42cd8ad8").unwrap()
+        );
+        assert_eq!(context.trace_flags, TraceFlags::SAMPLED);
+        assert_eq!(context.source, TraceContextSource::XRay);
+
+        let unsampled =
+            ParentContext::from_xray_header("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=0")
+                .unwrap();
+        assert!(!unsampled.trace_flags.is_sampled());
+        assert_eq!(unsampled.span_id, SpanId::INVALID);
+
+        for invalid in [
+            "",
+            "Parent=53995c3f42cd8ad8;Sampled=1",
+            "Root=2-5759e988-bd862e3fe1be46a994272793",
+            "Root=1-5759e988-bd862e3fe1be46a99427279",
+            "Root=1-5759e98g-bd862e3fe1be46a994272793",
+        ] {
+            assert!(
+                ParentContext::from_xray_header(invalid).is_none(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_lambda_tracing() {
+        let tracing = LambdaTraceContext {
+            span_id: Some("24cd7d670fa455f0".to_string()),
+            r#type: TracingType::AmznTraceId,
+            value: "Root=1-6352a70e-1e2c502e358361800241fd45;Parent=35465b3a9e2f7c6a;Sampled=1"
+                .to_string(),
+        };
+        let context = ParentContext::from_lambda_tracing(&tracing).unwrap();
+        assert_eq!(
+            context.span_id,
+            SpanId::from_hex("35465b3a9e2f7c6a").unwrap()
+        );
+
+        // Lambda's span ID is used when the header has no parent
+        let tracing = LambdaTraceContext {
+            value: "Root=1-6352a70e-1e2c502e358361800241fd45;Sampled=1".to_string(),
+            ..tracing
+        };
+        let context = ParentContext::from_lambda_tracing(&tracing).unwrap();
+        assert_eq!(
+            context.span_id,
+            SpanId::from_hex("24cd7d670fa455f0").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trace_context_precedence_from_str() {
+        assert_eq!(
+            "pipe".parse::<TraceContextPrecedence>(),
+            Ok(TraceContextPrecedence::Pipe)
+        );
+        assert_eq!(
+            "X-Ray".parse::<TraceContextPrecedence>(),
+            Ok(TraceContextPrecedence::XRay)
+        );
+        assert!("both".parse::<TraceContextPrecedence>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use lambda_extension::tracing;
+use opentelemetry::trace::{SpanId, TraceId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Trace context of each invocation, keyed by request ID, used to parent the synthesized
+/// platform spans under the function's own trace.
+///
+/// Holds at most `max_entries` mappings: inserting into a full map evicts the oldest one,
+/// so a burst of invocations whose telemetry never arrives can't grow it without bound.
+/// The context defaults to a trace and span ID; the log map also keeps the trace flags.
+#[derive(Debug)]
+pub struct TraceContextMap<C = (TraceId, SpanId)> {
+    entries: HashMap<String, (C, Instant)>,
+    max_entries: usize,
+    evicted: u64,
+}
+
+impl<C: Copy> TraceContextMap<C> {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries: max_entries.max(1),
+            evicted: 0,
+        }
+    }
+
+    pub fn insert(&mut self, request_id: String, context: C) {
+        self.insert_at(request_id, context, Instant::now());
+    }
+
+    fn insert_at(&mut self, request_id: String, context: C, at: Instant) {
+        if !self.entries.contains_key(&request_id) && self.entries.len() >= self.max_entries {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, inserted))| *inserted)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                tracing::debug!(request_id = %oldest, "Trace context map full, evicting oldest entry");
+                self.entries.remove(&oldest);
+                self.evicted += 1;
+            }
+        }
+        self.entries.insert(request_id, (context, at));
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<C> {
+        self.entries.get(request_id).map(|(context, _)| *context)
+    }
+
+    pub fn remove(&mut self, request_id: &str) {
+        self.entries.remove(request_id);
+    }
+
+    /// Removes the entries older than `ttl` and those of `request_ids`, returning how
+    /// many were removed.
+    pub fn prune(&mut self, ttl: Duration, request_ids: &[String]) -> usize {
+        let cutoff = Instant::now().checked_sub(ttl);
+        let initial_size = self.entries.len();
+        self.entries.retain(|request_id, (_, inserted)| {
+            !request_ids.contains(request_id) && cutoff.is_none_or(|cutoff| *inserted >= cutoff)
+        });
+        initial_size - self.entries.len()
+    }
+
+    /// Returns the number of entries evicted to stay within the limit since the last call.
+    pub fn take_evicted(&mut self) -> u64 {
+        std::mem::take(&mut self.evicted)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: u8) -> (TraceId, SpanId) {
+        (TraceId::from_bytes([n; 16]), SpanId::from_bytes([n; 8]))
+    }
+
+    #[test]
+    fn test_insert_evicts_oldest_when_full() {
+        let mut map = TraceContextMap::new(2);
+        let start = Instant::now();
+        for (n, request_id) in ["req-1", "req-2", "req-3"].into_iter().enumerate() {
+            map.insert_at(
+                request_id.to_string(),
+                ids(n as u8 + 1),
+                start + Duration::from_millis(n as u64),
+            );
+        }
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("req-1"), None);
+        assert_eq!(map.get("req-3"), Some(ids(3)));
+        assert_eq!(map.take_evicted(), 1);
+        assert_eq!(map.take_evicted(), 0);
+    }
+
+    #[test]
+    fn test_reinserting_existing_key_does_not_evict() {
+        let mut map = TraceContextMap::new(2);
+        map.insert("req-1".to_string(), ids(1));
+        map.insert("req-2".to_string(), ids(1));
+        map.insert("req-2".to_string(), ids(1));
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.take_evicted(), 0);
+    }
+
+    #[test]
+    fn test_prune_removes_expired_and_listed_entries() {
+        let mut map = TraceContextMap::new(10);
+        let Some(old) = Instant::now().checked_sub(Duration::from_secs(600)) else {
+            // Monotonic clock too close to its origin to backdate an entry
+            return;
+        };
+        map.insert_at("req-old".to_string(), ids(1), old);
+        map.insert("req-timed-out".to_string(), ids(1));
+        map.insert("req-live".to_string(), ids(1));
+
+        let removed = map.prune(Duration::from_secs(300), &["req-timed-out".to_string()]);
+
+        assert_eq!(removed, 2);
+        assert_eq!(map.len(), 1);
+        assert!(map.get("req-live").is_some());
+    }
+}
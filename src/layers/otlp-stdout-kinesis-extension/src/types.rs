@@ -1,4 +1,5 @@
 use crate::events::ParsedPlatformEvent;
+use crate::logs::TelemetryLog;
 use std::fmt::Debug;
 
 /// Enum representing the different types of input the main processor loop can receive.
@@ -6,9 +7,6 @@ use std::fmt::Debug;
 pub(crate) enum ProcessorInput {
     /// A parsed platform event received from the Telemetry API.
     PlatformTelemetry(ParsedPlatformEvent),
-    /// Indicates that init duration data is available from the first invoke's report.
-    InitDataAvailable {
-        request_id: String,
-        init_duration_ms: f64,
-    },
+    /// A function or extension log record received from the Telemetry API.
+    Log(TelemetryLog),
 }